@@ -0,0 +1,426 @@
+use crate::db::FileMeta;
+use crate::tags;
+use serde::{Deserialize, Serialize};
+
+/// Separator between tags inside a single CSV cell. Hierarchical tags keep
+/// using `|`, so `Places|Berlin; Clients|Acme` is two tags.
+const CSV_TAG_SEPARATOR: char = ';';
+
+const CSV_COLUMNS: [&str; 17] = [
+    "path",
+    "user_rating",
+    "tags",
+    "color_label",
+    "flag",
+    "title",
+    "caption",
+    "notes",
+    "camera_rating",
+    "taken_at",
+    "camera_make",
+    "camera_model",
+    "gps_lat",
+    "gps_lon",
+    "orientation",
+    "file_size",
+    "last_modified",
+];
+
+/// Fields an import may change. Everything else is read from the file.
+const CATALOG_FIELDS: [&str; 7] = ["user_rating", "tags", "color_label", "flag", "title", "caption", "notes"];
+
+/// A parsed CSV row and the line it starts on.
+type CsvRow = (usize, Vec<String>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatalogFormat {
+    JsonLines,
+    Csv,
+}
+
+impl CatalogFormat {
+    pub fn parse(raw: Option<&str>) -> Option<Self> {
+        match raw.map(|value| value.trim().to_lowercase()).as_deref() {
+            None | Some("") | Some("jsonl") | Some("ndjson") | Some("json") => Some(Self::JsonLines),
+            Some("csv") => Some(Self::Csv),
+            Some(_) => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::JsonLines => "application/x-ndjson",
+            Self::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::JsonLines => "jsonl",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+/// How an imported record is combined with a row that already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// Leave existing rows alone; only files without a row are imported.
+    Skip,
+    /// Fields set in the import win, tags are combined.
+    #[default]
+    Merge,
+    /// The imported values replace the catalog fields, including clearing them.
+    Overwrite,
+}
+
+/// One imported row, plus which fields it actually carried (CSV columns or
+/// JSON keys), so a partial export never clears fields it did not mention.
+#[derive(Debug, Clone)]
+pub struct CatalogRecord {
+    pub meta: FileMeta,
+    fields: Vec<&'static str>,
+}
+
+impl CatalogRecord {
+    fn new<'a>(meta: FileMeta, mut present: impl FnMut(&'a str) -> bool) -> Self {
+        let fields = CATALOG_FIELDS
+            .iter()
+            .copied()
+            .filter(|field| present(field))
+            .collect();
+        Self { meta, fields }
+    }
+
+    fn has(&self, field: &str) -> bool {
+        self.fields.contains(&field)
+    }
+}
+
+pub fn header(format: CatalogFormat) -> Option<String> {
+    match format {
+        CatalogFormat::JsonLines => None,
+        CatalogFormat::Csv => Some(format!("{}\n", CSV_COLUMNS.join(","))),
+    }
+}
+
+/// Encodes one row as a complete line, including the trailing newline.
+pub fn encode(format: CatalogFormat, meta: &FileMeta) -> String {
+    match format {
+        CatalogFormat::JsonLines => {
+            let mut line = serde_json::to_string(meta).unwrap_or_default();
+            line.push('\n');
+            line
+        }
+        CatalogFormat::Csv => {
+            let tags = join_csv_tags(&meta.tags);
+            let cells = [
+                meta.path.clone(),
+                opt_cell(meta.user_rating),
+                tags,
+                meta.color_label.clone().unwrap_or_default(),
+                meta.flag.clone().unwrap_or_default(),
+                meta.title.clone().unwrap_or_default(),
+                meta.caption.clone().unwrap_or_default(),
+                meta.notes.clone().unwrap_or_default(),
+                opt_cell(meta.camera_rating),
+                meta.taken_at.clone().unwrap_or_default(),
+                meta.camera_make.clone().unwrap_or_default(),
+                meta.camera_model.clone().unwrap_or_default(),
+                opt_cell(meta.gps_lat),
+                opt_cell(meta.gps_lon),
+                opt_cell(meta.orientation),
+                meta.file_size.to_string(),
+                meta.last_modified.to_string(),
+            ];
+            let mut line = cells
+                .iter()
+                .map(|cell| csv_escape(cell))
+                .collect::<Vec<_>>()
+                .join(",");
+            line.push('\n');
+            line
+        }
+    }
+}
+
+/// Parses an export back into records, keyed by the (1-based) line each one
+/// starts on. Records that cannot be read are returned as errors so the
+/// caller can report them next to the ones that were imported.
+pub fn decode(format: CatalogFormat, text: &str) -> Vec<(usize, Result<CatalogRecord, String>)> {
+    match format {
+        CatalogFormat::JsonLines => text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| (index + 1, json_record(line.trim())))
+            .collect(),
+        CatalogFormat::Csv => decode_csv(text),
+    }
+}
+
+fn json_record(line: &str) -> Result<CatalogRecord, String> {
+    let value: serde_json::Value = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    let object = value
+        .as_object()
+        .ok_or_else(|| "Expected a JSON object".to_string())?;
+    let meta = FileMeta::deserialize(&value).map_err(|err| err.to_string())?;
+    Ok(CatalogRecord::new(meta, |field| object.contains_key(field)))
+}
+
+fn decode_csv(text: &str) -> Vec<(usize, Result<CatalogRecord, String>)> {
+    let rows = match split_csv(text) {
+        Ok(rows) => rows,
+        Err((line, err)) => return vec![(line, Err(err))],
+    };
+    let mut rows = rows.into_iter();
+    let Some((_, header)) = rows.next() else {
+        return Vec::new();
+    };
+    let columns: Vec<String> = header.iter().map(|name| name.trim().to_lowercase()).collect();
+    if !columns.iter().any(|name| name == "path") {
+        return vec![(1, Err("CSV header must include a path column".to_string()))];
+    }
+
+    rows.filter(|(_, cells)| cells.iter().any(|cell| !cell.trim().is_empty()))
+        .map(|(line, cells)| {
+            let record = csv_record(&columns, &cells)
+                .map(|meta| CatalogRecord::new(meta, |field| columns.iter().any(|name| name == field)));
+            (line, record)
+        })
+        .collect()
+}
+
+fn csv_record(columns: &[String], cells: &[String]) -> Result<FileMeta, String> {
+    let mut meta = FileMeta::default();
+    for (name, cell) in columns.iter().zip(cells) {
+        let value = cell.trim();
+        let text = || Some(value.to_string()).filter(|value| !value.is_empty());
+        match name.as_str() {
+            "path" => meta.path = value.to_string(),
+            "user_rating" => meta.user_rating = parse_cell(name, value)?,
+            "camera_rating" => meta.camera_rating = parse_cell(name, value)?,
+            "tags" => {
+                meta.tags = tags::normalize_tags(split_csv_tags(value));
+            }
+            "color_label" => meta.color_label = text(),
+            "flag" => meta.flag = text(),
+            "title" => meta.title = text(),
+            "caption" => meta.caption = text(),
+            "notes" => meta.notes = text(),
+            "taken_at" => meta.taken_at = text(),
+            "camera_make" => meta.camera_make = text(),
+            "camera_model" => meta.camera_model = text(),
+            "gps_lat" => meta.gps_lat = parse_cell(name, value)?,
+            "gps_lon" => meta.gps_lon = parse_cell(name, value)?,
+            "orientation" => meta.orientation = parse_cell(name, value)?,
+            "file_size" => meta.file_size = parse_cell(name, value)?.unwrap_or(0),
+            "last_modified" => meta.last_modified = parse_cell(name, value)?.unwrap_or(0),
+            _ => {}
+        }
+    }
+    Ok(meta)
+}
+
+fn parse_cell<T: std::str::FromStr>(column: &str, value: &str) -> Result<Option<T>, String> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("Invalid {column}: {value}"))
+}
+
+fn opt_cell<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// The tags of a CSV row as one cell: `Places|Paris; People`. A `;` or `\`
+/// inside a tag is escaped with a backslash so it survives the round trip.
+fn join_csv_tags(tags: &[String]) -> String {
+    tags.iter()
+        .map(|tag| {
+            tag.replace('\\', "\\\\")
+                .replace(CSV_TAG_SEPARATOR, &format!("\\{CSV_TAG_SEPARATOR}"))
+        })
+        .collect::<Vec<_>>()
+        .join(&format!("{CSV_TAG_SEPARATOR} "))
+}
+
+/// Splits a tags cell on the separators `join_csv_tags` left unescaped.
+fn split_csv_tags(cell: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    let mut tag = String::new();
+    let mut chars = cell.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => tag.extend(chars.next()),
+            CSV_TAG_SEPARATOR => tags.push(std::mem::take(&mut tag)),
+            _ => tag.push(c),
+        }
+    }
+    tags.push(tag);
+    tags
+}
+
+fn csv_escape(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+/// Splits RFC 4180 text into rows of cells. Quoted cells may contain commas,
+/// doubled quotes and line breaks; each row carries the line it starts on.
+fn split_csv(text: &str) -> Result<Vec<CsvRow>, (usize, String)> {
+    let mut rows = Vec::new();
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut row_start = 1;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    cell.push('"');
+                }
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    cell.push(c);
+                }
+                _ => cell.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if cell.is_empty() => in_quotes = true,
+            ',' => cells.push(std::mem::take(&mut cell)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                cells.push(std::mem::take(&mut cell));
+                rows.push((row_start, std::mem::take(&mut cells)));
+                line += 1;
+                row_start = line;
+            }
+            _ => cell.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err((row_start, "Unterminated quoted CSV field".to_string()));
+    }
+    if !cell.is_empty() || !cells.is_empty() {
+        cells.push(cell);
+        rows.push((row_start, cells));
+    }
+    Ok(rows)
+}
+
+/// Rewrites `path` when it sits at or below `from`, e.g. remapping
+/// `Old/Shoots` to `Shoots` turns `Old/Shoots/2024/a.arw` into
+/// `Shoots/2024/a.arw`. Other paths are returned unchanged.
+pub fn remap_path(path: &str, from: &str, to: &str) -> String {
+    let path = path.trim_matches('/');
+    let from = from.trim_matches('/');
+    let to = to.trim_matches('/');
+    let rest = if from.is_empty() {
+        Some(path)
+    } else if path == from {
+        Some("")
+    } else {
+        path.strip_prefix(from).and_then(|rest| rest.strip_prefix('/'))
+    };
+    match rest {
+        Some("") => to.to_string(),
+        Some(rest) if to.is_empty() => rest.to_string(),
+        Some(rest) => format!("{to}/{rest}"),
+        None => path.to_string(),
+    }
+}
+
+/// Applies the catalog fields `record` carries (rating, tags, label, flag
+/// and text) to `meta`. Camera data is left alone; it comes from the file.
+/// `Merge` only takes values that are set and adds tags to the existing
+/// ones; `Overwrite` (and `Skip`, which only ever sees new rows) copies the
+/// fields as they are, clearing them when the record leaves them empty.
+pub fn apply_record(meta: &mut FileMeta, record: &CatalogRecord, strategy: MergeStrategy) {
+    let incoming = &record.meta;
+    let merge = strategy == MergeStrategy::Merge;
+
+    if record.has("user_rating") && (!merge || incoming.user_rating.is_some()) {
+        meta.user_rating = incoming.user_rating;
+    }
+    if record.has("tags") {
+        meta.tags = if merge {
+            tags::normalize_tags(meta.tags.iter().chain(&incoming.tags))
+        } else {
+            incoming.tags.clone()
+        };
+    }
+    for (field, target, value) in [
+        ("color_label", &mut meta.color_label, &incoming.color_label),
+        ("flag", &mut meta.flag, &incoming.flag),
+        ("title", &mut meta.title, &incoming.title),
+        ("caption", &mut meta.caption, &incoming.caption),
+        ("notes", &mut meta.notes, &incoming.notes),
+    ] {
+        if record.has(field) && (!merge || value.is_some()) {
+            *target = value.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_tags_with_separators_survive_a_round_trip() {
+        let tags = vec![
+            "Places|Paris".to_string(),
+            "R&D; 2024".to_string(),
+            "C:\\Photos".to_string(),
+        ];
+        let cell = join_csv_tags(&tags);
+        assert_eq!(cell, "Places|Paris; R&D\\; 2024; C:\\\\Photos");
+        assert_eq!(tags::normalize_tags(split_csv_tags(&cell)), tags);
+    }
+
+    #[test]
+    fn plain_csv_tags_split_on_semicolons() {
+        assert_eq!(
+            tags::normalize_tags(split_csv_tags("a;b ; c|d")),
+            vec!["a", "b", "c|d"]
+        );
+    }
+
+    #[test]
+    fn csv_export_reads_back_tags_and_text() {
+        let meta = FileMeta {
+            path: "day1/a.ARW".to_string(),
+            user_rating: Some(4),
+            tags: vec!["x;y".to_string(), "Places|Paris".to_string()],
+            caption: Some("line one\nline \"two\", end".to_string()),
+            ..Default::default()
+        };
+        let text = format!(
+            "{}{}",
+            header(CatalogFormat::Csv).unwrap(),
+            encode(CatalogFormat::Csv, &meta)
+        );
+        let records = decode(CatalogFormat::Csv, &text);
+        assert_eq!(records.len(), 1);
+        let record = records[0].1.as_ref().unwrap();
+        assert_eq!(record.meta.tags, meta.tags);
+        assert_eq!(record.meta.caption, meta.caption);
+        assert_eq!(record.meta.user_rating, Some(4));
+    }
+}
@@ -0,0 +1,198 @@
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// What a file is to the shot it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompanionKind {
+    Raw,
+    Jpeg,
+    Heif,
+    Sidecar,
+    Other,
+}
+
+impl CompanionKind {
+    /// Kind of a non-RAW file, judged by its extension. RAW detection lives
+    /// with the supported formats, so callers check that first.
+    pub fn of(name: &str) -> Self {
+        let ext = Path::new(name)
+            .extension()
+            .and_then(OsStr::to_str)
+            .map(|ext| ext.to_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "jpg" | "jpeg" => Self::Jpeg,
+            "heic" | "heif" | "hif" => Self::Heif,
+            "xmp" => Self::Sidecar,
+            _ => Self::Other,
+        }
+    }
+
+    /// Whether a file of this kind and one of `other` form a RAW+JPEG or
+    /// RAW+HEIF pair, which share ratings and tags.
+    pub fn pairs_with(self, other: Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::Raw, Self::Jpeg | Self::Heif) | (Self::Jpeg | Self::Heif, Self::Raw)
+        )
+    }
+
+    /// Whether a companion of kind `other` goes along when a file of this
+    /// kind is moved, deleted or renamed. Only a RAW takes its JPEG, HEIF and
+    /// sidecars with it: a JPEG deleted while culling must not take the RAW,
+    /// and two RAWs that happen to share a name are separate shots.
+    pub fn takes_along(self, other: Self) -> bool {
+        self == Self::Raw && matches!(other, Self::Jpeg | Self::Heif | Self::Sidecar)
+    }
+}
+
+/// The part of a file name that all files of one shot share: `DSC0001` for
+/// `DSC0001.ARW`, `DSC0001.JPG`, `DSC0001.xmp` and `DSC0001.ARW.xmp`.
+/// Always a prefix of `name`; compare keys without case.
+pub fn group_key(name: &str) -> &str {
+    let base = sidecar_target(name).unwrap_or(name);
+    match base.rfind('.') {
+        Some(0) | None => base,
+        Some(dot) => &base[..dot],
+    }
+}
+
+pub fn same_group(a: &str, b: &str) -> bool {
+    group_key(a).to_lowercase() == group_key(b).to_lowercase()
+}
+
+/// Whether `companion`, found in the same shot as `owner`, follows `owner`
+/// when it is moved, deleted or renamed. A `DSC0001.NEF.xmp` sidecar belongs
+/// to `DSC0001.NEF` alone and stays behind when `DSC0001.ARW` goes; the JPEG
+/// and plain `DSC0001.xmp` go with any RAW of the shot.
+pub fn follows(owner: &str, companion: &str) -> bool {
+    match sidecar_target(companion) {
+        Some(target) => target.eq_ignore_ascii_case(owner),
+        None => true,
+    }
+}
+
+/// The file a `<name>.<ext>.xmp` sidecar was written for, e.g. `DSC0001.NEF`
+/// for `DSC0001.NEF.xmp`. `None` for any other name.
+fn sidecar_target(name: &str) -> Option<&str> {
+    let split = name.len().checked_sub(4)?;
+    if !name.is_char_boundary(split) || !name[split..].eq_ignore_ascii_case(".xmp") {
+        return None;
+    }
+    let base = &name[..split];
+    Path::new(base).extension().is_some().then_some(base)
+}
+
+/// Names of the other files in `dir` that belong to the same shot as `name`,
+/// such as its sidecars and the JPEG shot alongside a RAW.
+pub fn find(dir: &Path, name: &str) -> io::Result<Vec<String>> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let other = entry.file_name().to_string_lossy().to_string();
+        if other != name && same_group(&other, name) {
+            found.push(other);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// The name a companion gets when its shot is renamed to `new_key`, e.g.
+/// `DSC0001.ARW.xmp` becomes `Wedding_01.ARW.xmp`.
+pub fn renamed(companion: &str, new_key: &str) -> String {
+    let key_len = group_key(companion).len();
+    format!("{new_key}{}", &companion[key_len..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_key_strips_extension_and_sidecar() {
+        assert_eq!(group_key("DSC0001.ARW"), "DSC0001");
+        assert_eq!(group_key("DSC0001.JPG"), "DSC0001");
+        assert_eq!(group_key("DSC0001.xmp"), "DSC0001");
+        assert_eq!(group_key("DSC0001.ARW.xmp"), "DSC0001");
+        assert_eq!(group_key("DSC0001.ARW.XMP"), "DSC0001");
+        assert_eq!(group_key("2024.05.01 wedding.NEF"), "2024.05.01 wedding");
+        assert_eq!(group_key("2024.05.01 wedding.NEF.xmp"), "2024.05.01 wedding");
+        assert_eq!(group_key("README"), "README");
+        assert_eq!(group_key("été.xmp"), "été");
+    }
+
+    #[test]
+    fn group_key_keeps_hidden_names() {
+        assert_eq!(group_key(".hidden"), ".hidden");
+        assert_eq!(group_key(".hidden.xmp"), ".hidden");
+        assert_eq!(group_key(".xmp"), ".xmp");
+    }
+
+    #[test]
+    fn same_group_ignores_case() {
+        assert!(same_group("dsc0001.arw", "DSC0001.JPG"));
+        assert!(same_group("DSC0001.ARW.xmp", "DSC0001.NEF"));
+        assert!(!same_group("DSC0001.ARW", "DSC00011.ARW"));
+    }
+
+    #[test]
+    fn renamed_keeps_everything_after_the_key() {
+        assert_eq!(renamed("DSC0001.ARW.xmp", "Wedding_01"), "Wedding_01.ARW.xmp");
+        assert_eq!(renamed("DSC0001.JPG", "Wedding_01"), "Wedding_01.JPG");
+        assert_eq!(renamed("a.b.c.xmp", "x"), "x.c.xmp");
+        assert_eq!(renamed(".hidden", "x"), "x");
+    }
+
+    #[test]
+    fn named_sidecars_follow_only_their_own_raw() {
+        assert!(follows("DSC0001.NEF", "DSC0001.NEF.xmp"));
+        assert!(follows("DSC0001.nef", "DSC0001.NEF.XMP"));
+        assert!(!follows("DSC0001.ARW", "DSC0001.NEF.xmp"));
+        assert!(follows("DSC0001.ARW", "DSC0001.xmp"));
+        assert!(follows("DSC0001.ARW", "DSC0001.JPG"));
+        assert!(follows("2024.05.01 wedding.NEF", "2024.05.01 wedding.NEF.xmp"));
+        assert!(!follows("2024.05.01 wedding.ARW", "2024.05.01 wedding.NEF.xmp"));
+    }
+
+    #[test]
+    fn kinds_by_extension() {
+        assert_eq!(CompanionKind::of("a.jpeg"), CompanionKind::Jpeg);
+        assert_eq!(CompanionKind::of("a.JPG"), CompanionKind::Jpeg);
+        assert_eq!(CompanionKind::of("a.HIF"), CompanionKind::Heif);
+        assert_eq!(CompanionKind::of("a.ARW.xmp"), CompanionKind::Sidecar);
+        assert_eq!(CompanionKind::of("a.wav"), CompanionKind::Other);
+        assert_eq!(CompanionKind::of("noext"), CompanionKind::Other);
+    }
+
+    #[test]
+    fn only_a_raw_takes_companions_along() {
+        use CompanionKind::*;
+        assert!(Raw.takes_along(Jpeg));
+        assert!(Raw.takes_along(Heif));
+        assert!(Raw.takes_along(Sidecar));
+        assert!(!Raw.takes_along(Raw));
+        assert!(!Raw.takes_along(Other));
+        for kind in [Jpeg, Heif, Sidecar, Other] {
+            assert!(!kind.takes_along(Raw));
+            assert!(!kind.takes_along(Sidecar));
+        }
+    }
+
+    #[test]
+    fn pairs_are_raw_with_jpeg_or_heif() {
+        use CompanionKind::*;
+        assert!(Raw.pairs_with(Jpeg));
+        assert!(Heif.pairs_with(Raw));
+        assert!(!Raw.pairs_with(Raw));
+        assert!(!Raw.pairs_with(Sidecar));
+        assert!(!Jpeg.pairs_with(Heif));
+    }
+}
@@ -1,10 +1,18 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+use sqlx::{sqlite::SqliteRow, Executor, QueryBuilder, Row, Sqlite, SqliteConnection, SqlitePool, Transaction};
 use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::catalog::{self, CatalogRecord, MergeStrategy};
+use crate::history;
+use crate::migrations;
+use crate::tags::{self, TagUsage};
+use crate::trash;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct FileMeta {
     pub path: String,
     pub camera_rating: Option<i32>,
@@ -14,40 +22,325 @@ pub struct FileMeta {
     pub gps_lon: Option<f64>,
     pub taken_at: Option<String>,
     pub orientation: Option<i32>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub color_label: Option<String>,
+    pub flag: Option<String>,
+    pub title: Option<String>,
+    pub caption: Option<String>,
+    pub notes: Option<String>,
+    pub file_size: i64,
+    pub last_modified: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchTarget {
+    pub path: String,
     pub file_size: i64,
     pub last_modified: i64,
 }
 
-pub async fn init_db(pool: &SqlitePool) -> Result<()> {
-    create_files_table(pool).await?;
-    ensure_files_schema(pool).await?;
+#[derive(Debug, Clone, Serialize)]
+pub struct Album {
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    pub item_count: i64,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// Criteria for finding files. Every set field must match; `tags` must all
+/// match, each one including its descendants in the keyword tree.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchFilter {
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub min_rating: Option<i32>,
+    pub taken_after: Option<String>,
+    pub taken_before: Option<String>,
+    pub taken_within_days: Option<i64>,
+    pub camera: Option<String>,
+    pub folder: Option<String>,
+    pub color_label: Option<String>,
+    pub flag: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedSearch {
+    pub id: i64,
+    pub name: String,
+    pub filter: SearchFilter,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// How a hot folder's files are filed, like the options of a manual ingest.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HotFolderSettings {
+    /// Folder template; the server default when absent.
+    pub template: Option<String>,
+    pub shoot: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub copyright: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HotFolder {
+    pub id: i64,
+    pub path: String,
+    pub destination: String,
+    pub settings: HotFolderSettings,
+    pub enabled: bool,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// A file a hot folder moved into the library.
+#[derive(Debug, Clone, Serialize)]
+pub struct HotFolderArrival {
+    pub path: String,
+    pub source: String,
+    pub size: i64,
+    pub job_id: i64,
+    pub arrived_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TaggedFile {
+    pub tags: Vec<String>,
+    pub tags_updated_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportAction {
+    Created,
+    Updated,
+    Unchanged,
+    Skipped,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportChange {
+    pub action: ImportAction,
+    pub meta: FileMeta,
+    pub tags_changed: bool,
+    pub title_changed: bool,
+    pub caption_changed: bool,
+}
+
+/// Describes the request behind a set of edits, so they are recorded in the
+/// history as one operation.
+#[derive(Debug, Clone, Copy)]
+pub struct Edit<'a> {
+    pub kind: &'a str,
+    pub actor: &'a str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryOperation {
+    pub id: i64,
+    pub kind: String,
+    pub actor: String,
+    pub created_at: i64,
+    pub undone_at: Option<i64>,
+    pub file_count: i64,
+    pub change_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub operation_id: i64,
+    pub kind: String,
+    pub actor: String,
+    pub created_at: i64,
+    pub undone_at: Option<i64>,
+    pub path: String,
+    pub field: String,
+    pub old_value: serde_json::Value,
+    pub new_value: serde_json::Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct RestoredFile {
+    pub meta: FileMeta,
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct UndoOutcome {
+    pub operations: Vec<i64>,
+    /// Operations none of whose changes could be restored, because every
+    /// field was changed again since. They are marked undone all the same so
+    /// the next undo moves past them; their entries are in `conflicts`.
+    pub skipped: Vec<i64>,
+    pub restored: Vec<RestoredFile>,
+    pub conflicts: Vec<HistoryEntry>,
+}
+
+/// One rename on disk: a move to its destination, or a delete staged next
+/// to the original until the catalog update is committed.
+#[derive(Debug, Clone)]
+pub struct JournalStep {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub is_dir: bool,
+}
+
+/// How a journaled rename affects the catalog.
+#[derive(Debug, Clone)]
+pub enum PathChange {
+    Move { from: String, to: String, is_dir: bool },
+    Delete { path: String, is_dir: bool },
+    /// Moved into the trash; rows are kept and marked deleted.
+    Trash { from: String, to: String, is_dir: bool, size: i64 },
+    /// Moved back out of the trash to where it was deleted from.
+    Restore { id: i64, from: String, to: String, is_dir: bool },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrashItem {
+    pub id: i64,
+    pub original_path: String,
+    pub trash_path: String,
+    pub is_dir: bool,
+    pub size: i64,
+    pub deleted_at: i64,
+}
+
+/// A long-running filesystem operation, such as a move to another disk, that
+/// is picked up again after a restart.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: i64,
+    pub kind: String,
+    pub state: String,
+    pub error: Option<String>,
+    pub total_bytes: i64,
+    pub done_bytes: i64,
+    pub item_count: i64,
+    pub done_count: i64,
+    pub skipped_count: i64,
+    /// Items that could not be done; the job carried on with the rest.
+    pub failed_count: i64,
+    /// Settings of the job kinds that take any, such as ingest.
+    pub options: Option<serde_json::Value>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// One library-relative path a job works on.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobItem {
+    pub id: i64,
+    pub source: String,
+    pub target: String,
+    pub is_dir: bool,
+    pub size: i64,
+    pub state: String,
+}
+
+impl JobItem {
+    pub fn new(source: String, target: String, is_dir: bool, size: i64) -> Self {
+        Self {
+            id: 0,
+            source,
+            target,
+            is_dir,
+            size,
+            state: "pending".to_string(),
+        }
+    }
+}
+
+/// A resumable upload: the file is written to the staging folder piece by
+/// piece and moved to `directory/name` once `received` reaches `size`.
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadSession {
+    pub id: String,
+    pub directory: String,
+    pub name: String,
+    pub size: i64,
+    pub received: i64,
+    pub sha256: Option<String>,
+    /// Library path of the finished file. A finished session is kept until
+    /// it expires, so a client that missed the last response can ask.
+    pub path: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub batch: i64,
+    pub op: String,
+    pub committed: bool,
+    pub step: JournalStep,
+}
+
+#[derive(Debug, Clone)]
+pub struct TagChange {
+    pub path: String,
+    pub tags: Vec<String>,
+}
+
+/// A title or caption edit to write back; `None` means the field did not
+/// change and is left alone in the sidecar.
+#[derive(Debug, Clone)]
+pub struct TextChange {
+    pub path: String,
+    pub title: Option<Option<String>>,
+    pub caption: Option<Option<String>>,
+}
+
+impl TextChange {
+    /// The change for `meta`, carrying only the fields that changed.
+    pub fn of(meta: &FileMeta, title_changed: bool, caption_changed: bool) -> Self {
+        Self {
+            path: meta.path.clone(),
+            title: title_changed.then(|| meta.title.clone()),
+            caption: caption_changed.then(|| meta.caption.clone()),
+        }
+    }
+}
+
+pub async fn init_db(pool: &SqlitePool, db_path: &Path) -> Result<()> {
+    migrations::run(pool, db_path).await?;
 
     Ok(())
 }
 
-pub async fn get_file_meta(pool: &SqlitePool, path: &str) -> Result<Option<FileMeta>> {
-    let row = sqlx::query(
-        r#"
-        SELECT path, camera_rating, user_rating, tags, gps_lat, gps_lon, taken_at, file_size, last_modified, orientation
-        FROM files
-        WHERE path = ?
-        "#,
-    )
-    .bind(path)
-    .fetch_optional(pool)
-    .await?;
+const FILE_COLUMNS: &str = "path, camera_rating, user_rating, tags, gps_lat, gps_lon, taken_at, \
+    file_size, last_modified, orientation, camera_make, camera_model, color_label, flag, title, caption, notes";
+
+pub async fn get_file_meta<'e, E>(executor: E, path: &str) -> Result<Option<FileMeta>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let sql = format!("SELECT {FILE_COLUMNS} FROM files WHERE path = ?");
+    let row = sqlx::query(&sql)
+        .bind(path)
+        .fetch_optional(executor)
+        .await?;
 
     Ok(row.map(row_to_meta))
 }
 
-pub async fn upsert_file_meta(pool: &SqlitePool, meta: &FileMeta) -> Result<()> {
+pub async fn upsert_file_meta<'e, E>(executor: E, meta: &FileMeta) -> Result<()>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
     let tags_json = serde_json::to_string(&meta.tags)?;
     sqlx::query(
         r#"
         INSERT INTO files (
-            path, camera_rating, user_rating, tags, gps_lat, gps_lon, taken_at, file_size, last_modified, orientation
+            path, camera_rating, user_rating, tags, gps_lat, gps_lon, taken_at, file_size, last_modified, orientation,
+            camera_make, camera_model, color_label, flag, title, caption, notes
         )
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
         ON CONFLICT(path) DO UPDATE SET
             camera_rating = excluded.camera_rating,
             user_rating = excluded.user_rating,
@@ -57,8 +350,14 @@ pub async fn upsert_file_meta(pool: &SqlitePool, meta: &FileMeta) -> Result<()>
             taken_at = excluded.taken_at,
             file_size = excluded.file_size,
             last_modified = excluded.last_modified,
-            orientation = excluded.orientation;
-
+            orientation = excluded.orientation,
+            camera_make = excluded.camera_make,
+            camera_model = excluded.camera_model,
+            color_label = excluded.color_label,
+            flag = excluded.flag,
+            title = excluded.title,
+            caption = excluded.caption,
+            notes = excluded.notes;
         "#,
     )
     .bind(&meta.path)
@@ -71,133 +370,817 @@ pub async fn upsert_file_meta(pool: &SqlitePool, meta: &FileMeta) -> Result<()>
     .bind(meta.file_size)
     .bind(meta.last_modified)
     .bind(meta.orientation)
-    .execute(pool)
+    .bind(&meta.camera_make)
+    .bind(&meta.camera_model)
+    .bind(&meta.color_label)
+    .bind(&meta.flag)
+    .bind(&meta.title)
+    .bind(&meta.caption)
+    .bind(&meta.notes)
+    .execute(executor)
     .await?;
 
     Ok(())
 }
 
-pub async fn upsert_user_rating(
-    pool: &SqlitePool,
-    path: &str,
-    rating: Option<i32>,
-    file_size: i64,
-    last_modified: i64,
-) -> Result<()> {
-    sqlx::query(
+/// Usage of every tag, counted by SQLite so that autocomplete does not load
+/// every tagged row on each keystroke.
+pub async fn list_tags(pool: &SqlitePool) -> Result<Vec<TagUsage>> {
+    let rows = sqlx::query(
         r#"
-        INSERT INTO files (path, user_rating, tags, file_size, last_modified)
-        VALUES (?, ?, ?, ?, ?)
-        ON CONFLICT(path) DO UPDATE SET
-            user_rating = excluded.user_rating,
-            file_size = excluded.file_size,
-            last_modified = excluded.last_modified;
+        SELECT MIN(tag.value) AS tag, COUNT(*) AS count, MAX(COALESCE(files.tags_updated_at, 0)) AS last_used
+        FROM files, json_each(files.tags) AS tag
+        WHERE files.deleted_at IS NULL AND json_valid(files.tags) AND tag.type = 'text'
+        GROUP BY lower(tag.value)
         "#,
     )
-    .bind(path)
-    .bind(rating)
-    .bind("[]")
-    .bind(file_size)
-    .bind(last_modified)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tags::merge_usage(rows.into_iter().map(|row| TagUsage {
+        tag: row.get("tag"),
+        count: row.get("count"),
+        last_used: row.get("last_used"),
+    })))
+}
+
+pub async fn list_tagged_files(pool: &SqlitePool) -> Result<Vec<TaggedFile>> {
+    list_tagged_files_matching(pool, &[]).await
+}
+
+/// Tagged files that carry one of `anchors` (compared without case), or all
+/// tagged files when `anchors` is empty.
+pub async fn list_tagged_files_matching(pool: &SqlitePool, anchors: &[String]) -> Result<Vec<TaggedFile>> {
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT tags, tags_updated_at FROM files WHERE deleted_at IS NULL AND tags IS NOT NULL AND tags != '' AND tags != '[]'",
+    );
+    if !anchors.is_empty() {
+        query.push(" AND json_valid(tags) AND EXISTS (SELECT 1 FROM json_each(files.tags) WHERE lower(value) IN (");
+        let mut list = query.separated(", ");
+        for anchor in anchors {
+            list.push("lower(").push_bind_unseparated(anchor.clone()).push_unseparated(")");
+        }
+        query.push("))");
+    }
+    let rows = query.build().fetch_all(pool).await?;
+
+    let mut files = Vec::new();
+    for row in rows {
+        let raw: String = row.get("tags");
+        let Ok(list) = serde_json::from_str::<Vec<String>>(&raw) else {
+            continue;
+        };
+        let tags = tags::normalize_tags(list);
+        if tags.is_empty() {
+            continue;
+        }
+        files.push(TaggedFile {
+            tags,
+            tags_updated_at: row.get::<Option<i64>, _>("tags_updated_at").unwrap_or(0),
+        });
+    }
+    Ok(files)
+}
+
+pub async fn search_files(pool: &SqlitePool, filter: &SearchFilter) -> Result<Vec<FileMeta>> {
+    let mut query = QueryBuilder::<Sqlite>::new(format!("SELECT {FILE_COLUMNS} FROM files WHERE deleted_at IS NULL"));
+    push_search_conditions(&mut query, filter);
+    query.push(" ORDER BY taken_at DESC, path");
+
+    let rows = query.build().fetch_all(pool).await?;
+    Ok(rows
+        .into_iter()
+        .map(row_to_meta)
+        .filter(|meta| has_search_tags(&meta.tags, filter))
+        .collect())
+}
+
+/// Counts the files `search_files` would return without loading them. Tag
+/// matching is done here rather than in SQL, like in `search_files`, so only
+/// the tags column is read when the filter has tags.
+pub async fn count_search_files(pool: &SqlitePool, filter: &SearchFilter) -> Result<usize> {
+    if filter.tags.is_empty() {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) FROM files WHERE deleted_at IS NULL");
+        push_search_conditions(&mut query, filter);
+        let count: i64 = query.build().fetch_one(pool).await?.get(0);
+        return Ok(count as usize);
+    }
+
+    let mut query = QueryBuilder::<Sqlite>::new("SELECT tags FROM files WHERE deleted_at IS NULL");
+    push_search_conditions(&mut query, filter);
+    let rows = query.build().fetch_all(pool).await?;
+    Ok(rows
+        .into_iter()
+        .filter(|row| {
+            let tags: Vec<String> = row
+                .get::<Option<String>, _>("tags")
+                .and_then(|raw| serde_json::from_str(&raw).ok())
+                .unwrap_or_default();
+            has_search_tags(&tags, filter)
+        })
+        .count())
+}
+
+fn push_search_conditions(query: &mut QueryBuilder<'_, Sqlite>, filter: &SearchFilter) {
+    if let Some(min_rating) = filter.min_rating {
+        query
+            .push(" AND COALESCE(user_rating, camera_rating, 0) >= ")
+            .push_bind(min_rating);
+    }
+    if let Some(after) = &filter.taken_after {
+        query.push(" AND taken_at >= ").push_bind(after.clone());
+    }
+    if let Some(before) = &filter.taken_before {
+        // Dates compare as text, so pad a bare day to include all of it.
+        let before = if before.len() == 10 {
+            format!("{before} 99")
+        } else {
+            before.clone()
+        };
+        query.push(" AND taken_at <= ").push_bind(before);
+    }
+    if let Some(days) = filter.taken_within_days {
+        let cutoff = unix_now() - days.max(0) * 86_400;
+        query.push(" AND taken_at >= ").push_bind(unix_to_date(cutoff));
+    }
+    if let Some(camera) = &filter.camera {
+        let pattern = format!("%{}%", like_escape(camera));
+        query
+            .push(" AND (COALESCE(camera_make, '') || ' ' || COALESCE(camera_model, '')) LIKE ")
+            .push_bind(pattern)
+            .push(" ESCAPE '\\'");
+    }
+    if let Some(folder) = &filter.folder {
+        let folder = folder.trim_matches('/');
+        if !folder.is_empty() {
+            query
+                .push(" AND path LIKE ")
+                .push_bind(format!("{}/%", like_escape(folder)))
+                .push(" ESCAPE '\\'");
+        }
+    }
+    if let Some(label) = &filter.color_label {
+        query.push(" AND color_label = ").push_bind(label.clone());
+    }
+    if let Some(flag) = &filter.flag {
+        query.push(" AND flag = ").push_bind(flag.clone());
+    }
+    if !filter.tags.is_empty() {
+        query.push(" AND tags IS NOT NULL AND tags != '' AND tags != '[]'");
+    }
+}
+
+fn has_search_tags(file_tags: &[String], filter: &SearchFilter) -> bool {
+    filter.tags.iter().all(|wanted| {
+        file_tags
+            .iter()
+            .any(|candidate| tags::matches_tag(candidate, wanted))
+    })
+}
+
+pub async fn list_saved_searches(pool: &SqlitePool) -> Result<Vec<SavedSearch>> {
+    let rows = sqlx::query(
+        "SELECT id, name, filter, created_at, updated_at FROM saved_searches ORDER BY name COLLATE NOCASE",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(row_to_saved_search).collect())
+}
+
+pub async fn get_saved_search(pool: &SqlitePool, id: i64) -> Result<Option<SavedSearch>> {
+    let row = sqlx::query("SELECT id, name, filter, created_at, updated_at FROM saved_searches WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(row_to_saved_search))
+}
+
+pub async fn create_saved_search(
+    pool: &SqlitePool,
+    name: &str,
+    filter: &SearchFilter,
+) -> Result<SavedSearch> {
+    let now = unix_now();
+    let result = sqlx::query(
+        "INSERT INTO saved_searches (name, filter, created_at, updated_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(name)
+    .bind(serde_json::to_string(filter)?)
+    .bind(now)
+    .bind(now)
     .execute(pool)
     .await?;
 
-    Ok(())
+    get_saved_search(pool, result.last_insert_rowid())
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("saved search missing after insert"))
+}
+
+pub async fn update_saved_search(
+    pool: &SqlitePool,
+    id: i64,
+    name: Option<&str>,
+    filter: Option<&SearchFilter>,
+) -> Result<Option<SavedSearch>> {
+    let Some(existing) = get_saved_search(pool, id).await? else {
+        return Ok(None);
+    };
+    let name = name.unwrap_or(&existing.name);
+    let filter = filter.unwrap_or(&existing.filter);
+    sqlx::query("UPDATE saved_searches SET name = ?, filter = ?, updated_at = ? WHERE id = ?")
+        .bind(name)
+        .bind(serde_json::to_string(filter)?)
+        .bind(unix_now())
+        .bind(id)
+        .execute(pool)
+        .await?;
+    get_saved_search(pool, id).await
+}
+
+pub async fn delete_saved_search(pool: &SqlitePool, id: i64) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM saved_searches WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+const HOT_FOLDER_COLUMNS: &str = "id, path, destination, settings, enabled, created_at, updated_at";
+
+pub async fn list_hot_folders(pool: &SqlitePool) -> Result<Vec<HotFolder>> {
+    let rows = sqlx::query(&format!("SELECT {HOT_FOLDER_COLUMNS} FROM hot_folders ORDER BY path"))
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(row_to_hot_folder).collect())
+}
+
+pub async fn get_hot_folder(pool: &SqlitePool, id: i64) -> Result<Option<HotFolder>> {
+    let row = sqlx::query(&format!("SELECT {HOT_FOLDER_COLUMNS} FROM hot_folders WHERE id = ?"))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(row_to_hot_folder))
 }
 
-pub async fn upsert_tags(
+pub async fn create_hot_folder(
     pool: &SqlitePool,
     path: &str,
-    tags: &[String],
-    file_size: i64,
-    last_modified: i64,
-) -> Result<()> {
-    let tags_json = serde_json::to_string(tags)?;
-    sqlx::query(
-        r#"
-        INSERT INTO files (path, user_rating, tags, file_size, last_modified)
-        VALUES (?, ?, ?, ?, ?)
-        ON CONFLICT(path) DO UPDATE SET
-            tags = excluded.tags,
-            file_size = excluded.file_size,
-            last_modified = excluded.last_modified;
-        "#,
+    destination: &str,
+    settings: &HotFolderSettings,
+    enabled: bool,
+) -> Result<HotFolder> {
+    let now = unix_now();
+    let result = sqlx::query(
+        "INSERT INTO hot_folders (path, destination, settings, enabled, created_at, updated_at) \
+         VALUES (?, ?, ?, ?, ?, ?)",
     )
     .bind(path)
-    .bind(None::<i32>)
-    .bind(tags_json)
-    .bind(file_size)
-    .bind(last_modified)
+    .bind(destination)
+    .bind(serde_json::to_string(settings)?)
+    .bind(enabled)
+    .bind(now)
+    .bind(now)
     .execute(pool)
     .await?;
 
-    Ok(())
+    get_hot_folder(pool, result.last_insert_rowid())
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("hot folder missing after insert"))
 }
 
-pub async fn list_tags(pool: &SqlitePool) -> Result<Vec<String>> {
-    let rows = sqlx::query("SELECT tags FROM files WHERE tags IS NOT NULL AND tags != ''")
-        .fetch_all(pool)
+pub async fn update_hot_folder(
+    pool: &SqlitePool,
+    id: i64,
+    destination: Option<&str>,
+    settings: Option<&HotFolderSettings>,
+    enabled: Option<bool>,
+) -> Result<Option<HotFolder>> {
+    let Some(existing) = get_hot_folder(pool, id).await? else {
+        return Ok(None);
+    };
+    sqlx::query("UPDATE hot_folders SET destination = ?, settings = ?, enabled = ?, updated_at = ? WHERE id = ?")
+        .bind(destination.unwrap_or(&existing.destination))
+        .bind(serde_json::to_string(settings.unwrap_or(&existing.settings))?)
+        .bind(enabled.unwrap_or(existing.enabled))
+        .bind(unix_now())
+        .bind(id)
+        .execute(pool)
+        .await?;
+    get_hot_folder(pool, id).await
+}
+
+pub async fn delete_hot_folder(pool: &SqlitePool, id: i64) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM hot_folders WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Files the hot folder has moved into the library, newest first,
+/// optionally only those that arrived after `since`.
+pub async fn list_hot_folder_arrivals(
+    pool: &SqlitePool,
+    id: i64,
+    since: Option<i64>,
+    limit: i64,
+) -> Result<Vec<HotFolderArrival>> {
+    let rows = sqlx::query(
+        "SELECT job_items.target, job_items.source, job_items.size, jobs.id AS job_id, jobs.created_at \
+         FROM job_items JOIN jobs ON jobs.id = job_items.job_id \
+         WHERE jobs.kind = 'ingest' AND json_extract(jobs.options, '$.hot_folder') = ? \
+         AND job_items.state = 'done' AND jobs.created_at > ? \
+         ORDER BY job_items.id DESC LIMIT ?",
+    )
+    .bind(id)
+    .bind(since.unwrap_or(0))
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| HotFolderArrival {
+            path: row.get("target"),
+            source: row.get("source"),
+            size: row.get("size"),
+            job_id: row.get("job_id"),
+            arrived_at: row.get("created_at"),
+        })
+        .collect())
+}
+
+/// The latest ingest job the hot folder started, if any.
+pub async fn last_hot_folder_job(pool: &SqlitePool, id: i64) -> Result<Option<Job>> {
+    let sql = format!(
+        "SELECT {JOB_COLUMNS} FROM jobs WHERE kind = 'ingest' \
+         AND json_extract(options, '$.hot_folder') = ? ORDER BY jobs.id DESC LIMIT 1"
+    );
+    let row = sqlx::query(&sql).bind(id).fetch_optional(pool).await?;
+    Ok(row.map(row_to_job))
+}
+
+fn row_to_hot_folder(row: SqliteRow) -> HotFolder {
+    let settings_raw: String = row.get("settings");
+    HotFolder {
+        id: row.get("id"),
+        path: row.get("path"),
+        destination: row.get("destination"),
+        settings: serde_json::from_str(&settings_raw).unwrap_or_default(),
+        enabled: row.get("enabled"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+fn row_to_saved_search(row: SqliteRow) -> SavedSearch {
+    let filter_raw: String = row.get("filter");
+    SavedSearch {
+        id: row.get("id"),
+        name: row.get("name"),
+        filter: serde_json::from_str(&filter_raw).unwrap_or_default(),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+/// Applies `rewrite` to the tag list of every tagged file inside one
+/// transaction and returns the files whose tags actually changed.
+pub async fn rewrite_tags<F>(pool: &SqlitePool, edit: Edit<'_>, mut rewrite: F) -> Result<Vec<TagChange>>
+where
+    F: FnMut(&[String]) -> Vec<String>,
+{
+    let mut tx = pool.begin().await?;
+    let rows = sqlx::query(
+        "SELECT path, tags FROM files WHERE deleted_at IS NULL AND tags IS NOT NULL AND tags != '' AND tags != '[]'",
+    )
+        .fetch_all(&mut *tx)
         .await?;
-    let mut seen = HashSet::new();
-    let mut tags = Vec::new();
 
+    let now = unix_now();
+    let mut operation_id = None;
+    let mut changes = Vec::new();
     for row in rows {
-        let raw: Option<String> = row.get("tags");
-        let Some(raw) = raw else {
+        let path: String = row.get("path");
+        let raw: String = row.get("tags");
+        let Ok(current) = serde_json::from_str::<Vec<String>>(&raw) else {
             continue;
         };
-        if raw.trim().is_empty() || raw.trim() == "[]" {
+        let updated = rewrite(&current);
+        if updated == current {
             continue;
         }
-        let parsed: Result<Vec<String>, _> = serde_json::from_str(&raw);
-        if let Ok(list) = parsed {
-            for tag in list {
-                let trimmed = tag.trim();
-                if trimmed.is_empty() {
-                    continue;
-                }
-                let key = trimmed.to_lowercase();
-                if seen.insert(key) {
-                    tags.push(trimmed.to_string());
-                }
+        sqlx::query("UPDATE files SET tags = ?, tags_updated_at = ? WHERE path = ?")
+            .bind(serde_json::to_string(&updated)?)
+            .bind(now)
+            .bind(&path)
+            .execute(&mut *tx)
+            .await?;
+        let before = FileMeta {
+            tags: current,
+            ..Default::default()
+        };
+        let after = FileMeta {
+            path: path.clone(),
+            tags: updated.clone(),
+            ..Default::default()
+        };
+        record_history(&mut tx, edit, &mut operation_id, &before, &after).await?;
+        changes.push(TagChange {
+            path,
+            tags: updated,
+        });
+    }
+
+    tx.commit().await?;
+    Ok(changes)
+}
+
+/// Loads (or starts) the row for every target, lets `apply` edit it and writes
+/// all rows back in a single transaction. Files without a row yet keep
+/// `orientation` unset so they are still picked up by the next scan.
+pub async fn update_files<F>(
+    pool: &SqlitePool,
+    edit: Edit<'_>,
+    targets: &[BatchTarget],
+    mut apply: F,
+) -> Result<Vec<FileMeta>>
+where
+    F: FnMut(&mut FileMeta),
+{
+    let mut tx = pool.begin().await?;
+    let now = unix_now();
+    let mut operation_id = None;
+    let mut updated = Vec::with_capacity(targets.len());
+
+    for target in targets {
+        let mut meta = get_file_meta(&mut *tx, &target.path)
+            .await?
+            .unwrap_or_else(|| FileMeta {
+                path: target.path.clone(),
+                file_size: target.file_size,
+                last_modified: target.last_modified,
+                ..Default::default()
+            });
+        let before = meta.clone();
+        apply(&mut meta);
+        upsert_file_meta(&mut *tx, &meta).await?;
+        record_history(&mut tx, edit, &mut operation_id, &before, &meta).await?;
+
+        if meta.tags != before.tags {
+            sqlx::query("UPDATE files SET tags_updated_at = ? WHERE path = ?")
+                .bind(now)
+                .bind(&meta.path)
+                .execute(&mut *tx)
+                .await?;
+        }
+        updated.push(meta);
+    }
+
+    tx.commit().await?;
+    Ok(updated)
+}
+
+/// Returns up to `limit` rows ordered by path, starting after `after`, so an
+/// export can walk the whole table without holding it in memory.
+pub async fn list_files_after(pool: &SqlitePool, after: &str, limit: i64) -> Result<Vec<FileMeta>> {
+    let sql = format!("SELECT {FILE_COLUMNS} FROM files WHERE deleted_at IS NULL AND path > ? ORDER BY path LIMIT ?");
+    let rows = sqlx::query(&sql)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(row_to_meta).collect())
+}
+
+/// Applies imported catalog records to their rows in one transaction. A dry
+/// run goes through exactly the same steps and rolls back at the end.
+pub async fn import_files(
+    pool: &SqlitePool,
+    edit: Edit<'_>,
+    records: &[(BatchTarget, CatalogRecord)],
+    strategy: MergeStrategy,
+    dry_run: bool,
+) -> Result<Vec<ImportChange>> {
+    let mut tx = pool.begin().await?;
+    let now = unix_now();
+    let mut operation_id = None;
+    let mut changes = Vec::with_capacity(records.len());
+
+    for (target, record) in records {
+        let existing = get_file_meta(&mut *tx, &target.path).await?;
+        let previous = existing.clone().unwrap_or_default();
+        let (action, meta) = match existing {
+            Some(existing) if strategy == MergeStrategy::Skip => (ImportAction::Skipped, existing),
+            Some(existing) => {
+                let mut meta = existing.clone();
+                catalog::apply_record(&mut meta, record, strategy);
+                let action = if same_catalog_fields(&meta, &existing) {
+                    ImportAction::Unchanged
+                } else {
+                    ImportAction::Updated
+                };
+                (action, meta)
+            }
+            None => {
+                let mut meta = FileMeta {
+                    path: target.path.clone(),
+                    file_size: target.file_size,
+                    last_modified: target.last_modified,
+                    ..Default::default()
+                };
+                catalog::apply_record(&mut meta, record, MergeStrategy::Overwrite);
+                (ImportAction::Created, meta)
+            }
+        };
+
+        let tags_changed = meta.tags != previous.tags;
+        let title_changed = meta.title != previous.title;
+        let caption_changed = meta.caption != previous.caption;
+        if matches!(action, ImportAction::Created | ImportAction::Updated) {
+            upsert_file_meta(&mut *tx, &meta).await?;
+            record_history(&mut tx, edit, &mut operation_id, &previous, &meta).await?;
+            if tags_changed {
+                sqlx::query("UPDATE files SET tags_updated_at = ? WHERE path = ?")
+                    .bind(now)
+                    .bind(&meta.path)
+                    .execute(&mut *tx)
+                    .await?;
             }
         }
+        changes.push(ImportChange {
+            action,
+            meta,
+            tags_changed,
+            title_changed,
+            caption_changed,
+        });
+    }
+
+    if dry_run {
+        tx.rollback().await?;
+    } else {
+        tx.commit().await?;
     }
+    Ok(changes)
+}
 
-    tags.sort_by(|a, b| a.to_lowercase().cmp(&b.to_lowercase()));
-    Ok(tags)
+fn same_catalog_fields(a: &FileMeta, b: &FileMeta) -> bool {
+    a.user_rating == b.user_rating
+        && a.tags == b.tags
+        && a.color_label == b.color_label
+        && a.flag == b.flag
+        && a.title == b.title
+        && a.caption == b.caption
+        && a.notes == b.notes
 }
 
-pub async fn delete_meta(pool: &SqlitePool, path: &str) -> Result<()> {
-    sqlx::query("DELETE FROM files WHERE path = ?")
-        .bind(path)
-        .execute(pool)
+/// Stores every recorded field that changed between `before` and `after`,
+/// starting the history operation on the first change so requests that
+/// change nothing leave no trace.
+async fn record_history(
+    tx: &mut Transaction<'_, Sqlite>,
+    edit: Edit<'_>,
+    operation_id: &mut Option<i64>,
+    before: &FileMeta,
+    after: &FileMeta,
+) -> Result<()> {
+    let changes = history::diff(before, after);
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let id = match *operation_id {
+        Some(id) => id,
+        None => {
+            let result = sqlx::query("INSERT INTO history_operations (kind, actor, created_at) VALUES (?, ?, ?)")
+                .bind(edit.kind)
+                .bind(edit.actor)
+                .bind(unix_now())
+                .execute(&mut **tx)
+                .await?;
+            let id = result.last_insert_rowid();
+            *operation_id = Some(id);
+            id
+        }
+    };
+    for (field, old, new) in changes {
+        sqlx::query(
+            "INSERT INTO history_entries (operation_id, path, field, old_value, new_value) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(&after.path)
+        .bind(field)
+        .bind(old.to_string())
+        .bind(new.to_string())
+        .execute(&mut **tx)
         .await?;
+    }
+    Ok(())
+}
+
+const HISTORY_ENTRY_COLUMNS: &str = "history_entries.id, history_entries.operation_id, history_entries.path, \
+    history_entries.field, history_entries.old_value, history_entries.new_value, history_operations.kind, \
+    history_operations.actor, history_operations.created_at, history_operations.undone_at";
+
+pub async fn list_history(pool: &SqlitePool, limit: i64) -> Result<Vec<HistoryOperation>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT history_operations.id, history_operations.kind, history_operations.actor,
+            history_operations.created_at, history_operations.undone_at,
+            COUNT(DISTINCT history_entries.path) AS file_count,
+            COUNT(history_entries.id) AS change_count
+        FROM history_operations
+        LEFT JOIN history_entries ON history_entries.operation_id = history_operations.id
+        GROUP BY history_operations.id
+        ORDER BY history_operations.id DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| HistoryOperation {
+            id: row.get("id"),
+            kind: row.get("kind"),
+            actor: row.get("actor"),
+            created_at: row.get("created_at"),
+            undone_at: row.get("undone_at"),
+            file_count: row.get("file_count"),
+            change_count: row.get("change_count"),
+        })
+        .collect())
+}
+
+pub async fn list_file_history(pool: &SqlitePool, path: &str, limit: i64) -> Result<Vec<HistoryEntry>> {
+    let sql = format!(
+        "SELECT {HISTORY_ENTRY_COLUMNS} FROM history_entries \
+        JOIN history_operations ON history_operations.id = history_entries.operation_id \
+        WHERE history_entries.path = ? ORDER BY history_entries.id DESC LIMIT ?"
+    );
+    let rows = sqlx::query(&sql).bind(path).bind(limit).fetch_all(pool).await?;
+    Ok(rows.into_iter().map(row_to_history_entry).collect())
+}
+
+pub async fn get_history_entry(pool: &SqlitePool, id: i64) -> Result<Option<HistoryEntry>> {
+    let sql = format!(
+        "SELECT {HISTORY_ENTRY_COLUMNS} FROM history_entries \
+        JOIN history_operations ON history_operations.id = history_entries.operation_id \
+        WHERE history_entries.id = ?"
+    );
+    let row = sqlx::query(&sql).bind(id).fetch_optional(pool).await?;
+    Ok(row.map(row_to_history_entry))
+}
+
+/// Rolls back the `count` most recent operations that are not undone yet,
+/// newest first, in one transaction. A field that was changed again outside
+/// the history since (or whose file is gone) is left alone and reported as
+/// a conflict instead of being overwritten. An operation none of whose
+/// changes could be restored is reported as skipped, but still marked undone
+/// so it cannot block later undos.
+pub async fn undo_history(pool: &SqlitePool, count: i64) -> Result<UndoOutcome> {
+    let mut tx = pool.begin().await?;
+    let now = unix_now();
+    let operations: Vec<i64> = sqlx::query_scalar(
+        "SELECT id FROM history_operations WHERE undone_at IS NULL ORDER BY id DESC LIMIT ?",
+    )
+    .bind(count)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut undone = Vec::with_capacity(operations.len());
+    let mut skipped = Vec::new();
+    let mut restored: Vec<RestoredFile> = Vec::new();
+    let mut conflicts = Vec::new();
+    for operation_id in operations {
+        let sql = format!(
+            "SELECT {HISTORY_ENTRY_COLUMNS} FROM history_entries \
+            JOIN history_operations ON history_operations.id = history_entries.operation_id \
+            WHERE history_entries.operation_id = ? ORDER BY history_entries.id DESC"
+        );
+        let entries: Vec<HistoryEntry> = sqlx::query(&sql)
+            .bind(operation_id)
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .map(row_to_history_entry)
+            .collect();
+
+        let mut any_restored = false;
+        for entry in entries {
+            let Some(mut meta) = get_file_meta(&mut *tx, &entry.path).await? else {
+                conflicts.push(entry);
+                continue;
+            };
+            if history::field_value(&meta, &entry.field) != entry.new_value {
+                conflicts.push(entry);
+                continue;
+            }
+            history::set_field(&mut meta, &entry.field, &entry.old_value);
+            upsert_file_meta(&mut *tx, &meta).await?;
+            any_restored = true;
+            if entry.field == "tags" {
+                sqlx::query("UPDATE files SET tags_updated_at = ? WHERE path = ?")
+                    .bind(now)
+                    .bind(&meta.path)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            match restored.iter_mut().find(|file| file.meta.path == meta.path) {
+                Some(file) => {
+                    file.meta = meta;
+                    file.fields.push(entry.field);
+                }
+                None => restored.push(RestoredFile {
+                    meta,
+                    fields: vec![entry.field],
+                }),
+            }
+        }
+
+        sqlx::query("UPDATE history_operations SET undone_at = ? WHERE id = ?")
+            .bind(now)
+            .bind(operation_id)
+            .execute(&mut *tx)
+            .await?;
+        if any_restored {
+            undone.push(operation_id);
+        } else {
+            skipped.push(operation_id);
+        }
+    }
+
+    tx.commit().await?;
+    Ok(UndoOutcome {
+        operations: undone,
+        skipped,
+        restored,
+        conflicts,
+    })
+}
+
+fn row_to_history_entry(row: SqliteRow) -> HistoryEntry {
+    let parse = |raw: String| serde_json::from_str(&raw).unwrap_or(serde_json::Value::Null);
+    HistoryEntry {
+        id: row.get("id"),
+        operation_id: row.get("operation_id"),
+        kind: row.get("kind"),
+        actor: row.get("actor"),
+        created_at: row.get("created_at"),
+        undone_at: row.get("undone_at"),
+        path: row.get("path"),
+        field: row.get("field"),
+        old_value: parse(row.get("old_value")),
+        new_value: parse(row.get("new_value")),
+    }
+}
+
+async fn delete_meta(conn: &mut SqliteConnection, path: &str) -> Result<()> {
+    for sql in [
+        "DELETE FROM files WHERE path = ?",
+        "DELETE FROM album_items WHERE path = ?",
+        "DELETE FROM history_entries WHERE path = ?",
+        "DELETE FROM ingested_files WHERE path = ?",
+    ] {
+        sqlx::query(sql).bind(path).execute(&mut *conn).await?;
+    }
     Ok(())
 }
 
-pub async fn delete_meta_prefix(pool: &SqlitePool, prefix: &str) -> Result<()> {
+async fn delete_meta_prefix(conn: &mut SqliteConnection, prefix: &str) -> Result<()> {
     let prefix = prefix.trim_matches('/');
     let like_pattern = format!("{}/%", prefix);
-    sqlx::query("DELETE FROM files WHERE path = ? OR path LIKE ?")
-        .bind(prefix)
-        .bind(like_pattern)
-        .execute(pool)
-        .await?;
+    for table in ["files", "album_items", "history_entries", "ingested_files"] {
+        let sql = format!("DELETE FROM {table} WHERE path = ? OR path LIKE ?");
+        sqlx::query(&sql)
+            .bind(prefix)
+            .bind(&like_pattern)
+            .execute(&mut *conn)
+            .await?;
+    }
     Ok(())
 }
 
-pub async fn move_meta(pool: &SqlitePool, from_path: &str, to_path: &str) -> Result<()> {
-    sqlx::query("UPDATE files SET path = ? WHERE path = ?")
-        .bind(to_path)
-        .bind(from_path)
-        .execute(pool)
-        .await?;
+async fn move_meta(conn: &mut SqliteConnection, from_path: &str, to_path: &str) -> Result<()> {
+    for sql in [
+        "UPDATE files SET path = ? WHERE path = ?",
+        "UPDATE OR REPLACE album_items SET path = ? WHERE path = ?",
+        "UPDATE history_entries SET path = ? WHERE path = ?",
+        "UPDATE ingested_files SET path = ? WHERE path = ?",
+    ] {
+        sqlx::query(sql)
+            .bind(to_path)
+            .bind(from_path)
+            .execute(&mut *conn)
+            .await?;
+    }
     Ok(())
 }
 
-pub async fn move_meta_prefix(
-    pool: &SqlitePool,
+async fn move_meta_prefix(
+    conn: &mut SqliteConnection,
     from_prefix: &str,
     to_prefix: &str,
 ) -> Result<()> {
@@ -210,17 +1193,738 @@ pub async fn move_meta_prefix(
         format!("{}/", to_prefix)
     };
     let start_index = from_prefix.len() + 2;
-    sqlx::query(
+    for sql in [
         "UPDATE files SET path = ? || substr(path, ?) WHERE path LIKE ?",
+        "UPDATE OR REPLACE album_items SET path = ? || substr(path, ?) WHERE path LIKE ?",
+        "UPDATE history_entries SET path = ? || substr(path, ?) WHERE path LIKE ?",
+        "UPDATE ingested_files SET path = ? || substr(path, ?) WHERE path LIKE ?",
+    ] {
+        sqlx::query(sql)
+            .bind(&target_prefix)
+            .bind(start_index as i64)
+            .bind(&like_pattern)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Records the renames a filesystem request is about to make, before any of
+/// them happen, and returns the batch id that ties them together (0 when
+/// there is nothing to record).
+pub async fn journal_begin(pool: &SqlitePool, op: &str, steps: &[JournalStep]) -> Result<i64> {
+    let mut tx = pool.begin().await?;
+    let now = unix_now();
+    let mut batch = None;
+    for step in steps {
+        // The first insert picks the batch id, so concurrent requests never
+        // share one.
+        let id: i64 = sqlx::query_scalar(
+            r#"
+            INSERT INTO fs_journal (batch, op, source, target, is_dir, state, created_at)
+            SELECT COALESCE(?, (SELECT COALESCE(MAX(batch), 0) + 1 FROM fs_journal)), ?, ?, ?, ?, 'pending', ?
+            RETURNING batch
+            "#,
+        )
+        .bind(batch)
+        .bind(op)
+        .bind(step.source.to_string_lossy().to_string())
+        .bind(step.target.to_string_lossy().to_string())
+        .bind(step.is_dir)
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await?;
+        batch = Some(id);
+    }
+    tx.commit().await?;
+    Ok(batch.unwrap_or(0))
+}
+
+/// Updates the catalog for a journaled batch in one transaction. Moves are
+/// done once this commits, so their journal rows go with it; deletes are
+/// marked committed until their staged files have been removed.
+pub async fn apply_path_changes(pool: &SqlitePool, batch: i64, changes: &[PathChange]) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    let now = unix_now();
+    for change in changes {
+        match change {
+            PathChange::Move { from, to, is_dir } => move_path_meta(&mut tx, from, to, *is_dir).await?,
+            PathChange::Delete { path, is_dir } => {
+                if *is_dir {
+                    delete_meta_prefix(&mut tx, path).await?;
+                } else {
+                    delete_meta(&mut tx, path).await?;
+                }
+                sqlx::query("DELETE FROM trash WHERE trash_path = ?")
+                    .bind(path)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            PathChange::Trash { from, to, is_dir, size } => {
+                move_path_meta(&mut tx, from, to, *is_dir).await?;
+                set_deleted_at(&mut tx, to, Some(now)).await?;
+                sqlx::query(
+                    "INSERT INTO trash (original_path, trash_path, is_dir, size, deleted_at) VALUES (?, ?, ?, ?, ?)",
+                )
+                .bind(from)
+                .bind(to)
+                .bind(is_dir)
+                .bind(size)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+            }
+            PathChange::Restore { id, from, to, is_dir } => {
+                move_path_meta(&mut tx, from, to, *is_dir).await?;
+                set_deleted_at(&mut tx, to, None).await?;
+                sqlx::query("DELETE FROM trash WHERE id = ?")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+    }
+    if changes.iter().any(|change| matches!(change, PathChange::Delete { .. })) {
+        sqlx::query("UPDATE fs_journal SET state = 'committed' WHERE batch = ?")
+            .bind(batch)
+            .execute(&mut *tx)
+            .await?;
+    } else {
+        journal_clear(&mut *tx, batch).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn move_path_meta(conn: &mut SqliteConnection, from: &str, to: &str, is_dir: bool) -> Result<()> {
+    if is_dir {
+        move_meta_prefix(conn, from, to).await
+    } else {
+        move_meta(conn, from, to).await
+    }
+}
+
+/// Sets or clears the deleted marker on `path` and everything below it.
+async fn set_deleted_at(conn: &mut SqliteConnection, path: &str, deleted_at: Option<i64>) -> Result<()> {
+    sqlx::query("UPDATE files SET deleted_at = ? WHERE path = ? OR path LIKE ? ESCAPE '\\'")
+        .bind(deleted_at)
+        .bind(path)
+        .bind(format!("{}/%", like_escape(path)))
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn list_trash(pool: &SqlitePool) -> Result<Vec<TrashItem>> {
+    let rows = sqlx::query(
+        "SELECT id, original_path, trash_path, is_dir, size, deleted_at FROM trash ORDER BY deleted_at DESC, id DESC",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(row_to_trash_item).collect())
+}
+
+pub async fn get_trash_item(pool: &SqlitePool, id: i64) -> Result<Option<TrashItem>> {
+    let row = sqlx::query(
+        "SELECT id, original_path, trash_path, is_dir, size, deleted_at FROM trash WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(row_to_trash_item))
+}
+
+fn row_to_trash_item(row: SqliteRow) -> TrashItem {
+    TrashItem {
+        id: row.get("id"),
+        original_path: row.get("original_path"),
+        trash_path: row.get("trash_path"),
+        is_dir: row.get("is_dir"),
+        size: row.get("size"),
+        deleted_at: row.get("deleted_at"),
+    }
+}
+
+pub async fn journal_clear<'e, E>(executor: E, batch: i64) -> Result<()>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    sqlx::query("DELETE FROM fs_journal WHERE batch = ?")
+        .bind(batch)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub async fn list_journal(pool: &SqlitePool) -> Result<Vec<JournalEntry>> {
+    let rows = sqlx::query(
+        "SELECT batch, op, source, target, is_dir, state FROM fs_journal ORDER BY batch, id",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| JournalEntry {
+            batch: row.get("batch"),
+            op: row.get("op"),
+            committed: row.get::<String, _>("state") == "committed",
+            step: JournalStep {
+                source: PathBuf::from(row.get::<String, _>("source")),
+                target: PathBuf::from(row.get::<String, _>("target")),
+                is_dir: row.get("is_dir"),
+            },
+        })
+        .collect())
+}
+
+const JOB_COLUMNS: &str = "jobs.id, jobs.kind, jobs.state, jobs.error, jobs.total_bytes, jobs.done_bytes, \
+    jobs.options, jobs.created_at, jobs.updated_at, \
+    (SELECT COUNT(*) FROM job_items WHERE job_items.job_id = jobs.id AND job_items.state = 'skipped') AS skipped_count, \
+    (SELECT COUNT(*) FROM job_items WHERE job_items.job_id = jobs.id AND job_items.state = 'failed') AS failed_count, \
+    (SELECT COUNT(*) FROM job_items WHERE job_items.job_id = jobs.id) AS item_count, \
+    (SELECT COUNT(*) FROM job_items WHERE job_items.job_id = jobs.id AND job_items.state = 'done') AS done_count";
+
+pub async fn create_job(
+    pool: &SqlitePool,
+    kind: &str,
+    items: &[JobItem],
+    options: Option<&serde_json::Value>,
+) -> Result<Job> {
+    let mut tx = pool.begin().await?;
+    let now = unix_now();
+    let total: i64 = items.iter().map(|item| item.size).sum();
+    let id = sqlx::query(
+        "INSERT INTO jobs (kind, state, total_bytes, done_bytes, options, created_at, updated_at) \
+         VALUES (?, 'pending', ?, 0, ?, ?, ?)",
+    )
+    .bind(kind)
+    .bind(total)
+    .bind(options.map(|options| options.to_string()))
+    .bind(now)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?
+    .last_insert_rowid();
+    for item in items {
+        sqlx::query(
+            "INSERT INTO job_items (job_id, source, target, is_dir, size, state) VALUES (?, ?, ?, ?, ?, 'pending')",
+        )
+        .bind(id)
+        .bind(&item.source)
+        .bind(&item.target)
+        .bind(item.is_dir)
+        .bind(item.size)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    get_job(pool, id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("job missing after insert"))
+}
+
+pub async fn get_job(pool: &SqlitePool, id: i64) -> Result<Option<Job>> {
+    let sql = format!("SELECT {JOB_COLUMNS} FROM jobs WHERE jobs.id = ?");
+    let row = sqlx::query(&sql).bind(id).fetch_optional(pool).await?;
+    Ok(row.map(row_to_job))
+}
+
+pub async fn list_jobs(pool: &SqlitePool, limit: i64) -> Result<Vec<Job>> {
+    let sql = format!("SELECT {JOB_COLUMNS} FROM jobs ORDER BY jobs.id DESC LIMIT ?");
+    let rows = sqlx::query(&sql).bind(limit).fetch_all(pool).await?;
+    Ok(rows.into_iter().map(row_to_job).collect())
+}
+
+pub async fn list_job_items(pool: &SqlitePool, job_id: i64) -> Result<Vec<JobItem>> {
+    let rows = sqlx::query(
+        "SELECT id, source, target, is_dir, size, state FROM job_items WHERE job_id = ? ORDER BY id",
+    )
+    .bind(job_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| JobItem {
+            id: row.get("id"),
+            source: row.get("source"),
+            target: row.get("target"),
+            is_dir: row.get("is_dir"),
+            size: row.get("size"),
+            state: row.get("state"),
+        })
+        .collect())
+}
+
+/// Marks a pending or failed job as running, and its failed items pending
+/// again. Returns false when it is already running or finished, so a job is
+/// never worked on twice.
+pub async fn claim_job(pool: &SqlitePool, id: i64) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query(
+        "UPDATE jobs SET state = 'running', error = NULL, updated_at = ? WHERE id = ? AND state IN ('pending', 'failed')",
+    )
+    .bind(unix_now())
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    sqlx::query("UPDATE job_items SET state = 'pending' WHERE job_id = ? AND state = 'failed'")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Puts jobs that were running when the server stopped back in the queue
+/// and returns every queued job.
+pub async fn requeue_jobs(pool: &SqlitePool) -> Result<Vec<i64>> {
+    sqlx::query("UPDATE jobs SET state = 'pending' WHERE state = 'running'")
+        .execute(pool)
+        .await?;
+    let ids = sqlx::query_scalar("SELECT id FROM jobs WHERE state = 'pending' ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+    Ok(ids)
+}
+
+/// Moves the catalog row of one file a job has moved, and counts its bytes.
+pub async fn move_job_file(pool: &SqlitePool, job_id: i64, from: &str, to: &str, size: i64) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    move_meta(&mut tx, from, to).await?;
+    add_job_progress(&mut tx, job_id, size).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Gives a file a job has copied the same catalog row (ratings, tags, labels,
+/// text and camera data) as its original, and counts its bytes.
+pub async fn copy_job_file(pool: &SqlitePool, job_id: i64, from: &str, to: &str, size: i64) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    if let Some(mut meta) = get_file_meta(&mut *tx, from).await? {
+        meta.path = to.to_string();
+        upsert_file_meta(&mut *tx, &meta).await?;
+    }
+    add_job_progress(&mut tx, job_id, size).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Counts bytes of a job item that was moved in one step.
+pub async fn add_job_bytes(pool: &SqlitePool, job_id: i64, size: i64) -> Result<()> {
+    let mut conn = pool.acquire().await?;
+    add_job_progress(&mut conn, job_id, size).await
+}
+
+async fn add_job_progress(conn: &mut SqliteConnection, job_id: i64, size: i64) -> Result<()> {
+    sqlx::query("UPDATE jobs SET done_bytes = done_bytes + ?, updated_at = ? WHERE id = ?")
+        .bind(size)
+        .bind(unix_now())
+        .bind(job_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Marks an item done. For moves, catalog rows still under the old path
+/// (files that were already gone, or the folder itself) are moved too.
+pub async fn finish_job_item(pool: &SqlitePool, item: &JobItem, move_rows: bool) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    if move_rows {
+        move_path_meta(&mut tx, &item.source, &item.target, item.is_dir).await?;
+    }
+    sqlx::query("UPDATE job_items SET state = 'done' WHERE id = ?")
+        .bind(item.id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Records where an ingested file went, or the library copy it was skipped
+/// for, and counts its bytes. The file is remembered by hash so later
+/// ingests skip it.
+pub async fn finish_ingest_item(
+    pool: &SqlitePool,
+    job_id: i64,
+    item: &JobItem,
+    target: &str,
+    skipped: bool,
+    hash: &str,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE job_items SET target = ?, state = ? WHERE id = ?")
+        .bind(target)
+        .bind(if skipped { "skipped" } else { "done" })
+        .bind(item.id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("INSERT OR REPLACE INTO ingested_files (hash, path, ingested_at) VALUES (?, ?, ?)")
+        .bind(hash)
+        .bind(target)
+        .bind(unix_now())
+        .execute(&mut *tx)
+        .await?;
+    add_job_progress(&mut tx, job_id, item.size).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Marks an item that could not be done as failed, unless it got further
+/// than that before the error.
+pub async fn fail_job_item(pool: &SqlitePool, item: &JobItem) -> Result<()> {
+    sqlx::query("UPDATE job_items SET state = 'failed' WHERE id = ? AND state = 'pending'")
+        .bind(item.id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Library path a file with this SHA-256 was ingested to, if any.
+pub async fn find_ingested(pool: &SqlitePool, hash: &str) -> Result<Option<String>> {
+    let path = sqlx::query_scalar("SELECT path FROM ingested_files WHERE hash = ?")
+        .bind(hash)
+        .fetch_optional(pool)
+        .await?;
+    Ok(path)
+}
+
+pub async fn finish_job(pool: &SqlitePool, id: i64, error: Option<&str>) -> Result<()> {
+    let state = if error.is_some() { "failed" } else { "done" };
+    sqlx::query(
+        "UPDATE jobs SET state = ?, error = ?, done_bytes = CASE WHEN ? IS NULL THEN total_bytes ELSE done_bytes END, \
+         updated_at = ? WHERE id = ?",
+    )
+    .bind(state)
+    .bind(error)
+    .bind(error)
+    .bind(unix_now())
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn create_upload(
+    pool: &SqlitePool,
+    id: &str,
+    directory: &str,
+    name: &str,
+    size: i64,
+    sha256: Option<&str>,
+) -> Result<UploadSession> {
+    let now = unix_now();
+    sqlx::query(
+        "INSERT INTO upload_sessions (id, directory, name, size, received, sha256, created_at, updated_at) \
+         VALUES (?, ?, ?, ?, 0, ?, ?, ?)",
     )
-    .bind(target_prefix)
-    .bind(start_index as i64)
-    .bind(like_pattern)
+    .bind(id)
+    .bind(directory)
+    .bind(name)
+    .bind(size)
+    .bind(sha256)
+    .bind(now)
+    .bind(now)
     .execute(pool)
     .await?;
+    get_upload(pool, id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("upload session missing after insert"))
+}
+
+pub async fn get_upload(pool: &SqlitePool, id: &str) -> Result<Option<UploadSession>> {
+    let row = sqlx::query(
+        "SELECT id, directory, name, size, received, sha256, path, created_at, updated_at FROM upload_sessions WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(row_to_upload))
+}
+
+pub async fn list_uploads(pool: &SqlitePool) -> Result<Vec<UploadSession>> {
+    let rows = sqlx::query(
+        "SELECT id, directory, name, size, received, sha256, path, created_at, updated_at FROM upload_sessions ORDER BY created_at",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(row_to_upload).collect())
+}
+
+pub async fn set_upload_received(pool: &SqlitePool, id: &str, received: i64) -> Result<()> {
+    sqlx::query("UPDATE upload_sessions SET received = ?, updated_at = ? WHERE id = ?")
+        .bind(received)
+        .bind(unix_now())
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Records that an upload was moved into the library as `path`.
+pub async fn finish_upload(pool: &SqlitePool, id: &str, path: &str) -> Result<()> {
+    sqlx::query("UPDATE upload_sessions SET path = ?, updated_at = ? WHERE id = ?")
+        .bind(path)
+        .bind(unix_now())
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_upload(pool: &SqlitePool, id: &str) -> Result<()> {
+    sqlx::query("DELETE FROM upload_sessions WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
     Ok(())
 }
 
+fn row_to_upload(row: SqliteRow) -> UploadSession {
+    UploadSession {
+        id: row.get("id"),
+        directory: row.get("directory"),
+        name: row.get("name"),
+        size: row.get("size"),
+        received: row.get("received"),
+        sha256: row.get("sha256"),
+        path: row.get("path"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+fn row_to_job(row: SqliteRow) -> Job {
+    Job {
+        id: row.get("id"),
+        kind: row.get("kind"),
+        state: row.get("state"),
+        error: row.get("error"),
+        total_bytes: row.get("total_bytes"),
+        done_bytes: row.get("done_bytes"),
+        item_count: row.get("item_count"),
+        done_count: row.get("done_count"),
+        skipped_count: row.get("skipped_count"),
+        failed_count: row.get("failed_count"),
+        options: row
+            .get::<Option<String>, _>("options")
+            .and_then(|options| serde_json::from_str(&options).ok()),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+pub async fn list_albums(pool: &SqlitePool) -> Result<Vec<Album>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT albums.id, albums.name, albums.description, albums.created_at, albums.updated_at,
+            COUNT(album_items.path) AS item_count
+        FROM albums
+        LEFT JOIN album_items ON album_items.album_id = albums.id
+        GROUP BY albums.id
+        ORDER BY albums.name COLLATE NOCASE
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(row_to_album).collect())
+}
+
+pub async fn get_album<'e, E>(executor: E, id: i64) -> Result<Option<Album>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let row = sqlx::query(
+        r#"
+        SELECT albums.id, albums.name, albums.description, albums.created_at, albums.updated_at,
+            (SELECT COUNT(*) FROM album_items WHERE album_items.album_id = albums.id) AS item_count
+        FROM albums
+        WHERE albums.id = ?
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.map(row_to_album))
+}
+
+pub async fn create_album(pool: &SqlitePool, name: &str, description: Option<&str>) -> Result<Album> {
+    let now = unix_now();
+    let result = sqlx::query(
+        "INSERT INTO albums (name, description, created_at, updated_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(name)
+    .bind(description)
+    .bind(now)
+    .bind(now)
+    .execute(pool)
+    .await?;
+
+    get_album(pool, result.last_insert_rowid())
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("album missing after insert"))
+}
+
+pub async fn update_album(
+    pool: &SqlitePool,
+    id: i64,
+    name: Option<&str>,
+    description: Option<Option<&str>>,
+) -> Result<Option<Album>> {
+    let mut tx = pool.begin().await?;
+    let Some(album) = get_album(&mut *tx, id).await? else {
+        return Ok(None);
+    };
+    let name = name.unwrap_or(&album.name);
+    let description = description.unwrap_or(album.description.as_deref());
+    sqlx::query("UPDATE albums SET name = ?, description = ?, updated_at = ? WHERE id = ?")
+        .bind(name)
+        .bind(description)
+        .bind(unix_now())
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    let album = get_album(&mut *tx, id).await?;
+    tx.commit().await?;
+    Ok(album)
+}
+
+pub async fn delete_album(pool: &SqlitePool, id: i64) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM album_items WHERE album_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    let result = sqlx::query("DELETE FROM albums WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn list_album_paths<'e, E>(executor: E, id: i64) -> Result<Vec<String>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    // Trashed items stay in the album so a restore brings them back.
+    let rows = sqlx::query(
+        "SELECT path FROM album_items WHERE album_id = ? \
+        AND path NOT LIKE ? ESCAPE '\\' AND path NOT LIKE ? ESCAPE '\\' ORDER BY position, added_at",
+    )
+    .bind(id)
+    .bind(format!("{}/%", like_escape(trash::TRASH_DIR)))
+    .bind(format!("%/{}/%", like_escape(trash::TRASH_DIR)))
+    .fetch_all(executor)
+    .await?;
+    Ok(rows.into_iter().map(|row| row.get("path")).collect())
+}
+
+/// Appends the paths to the end of the album, skipping ones already in it.
+/// Returns how many were added.
+pub async fn add_album_items(pool: &SqlitePool, id: i64, paths: &[String]) -> Result<usize> {
+    let mut tx = pool.begin().await?;
+    let next_position: Option<i64> =
+        sqlx::query_scalar("SELECT MAX(position) + 1 FROM album_items WHERE album_id = ?")
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+    let mut position = next_position.unwrap_or(0);
+    let now = unix_now();
+    let mut added = 0;
+
+    for path in paths {
+        let result = sqlx::query(
+            "INSERT OR IGNORE INTO album_items (album_id, path, position, added_at) VALUES (?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(path)
+        .bind(position)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() > 0 {
+            added += 1;
+            position += 1;
+        }
+    }
+
+    touch_album(&mut tx, id).await?;
+    tx.commit().await?;
+    Ok(added)
+}
+
+pub async fn remove_album_items(pool: &SqlitePool, id: i64, paths: &[String]) -> Result<usize> {
+    let mut tx = pool.begin().await?;
+    let mut removed = 0;
+    for path in paths {
+        let result = sqlx::query("DELETE FROM album_items WHERE album_id = ? AND path = ?")
+            .bind(id)
+            .bind(path)
+            .execute(&mut *tx)
+            .await?;
+        removed += result.rows_affected() as usize;
+    }
+    touch_album(&mut tx, id).await?;
+    tx.commit().await?;
+    Ok(removed)
+}
+
+/// Puts `paths` first, in the given order, followed by the remaining items in
+/// their current order. Paths that are not in the album are ignored.
+pub async fn reorder_album_items(pool: &SqlitePool, id: i64, paths: &[String]) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    let current = list_album_paths(&mut *tx, id).await?;
+    let members: HashSet<&str> = current.iter().map(String::as_str).collect();
+
+    let mut ordered: Vec<&str> = Vec::with_capacity(current.len());
+    let mut placed = HashSet::new();
+    for path in paths {
+        if members.contains(path.as_str()) && placed.insert(path.as_str()) {
+            ordered.push(path);
+        }
+    }
+    for path in &current {
+        if placed.insert(path.as_str()) {
+            ordered.push(path);
+        }
+    }
+
+    for (position, path) in ordered.into_iter().enumerate() {
+        sqlx::query("UPDATE album_items SET position = ? WHERE album_id = ? AND path = ?")
+            .bind(position as i64)
+            .bind(id)
+            .bind(path)
+            .execute(&mut *tx)
+            .await?;
+    }
+    touch_album(&mut tx, id).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn touch_album(tx: &mut Transaction<'_, Sqlite>, id: i64) -> Result<()> {
+    sqlx::query("UPDATE albums SET updated_at = ? WHERE id = ?")
+        .bind(unix_now())
+        .bind(id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+fn row_to_album(row: SqliteRow) -> Album {
+    Album {
+        id: row.get("id"),
+        name: row.get("name"),
+        description: row.get("description"),
+        item_count: row.get("item_count"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
 fn row_to_meta(row: SqliteRow) -> FileMeta {
     let tags_raw: Option<String> = row.get("tags");
     let tags: Vec<String> = tags_raw
@@ -236,78 +1940,106 @@ fn row_to_meta(row: SqliteRow) -> FileMeta {
         gps_lon: row.get("gps_lon"),
         taken_at: row.get("taken_at"),
         orientation: row.get("orientation"),
+        camera_make: row.get("camera_make"),
+        camera_model: row.get("camera_model"),
+        color_label: row.get("color_label"),
+        flag: row.get("flag"),
+        title: row.get("title"),
+        caption: row.get("caption"),
+        notes: row.get("notes"),
         file_size: row.get("file_size"),
         last_modified: row.get("last_modified"),
     }
 }
 
-async fn create_files_table(pool: &SqlitePool) -> Result<()> {
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS files (\
-            path TEXT PRIMARY KEY,\
-            camera_rating INTEGER,\
-            user_rating INTEGER,\
-            tags TEXT,\
-            gps_lat REAL,\
-            gps_lon REAL,\
-            taken_at TEXT,\
-            orientation INTEGER,\
-            file_size INTEGER NOT NULL,\
-            last_modified INTEGER NOT NULL\
-        );",
-    )
-    .execute(pool)
-    .await?;
-    Ok(())
+fn like_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
 }
 
-async fn ensure_files_schema(pool: &SqlitePool) -> Result<()> {
-    let columns = list_columns(pool, "files").await?;
-    if columns.is_empty() {
-        return Ok(());
-    }
+/// Formats a unix timestamp as `YYYY-MM-DD` (UTC), the prefix of the EXIF
+/// `taken_at` values stored in `files`.
+fn unix_to_date(secs: i64) -> String {
+    let days = secs.div_euclid(86_400);
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
 
-    if !columns.contains("path") {
-        let legacy_suffix = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        let legacy_name = format!("files_legacy_{}", legacy_suffix);
-        let rename_sql = format!("ALTER TABLE files RENAME TO {}", legacy_name);
-        sqlx::query(&rename_sql).execute(pool).await?;
-        create_files_table(pool).await?;
-        return Ok(());
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn test_pool() -> SqlitePool {
+        // One connection, so every query sees the same in-memory database.
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_db(&pool, Path::new("unused.db")).await.unwrap();
+        pool
     }
 
-    let required = [
-        ("camera_rating", "INTEGER"),
-        ("user_rating", "INTEGER"),
-        ("tags", "TEXT"),
-        ("gps_lat", "REAL"),
-        ("gps_lon", "REAL"),
-        ("taken_at", "TEXT"),
-        ("orientation", "INTEGER"),
-        ("file_size", "INTEGER NOT NULL DEFAULT 0"),
-        ("last_modified", "INTEGER NOT NULL DEFAULT 0"),
-    ];
-
-    for (name, ty) in required {
-        if !columns.contains(name) {
-            let sql = format!("ALTER TABLE files ADD COLUMN {} {}", name, ty);
-            sqlx::query(&sql).execute(pool).await?;
-        }
+    async fn rate(pool: &SqlitePool, path: &str, rating: i32) {
+        let target = BatchTarget {
+            path: path.to_string(),
+            file_size: 1,
+            last_modified: 1,
+        };
+        let edit = Edit {
+            kind: "rating",
+            actor: "test",
+        };
+        update_files(pool, edit, &[target], |meta| meta.user_rating = Some(rating))
+            .await
+            .unwrap();
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn undo_moves_past_an_operation_that_conflicts() {
+        let pool = test_pool().await;
+        rate(&pool, "a.ARW", 3).await;
+        rate(&pool, "b.ARW", 4).await;
+        // b.ARW is changed again outside the history.
+        let mut meta = get_file_meta(&pool, "b.ARW").await.unwrap().unwrap();
+        meta.user_rating = Some(1);
+        upsert_file_meta(&pool, &meta).await.unwrap();
 
-async fn list_columns(pool: &SqlitePool, table: &str) -> Result<HashSet<String>> {
-    let query = format!("PRAGMA table_info({});", table);
-    let rows = sqlx::query(&query).fetch_all(pool).await?;
-    let mut columns = HashSet::new();
-    for row in rows {
-        let name: String = row.get("name");
-        columns.insert(name);
+        let first = undo_history(&pool, 1).await.unwrap();
+        assert!(first.operations.is_empty());
+        assert_eq!(first.skipped.len(), 1);
+        assert_eq!(first.conflicts.len(), 1);
+        assert_eq!(first.conflicts[0].path, "b.ARW");
+
+        let second = undo_history(&pool, 1).await.unwrap();
+        assert_eq!(second.operations.len(), 1);
+        assert!(second.skipped.is_empty());
+        let a = get_file_meta(&pool, "a.ARW").await.unwrap().unwrap();
+        assert_eq!(a.user_rating, None);
+        let b = get_file_meta(&pool, "b.ARW").await.unwrap().unwrap();
+        assert_eq!(b.user_rating, Some(1));
     }
-    Ok(columns)
 }
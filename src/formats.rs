@@ -0,0 +1,341 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// How many leading bytes `sniff` looks at.
+pub const SNIFF_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FormatClass {
+    Raw,
+    Image,
+}
+
+/// The container a file's leading bytes identify, whatever its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Container {
+    /// `II*\0` / `MM\0*` (and BigTIFF): DNG, NEF, ARW, CR2 and most RAWs.
+    Tiff,
+    /// Olympus `IIRO`, `IIRS` or `MMOR`.
+    Orf,
+    /// Panasonic and Leica `IIU\0`.
+    Rw2,
+    /// ISOBMFF with the Canon `crx ` brand.
+    Cr3,
+    /// ISOBMFF with a HEIF brand.
+    Heif,
+    /// `FUJIFILMCCD-RAW`.
+    Raf,
+    /// Minolta `\0MRM`.
+    Mrw,
+    /// Sigma `FOVb`.
+    X3f,
+    Jpeg,
+    Png,
+}
+
+impl Container {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Tiff => "TIFF",
+            Self::Orf => "ORF",
+            Self::Rw2 => "RW2",
+            Self::Cr3 => "CR3",
+            Self::Heif => "HEIF",
+            Self::Raf => "RAF",
+            Self::Mrw => "MRW",
+            Self::X3f => "X3F",
+            Self::Jpeg => "JPEG",
+            Self::Png => "PNG",
+        }
+    }
+}
+
+/// Identifies a file from its first `SNIFF_LEN` bytes.
+pub fn sniff(head: &[u8]) -> Option<Container> {
+    let container = if head.starts_with(b"FUJIFILMCCD-RAW") {
+        Container::Raf
+    } else if head.starts_with(b"IIRO") || head.starts_with(b"IIRS") || head.starts_with(b"MMOR") {
+        Container::Orf
+    } else if head.starts_with(b"IIU\0") {
+        Container::Rw2
+    } else if head.starts_with(b"II*\0")
+        || head.starts_with(b"MM\0*")
+        || head.starts_with(b"II+\0")
+        || head.starts_with(b"MM\0+")
+    {
+        Container::Tiff
+    } else if head.starts_with(b"\0MRM") {
+        Container::Mrw
+    } else if head.starts_with(b"FOVb") {
+        Container::X3f
+    } else if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Container::Jpeg
+    } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Container::Png
+    } else if head.get(4..8) == Some(b"ftyp") {
+        match head.get(8..12)? {
+            b"crx " => Container::Cr3,
+            b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" | b"mif1" | b"msf1" => {
+                Container::Heif
+            }
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+    Some(container)
+}
+
+/// Reads the start of a file and identifies it. An empty file is `None`.
+pub fn sniff_file(path: &Path) -> io::Result<Option<Container>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok(sniff(&head))
+}
+
+/// What the app can do with files of one format.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Capabilities {
+    /// EXIF (capture time, camera, GPS, rating) can be read from the file.
+    /// XMP sidecars are read for every format.
+    pub metadata: bool,
+    /// The file embeds JPEG previews that can be extracted.
+    pub preview: bool,
+    /// The image itself can be decoded, so previews are made from it.
+    pub decode: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Format {
+    pub extension: String,
+    pub name: String,
+    pub class: FormatClass,
+    #[serde(flatten)]
+    pub capabilities: Capabilities,
+    /// Containers files of this format come in; empty when unknown.
+    pub containers: &'static [Container],
+    /// Added through configuration rather than known to the app.
+    pub custom: bool,
+}
+
+impl Format {
+    /// Whether content identified as `detected` fits this format. Formats
+    /// with unknown containers accept anything that is not empty.
+    pub fn accepts(&self, detected: Option<Container>, empty: bool) -> bool {
+        if empty {
+            return false;
+        }
+        self.containers.is_empty() || detected.is_some_and(|container| self.containers.contains(&container))
+    }
+}
+
+const fn caps(metadata: bool, preview: bool, decode: bool) -> Capabilities {
+    Capabilities {
+        metadata,
+        preview,
+        decode,
+    }
+}
+
+/// RAW formats. EXIF is readable where the file starts with a standard TIFF
+/// header; CR3, RAF, ORF, RW2/RWL, MRW and X3F use their own containers.
+const RAW_FORMATS: &[(&str, &str, Capabilities, &[Container])] = &[
+    ("3fr", "Hasselblad 3FR", caps(true, true, false), TIFF),
+    ("arw", "Sony ARW", caps(true, true, false), TIFF),
+    ("cr2", "Canon CR2", caps(true, true, false), TIFF),
+    ("cr3", "Canon CR3", caps(false, true, false), CR3),
+    ("dcr", "Kodak DCR", caps(true, true, false), TIFF),
+    ("dng", "Adobe DNG", caps(true, true, false), TIFF),
+    ("erf", "Epson ERF", caps(true, true, false), TIFF),
+    ("iiq", "Phase One IIQ", caps(true, true, false), TIFF),
+    ("mrw", "Minolta MRW", caps(false, false, false), MRW),
+    ("nef", "Nikon NEF", caps(true, true, false), TIFF),
+    ("nrw", "Nikon NRW", caps(true, true, false), TIFF),
+    ("orf", "Olympus ORF", caps(false, true, false), ORF),
+    ("pef", "Pentax PEF", caps(true, true, false), TIFF),
+    ("raf", "Fujifilm RAF", caps(false, true, false), RAF),
+    ("rw2", "Panasonic RW2", caps(false, true, false), RW2),
+    ("rwl", "Leica RWL", caps(false, true, false), RW2),
+    ("srf", "Sony SRF", caps(true, true, false), TIFF),
+    ("srw", "Samsung SRW", caps(true, true, false), TIFF),
+    ("x3f", "Sigma X3F", caps(false, true, false), X3F),
+];
+
+/// Ordinary images, managed only when enabled.
+const IMAGE_FORMATS: &[(&str, &str, Capabilities, &[Container])] = &[
+    ("heic", "HEIF", caps(true, false, false), HEIF),
+    ("heif", "HEIF", caps(true, false, false), HEIF),
+    ("hif", "HEIF", caps(true, false, false), HEIF),
+    ("jpeg", "JPEG", caps(true, true, true), JPEG),
+    ("jpg", "JPEG", caps(true, true, true), JPEG),
+    ("tif", "TIFF", caps(true, false, false), TIFF),
+    ("tiff", "TIFF", caps(true, false, false), TIFF),
+];
+
+const TIFF: &[Container] = &[Container::Tiff];
+const CR3: &[Container] = &[Container::Cr3];
+const MRW: &[Container] = &[Container::Mrw];
+const ORF: &[Container] = &[Container::Orf];
+const RAF: &[Container] = &[Container::Raf];
+const RW2: &[Container] = &[Container::Rw2];
+const X3F: &[Container] = &[Container::X3f];
+const HEIF: &[Container] = &[Container::Heif];
+const JPEG: &[Container] = &[Container::Jpeg];
+
+/// Extra RAW extensions from configuration are tried like any other RAW.
+const CUSTOM_CAPABILITIES: Capabilities = caps(true, true, false);
+
+/// The file formats the library shows, keyed by lowercase extension.
+#[derive(Debug, Clone)]
+pub struct FormatRegistry {
+    formats: BTreeMap<String, Format>,
+}
+
+impl FormatRegistry {
+    pub fn new(extra_raw: &[String], manage_images: bool) -> Self {
+        let mut formats = BTreeMap::new();
+        let mut add = |extension: &str, name: &str, class, capabilities, containers, custom| {
+            formats.entry(extension.to_string()).or_insert_with(|| Format {
+                extension: extension.to_string(),
+                name: name.to_string(),
+                class,
+                capabilities,
+                containers,
+                custom,
+            });
+        };
+        for (extension, name, capabilities, containers) in RAW_FORMATS {
+            add(extension, name, FormatClass::Raw, *capabilities, *containers, false);
+        }
+        if manage_images {
+            for (extension, name, capabilities, containers) in IMAGE_FORMATS {
+                add(extension, name, FormatClass::Image, *capabilities, *containers, false);
+            }
+        }
+        for extension in extra_raw {
+            let extension = extension.trim().trim_start_matches('.').to_lowercase();
+            if extension.is_empty() {
+                continue;
+            }
+            let name = extension.to_uppercase();
+            add(&extension, &name, FormatClass::Raw, CUSTOM_CAPABILITIES, &[], true);
+        }
+        Self { formats }
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Format> {
+        let ext = path.extension().and_then(OsStr::to_str)?.to_lowercase();
+        self.formats.get(&ext)
+    }
+
+    /// Whether files like `path` are listed and accepted at all.
+    pub fn is_managed(&self, path: &Path) -> bool {
+        self.get(path).is_some()
+    }
+
+    pub fn is_raw(&self, path: &Path) -> bool {
+        self.get(path)
+            .is_some_and(|format| format.class == FormatClass::Raw)
+    }
+
+    /// Whether EXIF should be read from files like `path`. Files of formats
+    /// the registry does not know are tried.
+    pub fn reads_exif(&self, path: &Path) -> bool {
+        self.get(path)
+            .is_none_or(|format| format.capabilities.metadata)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Format> {
+        self.formats.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn padded(head: &[u8]) -> Vec<u8> {
+        let mut bytes = head.to_vec();
+        bytes.resize(SNIFF_LEN, 0);
+        bytes
+    }
+
+    #[test]
+    fn sniffs_raw_containers() {
+        let cases: &[(&[u8], Container)] = &[
+            (b"II*\0\x08\0\0\0", Container::Tiff),
+            (b"MM\0*\0\0\0\x08", Container::Tiff),
+            (b"II+\0\x08\0\0\0", Container::Tiff),
+            (b"MM\0+\0\x08\0\0", Container::Tiff),
+            (b"IIRO\x08\0\0\0", Container::Orf),
+            (b"IIRS\x08\0\0\0", Container::Orf),
+            (b"MMOR\0\0\0\x08", Container::Orf),
+            (b"IIU\0\x08\0\0\0", Container::Rw2),
+            (b"FUJIFILMCCD-RAW 0201", Container::Raf),
+            (b"\0MRM\0\0\0\0", Container::Mrw),
+            (b"FOVb\0\0\0\0", Container::X3f),
+            (b"\0\0\0\x18ftypcrx \0\0\0\x01", Container::Cr3),
+        ];
+        for (head, expected) in cases {
+            assert_eq!(sniff(&padded(head)), Some(*expected), "{head:?}");
+        }
+    }
+
+    #[test]
+    fn sniffs_images() {
+        assert_eq!(sniff(&padded(&[0xFF, 0xD8, 0xFF, 0xE1])), Some(Container::Jpeg));
+        assert_eq!(sniff(&padded(b"\x89PNG\r\n\x1a\n")), Some(Container::Png));
+        for brand in [&b"heic"[..], b"heix", b"mif1", b"msf1", b"hevc"] {
+            let head = [&b"\0\0\0\x18ftyp"[..], brand].concat();
+            assert_eq!(sniff(&padded(&head)), Some(Container::Heif));
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_and_short_input() {
+        assert_eq!(sniff(b""), None);
+        assert_eq!(sniff(b"II"), None);
+        assert_eq!(sniff(&padded(b"GIF89a")), None);
+        assert_eq!(sniff(&padded(b"\0\0\0\x18ftypisom")), None);
+        // A truncated ISOBMFF header has no brand to look at.
+        assert_eq!(sniff(b"\0\0\0\x18ftyp"), None);
+        assert_eq!(sniff(b"\0\0\0\x18ftypcr"), None);
+    }
+
+    #[test]
+    fn formats_accept_their_containers() {
+        let registry = FormatRegistry::new(&["kdc".to_string()], true);
+        let arw = registry.get(Path::new("a/DSC0001.ARW")).unwrap();
+        assert!(arw.accepts(Some(Container::Tiff), false));
+        assert!(!arw.accepts(Some(Container::Jpeg), false));
+        assert!(!arw.accepts(None, false));
+        assert!(!arw.accepts(Some(Container::Tiff), true));
+
+        let custom = registry.get(Path::new("x.KDC")).unwrap();
+        assert!(custom.custom);
+        assert!(custom.accepts(None, false));
+        assert!(!custom.accepts(None, true));
+
+        assert!(registry.is_raw(Path::new("x.rw2")));
+        assert!(!registry.is_raw(Path::new("x.jpg")));
+        assert!(registry.is_managed(Path::new("x.jpg")));
+        assert!(!FormatRegistry::new(&[], false).is_managed(Path::new("x.jpg")));
+    }
+
+    #[test]
+    fn exif_only_for_formats_with_metadata() {
+        let registry = FormatRegistry::new(&[], true);
+        assert!(registry.reads_exif(Path::new("a.NEF")));
+        assert!(!registry.reads_exif(Path::new("a.CR3")));
+        assert!(!registry.reads_exif(Path::new("a.raf")));
+        assert!(registry.reads_exif(Path::new("a.unknown")));
+    }
+}
@@ -0,0 +1,694 @@
+use crate::uploads::{self, Taken};
+use crate::{
+    get_root_canon, ingest, rel_to_string, sanitize_relative, to_unix_seconds, validate_file_name,
+    ApiError, ApiResult, AppState,
+};
+use anyhow::Context;
+use axum::http::StatusCode;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::either::Either;
+use tracing::{error, info, warn};
+
+/// Longest command line accepted.
+const MAX_LINE: u64 = 4096;
+/// How long a client gets to open the data connection it asked for.
+const DATA_TIMEOUT: Duration = Duration::from_secs(30);
+/// Idle control connections are closed after this long.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+/// Wrong passwords allowed on one connection before it is closed.
+const MAX_LOGIN_FAILURES: u32 = 3;
+/// How long a client gets to finish a TLS handshake.
+const TLS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A control or data connection, encrypted once the client asked for TLS.
+type Conn = Either<TcpStream, TlsStream<TcpStream>>;
+type Control = BufReader<Conn>;
+
+#[derive(Clone)]
+pub struct FtpConfig {
+    pub addr: String,
+    /// Library folder that is `/` to FTP clients.
+    pub folder: PathBuf,
+    /// Password of each user name.
+    pub users: HashMap<String, String>,
+    /// Ports to open passive data connections on; any free port when absent.
+    pub passive_ports: Option<(u16, u16)>,
+    /// Address announced in `PASV` replies, for servers behind NAT.
+    pub passive_ip: Option<Ipv4Addr>,
+    /// Certificate for explicit FTPS (`AUTH TLS`). With one, logins are
+    /// only accepted over TLS; without one the server is plain FTP.
+    pub tls: Option<TlsAcceptor>,
+}
+
+/// Parses `name:password` pairs. The password is everything after the
+/// first colon.
+pub fn parse_users(entries: &[String]) -> HashMap<String, String> {
+    entries
+        .iter()
+        .filter_map(|entry| entry.split_once(':'))
+        .filter(|(name, password)| !name.is_empty() && !password.is_empty())
+        .map(|(name, password)| (name.to_string(), password.to_string()))
+        .collect()
+}
+
+/// Parses a passive port range such as `50000-50100`.
+pub fn parse_port_range(raw: &str) -> Option<(u16, u16)> {
+    let (first, last) = raw.trim().split_once('-')?;
+    let (first, last) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+    (first > 0 && first <= last).then_some((first, last))
+}
+
+/// Loads the PEM certificate chain and private key shown to FTPS clients.
+pub fn load_tls(cert_path: &Path, key_path: &Path) -> anyhow::Result<TlsAcceptor> {
+    let open = |path: &Path| {
+        std::fs::File::open(path)
+            .map(std::io::BufReader::new)
+            .with_context(|| format!("Unable to read {}", path.display()))
+    };
+    let certs = rustls_pemfile::certs(&mut open(cert_path)?)
+        .with_context(|| format!("Invalid certificate file {}", cert_path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificate in {}", cert_path.display());
+    }
+    let key = rustls_pemfile::read_all(&mut open(key_path)?)
+        .with_context(|| format!("Invalid key file {}", key_path.display()))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(key),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("No private key in {}", key_path.display()))?;
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            certs.into_iter().map(rustls::Certificate).collect(),
+            rustls::PrivateKey(key),
+        )
+        .context("Certificate and key do not make a usable TLS configuration")?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Listens for FTP connections in the background. The server supports what
+/// uploading from cameras and scripts needs: login, passive data
+/// connections (`PASV`/`EPSV`), moving between folders, listing, creating
+/// folders and `STOR`, plus explicit FTPS (`AUTH TLS`, `PBSZ`, `PROT`) when
+/// a certificate is configured. There is no active mode (`PORT`) and no
+/// implicit FTPS.
+pub async fn spawn(state: AppState, config: FtpConfig) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(&config.addr).await?;
+    if config.tls.is_some() {
+        info!("FTP server listening on {} (explicit FTPS, logins need AUTH TLS)", config.addr);
+    } else {
+        warn!(
+            "FTP server listening on {} without TLS: passwords and files travel unencrypted. \
+             Set RAW_MANAGER_FTP_TLS_CERT and RAW_MANAGER_FTP_TLS_KEY to require FTPS.",
+            config.addr
+        );
+    }
+    let config = Arc::new(config);
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    error!("FTP accept failed: {err}");
+                    continue;
+                }
+            };
+            let session = Session {
+                state: state.clone(),
+                config: config.clone(),
+                peer,
+                local: match stream.local_addr() {
+                    Ok(local) => local,
+                    Err(_) => continue,
+                },
+                pending_user: None,
+                actor: None,
+                cwd: PathBuf::new(),
+                passive: None,
+                failures: 0,
+                secure: false,
+                buffer_size_set: false,
+                protect_data: false,
+            };
+            tokio::spawn(async move {
+                if let Err(err) = session.run(stream).await {
+                    error!("FTP session with {peer} ended: {err}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+struct Session {
+    state: AppState,
+    config: Arc<FtpConfig>,
+    peer: SocketAddr,
+    local: SocketAddr,
+    pending_user: Option<String>,
+    /// The logged-in user, named in the log for each upload.
+    actor: Option<String>,
+    /// Working directory below the FTP folder.
+    cwd: PathBuf,
+    passive: Option<TcpListener>,
+    failures: u32,
+    /// The control connection is encrypted.
+    secure: bool,
+    /// `PBSZ` was sent, which `PROT` requires.
+    buffer_size_set: bool,
+    /// Data connections are encrypted (`PROT P`).
+    protect_data: bool,
+}
+
+/// What a command leads to besides its reply.
+enum Flow {
+    Continue,
+    Close,
+    /// `AUTH TLS` was accepted: the handshake follows on the control
+    /// connection.
+    StartTls(TlsAcceptor),
+}
+
+impl Session {
+    async fn run(mut self, stream: TcpStream) -> io::Result<()> {
+        let mut control = BufReader::new(Either::Left(stream));
+        reply(&mut control, 220, "raw-manager FTP ready").await?;
+        loop {
+            let mut line = String::new();
+            let read = tokio::time::timeout(IDLE_TIMEOUT, (&mut control).take(MAX_LINE).read_line(&mut line)).await;
+            match read {
+                Ok(Ok(0)) => return Ok(()),
+                Ok(Ok(_)) if !line.ends_with('\n') => {
+                    reply(&mut control, 500, "Line too long").await?;
+                    return Ok(());
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => return Err(err),
+                Err(_) => {
+                    reply(&mut control, 421, "Idle too long, closing").await?;
+                    return Ok(());
+                }
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            let (verb, arg) = match line.split_once(' ') {
+                Some((verb, arg)) => (verb.to_ascii_uppercase(), arg.trim()),
+                None => (line.to_ascii_uppercase(), ""),
+            };
+            match self.command(&mut control, &verb, arg).await? {
+                Flow::Continue => {}
+                Flow::Close => return Ok(()),
+                Flow::StartTls(acceptor) => {
+                    // Commands sent after AUTH but before the handshake came
+                    // in the clear and must not run as if they were protected.
+                    if !control.buffer().is_empty() {
+                        return Ok(());
+                    }
+                    let Either::Left(stream) = control.into_inner() else {
+                        return Ok(());
+                    };
+                    let stream = tokio::time::timeout(TLS_TIMEOUT, acceptor.accept(stream))
+                        .await
+                        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))??;
+                    control = BufReader::new(Either::Right(stream));
+                    self.secure = true;
+                }
+            }
+        }
+    }
+
+    async fn command(&mut self, writer: &mut Control, verb: &str, arg: &str) -> io::Result<Flow> {
+        match verb {
+            "USER" | "PASS" if self.config.tls.is_some() && !self.secure => {
+                reply(writer, 530, "Use AUTH TLS first; passwords are not accepted in cleartext").await?
+            }
+            "USER" => {
+                self.actor = None;
+                self.pending_user = Some(arg.to_string());
+                reply(writer, 331, "Password required").await?;
+            }
+            "PASS" => return self.login(writer, arg).await,
+            "AUTH" => return self.auth(writer, arg).await,
+            "PBSZ" | "PROT" if !self.secure => reply(writer, 503, "Use AUTH TLS first").await?,
+            // Data over TLS is not split into blocks, so the size is always 0.
+            "PBSZ" => {
+                self.buffer_size_set = true;
+                reply(writer, 200, "PBSZ=0").await?
+            }
+            "PROT" => self.protection(writer, arg).await?,
+            "QUIT" => {
+                reply(writer, 221, "Bye").await?;
+                return Ok(Flow::Close);
+            }
+            "SYST" => reply(writer, 215, "UNIX Type: L8").await?,
+            "FEAT" => {
+                let tls = if self.config.tls.is_some() { " AUTH TLS\r\n PBSZ\r\n PROT\r\n" } else { "" };
+                let features = format!("211-Features:\r\n{tls} EPSV\r\n PASV\r\n SIZE\r\n UTF8\r\n211 End\r\n");
+                writer.write_all(features.as_bytes()).await?;
+                writer.flush().await?
+            }
+            "OPTS" if arg.eq_ignore_ascii_case("UTF8 ON") => reply(writer, 200, "Always in UTF8 mode").await?,
+            "NOOP" => reply(writer, 200, "OK").await?,
+            _ if self.actor.is_none() => reply(writer, 530, "Please log in with USER and PASS").await?,
+            "TYPE" => reply(writer, 200, "Type set").await?,
+            "MODE" if arg.eq_ignore_ascii_case("S") => reply(writer, 200, "Mode set").await?,
+            "STRU" if arg.eq_ignore_ascii_case("F") => reply(writer, 200, "Structure set").await?,
+            "PWD" | "XPWD" => {
+                let cwd = format!("\"/{}\" is the current directory", rel_to_string(&self.cwd));
+                reply(writer, 257, &cwd).await?;
+            }
+            "CWD" | "XCWD" => self.change_dir(writer, arg).await?,
+            "CDUP" | "XCUP" => self.change_dir(writer, "..").await?,
+            "MKD" | "XMKD" => self.make_dir(writer, arg).await?,
+            "PASV" => self.passive(writer, false).await?,
+            "EPSV" => self.passive(writer, true).await?,
+            "LIST" | "NLST" => self.list(writer, arg, verb == "NLST").await?,
+            "STOR" => self.store(writer, arg).await?,
+            "SIZE" => match self.existing(arg).await {
+                Ok(full) => match tokio::fs::metadata(&full).await {
+                    Ok(meta) if meta.is_file() => reply(writer, 213, &meta.len().to_string()).await?,
+                    _ => reply(writer, 550, "Not a file").await?,
+                },
+                Err(err) => reply(writer, 550, &err.message).await?,
+            },
+            _ => reply(writer, 502, "Command not implemented").await?,
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Answers `AUTH TLS`. A new security context means a new login, as
+    /// RFC 4217 asks.
+    async fn auth(&mut self, writer: &mut Control, mechanism: &str) -> io::Result<Flow> {
+        if !matches!(mechanism.to_ascii_uppercase().as_str(), "TLS" | "TLS-C" | "SSL") {
+            reply(writer, 504, "Only AUTH TLS is supported").await?;
+            return Ok(Flow::Continue);
+        }
+        let Some(acceptor) = self.config.tls.clone() else {
+            reply(writer, 502, "TLS is not configured on this server").await?;
+            return Ok(Flow::Continue);
+        };
+        if self.secure {
+            reply(writer, 503, "Already using TLS").await?;
+            return Ok(Flow::Continue);
+        }
+        self.pending_user = None;
+        self.actor = None;
+        reply(writer, 234, "Proceed with TLS negotiation").await?;
+        Ok(Flow::StartTls(acceptor))
+    }
+
+    async fn protection(&mut self, writer: &mut Control, level: &str) -> io::Result<()> {
+        if !self.buffer_size_set {
+            return reply(writer, 503, "Send PBSZ first").await;
+        }
+        match level.to_ascii_uppercase().as_str() {
+            "P" => {
+                self.protect_data = true;
+                reply(writer, 200, "Data connections are encrypted").await
+            }
+            "C" => {
+                self.protect_data = false;
+                reply(writer, 200, "Data connections are not encrypted").await
+            }
+            _ => reply(writer, 536, "Only PROT C and PROT P are supported").await,
+        }
+    }
+
+    async fn login(&mut self, writer: &mut Control, password: &str) -> io::Result<Flow> {
+        let Some(user) = self.pending_user.take() else {
+            reply(writer, 503, "Send USER first").await?;
+            return Ok(Flow::Continue);
+        };
+        if self.config.users.get(&user).is_some_and(|expected| expected == password) {
+            info!("FTP login as {user} from {}", self.peer);
+            self.actor = Some(user);
+            reply(writer, 230, "Logged in").await?;
+            return Ok(Flow::Continue);
+        }
+        self.failures += 1;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        if self.failures >= MAX_LOGIN_FAILURES {
+            reply(writer, 421, "Too many failed logins").await?;
+            return Ok(Flow::Close);
+        }
+        reply(writer, 530, "Login incorrect").await?;
+        Ok(Flow::Continue)
+    }
+
+    async fn change_dir(&mut self, writer: &mut Control, arg: &str) -> io::Result<()> {
+        let virtual_path = self.virtual_path(arg);
+        match self.existing_dir(&virtual_path).await {
+            Ok(_) => {
+                self.cwd = virtual_path;
+                reply(writer, 250, "Directory changed").await
+            }
+            Err(err) => reply(writer, 550, &err.message).await,
+        }
+    }
+
+    async fn make_dir(&mut self, writer: &mut Control, arg: &str) -> io::Result<()> {
+        let result = async {
+            let (parent, name) = self.new_entry(arg).await?;
+            let full = parent.join(&name);
+            tokio::fs::create_dir(&full)
+                .await
+                .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, format!("Unable to create {name}: {err}")))?;
+            Ok::<_, ApiError>(())
+        }
+        .await;
+        match result {
+            Ok(()) => {
+                let created = format!("\"/{}\" created", rel_to_string(&self.virtual_path(arg)));
+                reply(writer, 257, &created).await
+            }
+            Err(err) => reply(writer, 550, &err.message).await,
+        }
+    }
+
+    async fn passive(&mut self, writer: &mut Control, extended: bool) -> io::Result<()> {
+        let ip = self.local.ip();
+        let listener = match self.bind_passive(ip).await {
+            Ok(listener) => listener,
+            Err(err) => return reply(writer, 425, &format!("Unable to open a data port: {err}")).await,
+        };
+        let port = listener.local_addr()?.port();
+        self.passive = Some(listener);
+        if extended {
+            return reply(writer, 229, &format!("Entering Extended Passive Mode (|||{port}|)")).await;
+        }
+        let announced = match (self.config.passive_ip, ip) {
+            (Some(announced), _) => announced,
+            (None, IpAddr::V4(ip)) => ip,
+            (None, IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+                Some(ip) => ip,
+                None => {
+                    self.passive = None;
+                    return reply(writer, 522, "Use EPSV on IPv6").await;
+                }
+            },
+        };
+        let [a, b, c, d] = announced.octets();
+        let text = format!("Entering Passive Mode ({a},{b},{c},{d},{},{})", port >> 8, port & 0xff);
+        reply(writer, 227, &text).await
+    }
+
+    async fn bind_passive(&self, ip: IpAddr) -> io::Result<TcpListener> {
+        let Some((first, last)) = self.config.passive_ports else {
+            return TcpListener::bind(SocketAddr::new(ip, 0)).await;
+        };
+        for port in first..=last {
+            if let Ok(listener) = TcpListener::bind(SocketAddr::new(ip, port)).await {
+                return Ok(listener);
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::AddrInUse, "all passive ports are in use"))
+    }
+
+    /// Waits for the client to open the data connection it asked for with
+    /// `PASV`, and after `PROT P` for its TLS handshake. Connections from
+    /// other addresses are turned away.
+    async fn accept_data(&mut self) -> io::Result<Conn> {
+        let listener = self
+            .passive
+            .take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "Use PASV or EPSV first"))?;
+        let accepted = tokio::time::timeout(DATA_TIMEOUT, async {
+            loop {
+                let (stream, peer) = listener.accept().await?;
+                if peer.ip() == self.peer.ip() {
+                    return Ok::<_, io::Error>(stream);
+                }
+            }
+        })
+        .await;
+        let stream = accepted.map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "No data connection"))??;
+        match &self.config.tls {
+            Some(acceptor) if self.protect_data => {
+                let stream = tokio::time::timeout(TLS_TIMEOUT, acceptor.accept(stream))
+                    .await
+                    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))??;
+                Ok(Either::Right(stream))
+            }
+            _ => Ok(Either::Left(stream)),
+        }
+    }
+
+    async fn list(&mut self, writer: &mut Control, arg: &str, names_only: bool) -> io::Result<()> {
+        // Clients often pass `ls` flags such as `-la`.
+        let path = arg
+            .split_whitespace()
+            .filter(|part| !part.starts_with('-'))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let listing = match self.existing_dir(&self.virtual_path(&path)).await {
+            Ok(dir) => list_dir(&dir, names_only).await,
+            Err(err) => return reply(writer, 550, &err.message).await,
+        };
+        let listing = match listing {
+            Ok(listing) => listing,
+            Err(err) => return reply(writer, 550, &format!("Unable to read folder: {err}")).await,
+        };
+        reply(writer, 150, "Here comes the listing").await?;
+        let mut data = match self.accept_data().await {
+            Ok(data) => data,
+            Err(err) => return reply(writer, 425, &err.to_string()).await,
+        };
+        match data.write_all(listing.as_bytes()).await {
+            Ok(()) => {
+                let _ = data.shutdown().await;
+                reply(writer, 226, "Listing sent").await
+            }
+            Err(err) => reply(writer, 426, &format!("Listing failed: {err}")).await,
+        }
+    }
+
+    /// Receives a file into a hidden partial file, checks that its content
+    /// fits its extension and moves it into place under a free name.
+    async fn store(&mut self, writer: &mut Control, arg: &str) -> io::Result<()> {
+        let prepared = async {
+            let (parent, name) = self.new_entry(arg).await?;
+            let format = self
+                .state
+                .formats
+                .get(Path::new(&name))
+                .cloned()
+                .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, format!("{name} is not a supported file type")))?;
+            Ok::<_, ApiError>((parent, name, format))
+        }
+        .await;
+        let (parent, name, format) = match prepared {
+            Ok(prepared) => prepared,
+            Err(err) => return reply(writer, 553, &err.message).await,
+        };
+
+        reply(writer, 150, "Ok to send data").await?;
+        let mut data = match self.accept_data().await {
+            Ok(data) => data,
+            Err(err) => return reply(writer, 425, &err.to_string()).await,
+        };
+        let (partial, file) = match uploads::create_partial(&parent.join(&name)).await {
+            Ok(created) => created,
+            Err(err) => return reply(writer, 451, &format!("Unable to save {name}: {err}")).await,
+        };
+        let max_bytes = self.state.upload_limits.max_file_bytes;
+        let received = receive(&mut data, file, max_bytes).await;
+        // Answers the client's TLS close_notify; a no-op on plain connections.
+        let _ = data.shutdown().await;
+        let size = match received {
+            Ok(Some(size)) => size,
+            Ok(None) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return reply(writer, 552, &format!("{name} is larger than the upload limit")).await;
+            }
+            Err(err) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return reply(writer, 426, &format!("Transfer failed: {err}")).await;
+            }
+        };
+
+        match uploads::wrong_content(&format, &partial, &name, size).await {
+            Ok(None) => {}
+            Ok(Some(err)) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return reply(writer, 553, &err.message).await;
+            }
+            Err(err) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return reply(writer, 451, &format!("Unable to read {name}: {}", err.message)).await;
+            }
+        }
+
+        // Cameras restart their numbering, so an existing name gets the
+        // next free `name-N.ext` rather than being overwritten.
+        let stored = async {
+            let root_canon = get_root_canon(&self.state).await?;
+            let dest_rel = parent
+                .strip_prefix(&root_canon)
+                .map_err(|_| ApiError::new(StatusCode::FORBIDDEN, "Invalid path"))?;
+            uploads::store(&self.state, &partial, dest_rel, &parent, &name, Taken::NextFree).await
+        }
+        .await;
+        let rel = match stored {
+            Ok(rel) => rel,
+            Err(err) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                let code = if err.status == StatusCode::CONFLICT { 553 } else { 451 };
+                return reply(writer, code, &err.message).await;
+            }
+        };
+        info!(
+            "FTP upload of {rel} ({size} bytes) by {}",
+            self.actor.as_deref().unwrap_or_default()
+        );
+        let stored_name = Path::new(&rel).file_name().unwrap_or_default().to_string_lossy();
+        reply(writer, 226, &format!("Stored as {stored_name}")).await
+    }
+
+    /// The path below the FTP folder an argument names: absolute paths start
+    /// at the FTP folder, others at the working directory. `..` never goes
+    /// above the FTP folder.
+    fn virtual_path(&self, arg: &str) -> PathBuf {
+        let mut path = if arg.starts_with('/') {
+            PathBuf::new()
+        } else {
+            self.cwd.clone()
+        };
+        for part in arg.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    path.pop();
+                }
+                name => path.push(name),
+            }
+        }
+        path
+    }
+
+    /// Canonical path of an existing file or folder, checked like any
+    /// library path and kept inside the FTP folder.
+    async fn existing(&self, arg: &str) -> ApiResult<PathBuf> {
+        self.resolve(&self.virtual_path(arg)).await
+    }
+
+    async fn existing_dir(&self, virtual_path: &Path) -> ApiResult<PathBuf> {
+        let full = self.resolve(virtual_path).await?;
+        match tokio::fs::metadata(&full).await {
+            Ok(meta) if meta.is_dir() => Ok(full),
+            _ => Err(ApiError::new(StatusCode::BAD_REQUEST, "Not a folder")),
+        }
+    }
+
+    async fn resolve(&self, virtual_path: &Path) -> ApiResult<PathBuf> {
+        let root_canon = get_root_canon(&self.state).await?;
+        let folder_canon = tokio::fs::canonicalize(root_canon.join(&self.config.folder))
+            .await
+            .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "FTP folder not found"))?;
+        let rel = sanitize_relative(&rel_to_string(&self.config.folder.join(virtual_path)))?;
+        let full = tokio::fs::canonicalize(root_canon.join(&rel))
+            .await
+            .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Path not found"))?;
+        if !full.starts_with(&folder_canon) {
+            return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+        }
+        Ok(full)
+    }
+
+    /// The existing parent folder and the checked name of a file or folder
+    /// about to be created.
+    async fn new_entry(&self, arg: &str) -> ApiResult<(PathBuf, String)> {
+        let virtual_path = self.virtual_path(arg);
+        let name = virtual_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Missing name"))?;
+        let name = validate_file_name(&name)?;
+        let parent = self
+            .existing_dir(virtual_path.parent().unwrap_or_else(|| Path::new("")))
+            .await?;
+        Ok((parent, name))
+    }
+}
+
+async fn reply(writer: &mut Control, code: u16, text: &str) -> io::Result<()> {
+    writer.write_all(format!("{code} {text}\r\n").as_bytes()).await?;
+    writer.flush().await
+}
+
+/// Streams a data connection into the partial `file` and syncs it. `None`
+/// when the file grows past `max_bytes`.
+async fn receive(
+    data: &mut (impl AsyncRead + Unpin),
+    mut file: tokio::fs::File,
+    max_bytes: Option<u64>,
+) -> io::Result<Option<u64>> {
+    let mut buffer = vec![0; 256 * 1024];
+    let mut size = 0u64;
+    loop {
+        let read = data.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        size += read as u64;
+        if max_bytes.is_some_and(|max| size > max) {
+            return Ok(None);
+        }
+        file.write_all(&buffer[..read]).await?;
+    }
+    file.sync_all().await?;
+    Ok(Some(size))
+}
+
+/// A folder listing in the `ls -l` format clients expect, or bare names
+/// for `NLST`. Names the API hides (the trash, partial files) are left out.
+async fn list_dir(dir: &Path, names_only: bool) -> io::Result<String> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    let mut lines = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if validate_file_name(&name).is_err() || name.starts_with('.') {
+            continue;
+        }
+        if names_only {
+            lines.push(format!("{name}\r\n"));
+            continue;
+        }
+        let Ok(meta) = entry.metadata().await else {
+            continue;
+        };
+        // `2024-05-01 14:03:22` becomes `May 01 2024`.
+        let modified = ingest::unix_to_taken(to_unix_seconds(meta.modified().ok()));
+        let mut date = modified.split(['-', ' ']);
+        let (year, month, day) = (
+            date.next().unwrap_or_default(),
+            date.next().and_then(|month| month.parse::<usize>().ok()).unwrap_or(1),
+            date.next().unwrap_or_default(),
+        );
+        let month = MONTHS.get(month.saturating_sub(1)).unwrap_or(&"Jan");
+        let kind = if meta.is_dir() { "drwxr-xr-x" } else { "-rw-r--r--" };
+        lines.push(format!(
+            "{kind} 1 raw raw {:>12} {month} {day} {year} {name}\r\n",
+            meta.len()
+        ));
+    }
+    lines.sort();
+    Ok(lines.concat())
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
@@ -0,0 +1,47 @@
+use crate::db::FileMeta;
+use serde_json::Value;
+
+/// Catalog fields whose edits are recorded and can be undone.
+pub const FIELDS: [&str; 7] = ["user_rating", "tags", "color_label", "flag", "title", "caption", "notes"];
+
+pub fn field_value(meta: &FileMeta, field: &str) -> Value {
+    match field {
+        "user_rating" => meta.user_rating.into(),
+        "tags" => meta.tags.clone().into(),
+        "color_label" => meta.color_label.clone().into(),
+        "flag" => meta.flag.clone().into(),
+        "title" => meta.title.clone().into(),
+        "caption" => meta.caption.clone().into(),
+        "notes" => meta.notes.clone().into(),
+        _ => Value::Null,
+    }
+}
+
+/// Writes a value recorded by [`field_value`] back into `meta`. Values of the
+/// wrong shape clear the field rather than failing the whole undo.
+pub fn set_field(meta: &mut FileMeta, field: &str, value: &Value) {
+    let text = || value.as_str().map(str::to_string);
+    match field {
+        "user_rating" => meta.user_rating = value.as_i64().map(|rating| rating as i32),
+        "tags" => meta.tags = serde_json::from_value(value.clone()).unwrap_or_default(),
+        "color_label" => meta.color_label = text(),
+        "flag" => meta.flag = text(),
+        "title" => meta.title = text(),
+        "caption" => meta.caption = text(),
+        "notes" => meta.notes = text(),
+        _ => {}
+    }
+}
+
+/// Lists every recorded field that differs between the two versions of a row
+/// as `(field, old value, new value)`.
+pub fn diff(before: &FileMeta, after: &FileMeta) -> Vec<(&'static str, Value, Value)> {
+    FIELDS
+        .iter()
+        .filter_map(|field| {
+            let old = field_value(before, field);
+            let new = field_value(after, field);
+            (old != new).then_some((*field, old, new))
+        })
+        .collect()
+}
@@ -0,0 +1,235 @@
+use crate::companions::{self, CompanionKind};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Folder template used when neither the request nor
+/// `RAW_MANAGER_INGEST_TEMPLATE` gives one: `2024/2024-05-01_wedding`.
+pub const DEFAULT_TEMPLATE: &str = "{taken:%Y}/{taken:%Y-%m-%d}_{shoot}";
+
+/// Settings of an ingest job, stored with it so an interrupted ingest
+/// continues the same way.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IngestOptions {
+    /// Library folder the dated folders are created in.
+    pub destination: String,
+    pub template: String,
+    pub shoot: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub copyright: Option<String>,
+    /// Who started the ingest, for the edit history.
+    pub actor: String,
+    /// The hot folder that started the ingest, if any.
+    #[serde(default)]
+    pub hot_folder: Option<i64>,
+    /// Remove each source file once it is safely in the library, or
+    /// skipped because the library already has it.
+    #[serde(default)]
+    pub move_source: bool,
+}
+
+/// Seconds between hot folder polls unless `RAW_MANAGER_HOT_FOLDER_INTERVAL_SECS`
+/// says otherwise.
+pub const DEFAULT_HOT_FOLDER_INTERVAL_SECS: u64 = 15;
+
+/// How long a hot folder file must go unmodified before it is picked up,
+/// unless `RAW_MANAGER_HOT_FOLDER_SETTLE_SECS` says otherwise.
+pub const DEFAULT_HOT_FOLDER_SETTLE_SECS: i64 = 30;
+
+/// Remembers the size and mtime of each file in a hot folder from the last
+/// poll. A file is ready once both held still between two polls and it has
+/// not been written to for the settle time, so copies still arriving over
+/// the network or from a card reader are left alone.
+///
+/// Files an ingest job failed on are held back until their size or mtime
+/// changes, so a file that cannot be ingested does not start a new failing
+/// job on every poll.
+#[derive(Debug, Default)]
+pub struct Settling {
+    seen: HashMap<PathBuf, (u64, i64)>,
+    failed: HashMap<PathBuf, (u64, i64)>,
+    failed_job: Option<i64>,
+}
+
+impl Settling {
+    /// Holds back the files of failed job `job_id` that are still in the
+    /// folder as listed in `files`. Each job is only counted once. Returns
+    /// how many files were held back.
+    pub fn record_failure(&mut self, job_id: i64, sources: &[PathBuf], files: &[(PathBuf, u64, i64)]) -> usize {
+        if self.failed_job == Some(job_id) {
+            return 0;
+        }
+        self.failed_job = Some(job_id);
+        let mut held = 0;
+        for (path, size, modified) in files {
+            if sources.contains(path) {
+                self.failed.insert(path.clone(), (*size, *modified));
+                held += 1;
+            }
+        }
+        held
+    }
+
+    /// Takes the folder's current `(path, size, mtime)` listing and returns
+    /// the files that are ready, with their sizes.
+    pub fn observe(&mut self, files: Vec<(PathBuf, u64, i64)>, now: i64, settle_secs: i64) -> Vec<(PathBuf, u64)> {
+        let mut ready = Vec::new();
+        let mut seen = HashMap::with_capacity(files.len());
+        for (path, size, modified) in files {
+            let held = self.failed.get(&path) == Some(&(size, modified));
+            if !held && self.seen.get(&path) == Some(&(size, modified)) && now - modified >= settle_secs {
+                ready.push((path.clone(), size));
+            }
+            seen.insert(path, (size, modified));
+        }
+        self.failed.retain(|path, state| seen.get(path) == Some(state));
+        self.seen = seen;
+        ready
+    }
+}
+
+/// Splits a rendered folder template into folder names. Separators left
+/// dangling by an empty placeholder (`2024-05-01_` without a shoot name) are
+/// trimmed, and empty levels dropped.
+pub fn folder_parts(rendered: &str) -> Vec<String> {
+    rendered
+        .split('/')
+        .map(|part| part.trim().trim_matches(|c| c == '_' || c == '-').trim())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_string())
+        .collect()
+}
+
+/// A unix time as an EXIF-style `2024-05-01 14:03:22` (UTC), for files
+/// without a capture date.
+pub fn unix_to_taken(secs: i64) -> String {
+    let days = secs.div_euclid(86_400);
+    let time = secs.rem_euclid(86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Order of files within one shot: sidecars are copied before the image
+/// they describe so the copyright and tags written for the image land in
+/// the copied sidecar, then the RAW, whose date places the shot.
+pub fn copy_rank(kind: CompanionKind) -> u8 {
+    match kind {
+        CompanionKind::Sidecar => 0,
+        CompanionKind::Raw => 1,
+        _ => 2,
+    }
+}
+
+/// Key that files of one shot in one source folder share.
+pub fn shot_key(source: &str) -> (String, String) {
+    let path = Path::new(source);
+    let dir = path
+        .parent()
+        .map(|parent| parent.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    (dir, companions::group_key(&name).to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unix_to_taken_handles_leap_years() {
+        assert_eq!(unix_to_taken(0), "1970-01-01 00:00:00");
+        assert_eq!(unix_to_taken(1_709_210_096), "2024-02-29 12:34:56");
+        assert_eq!(unix_to_taken(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(unix_to_taken(951_868_800), "2000-03-01 00:00:00");
+        assert_eq!(unix_to_taken(4_107_542_400), "2100-03-01 00:00:00");
+    }
+
+    #[test]
+    fn unix_to_taken_handles_dates_before_1970() {
+        assert_eq!(unix_to_taken(-1), "1969-12-31 23:59:59");
+        assert_eq!(unix_to_taken(-310_500_000), "1960-02-29 06:00:00");
+        assert_eq!(unix_to_taken(-2_203_891_200), "1900-03-01 00:00:00");
+    }
+
+    #[test]
+    fn folder_parts_trims_dangling_separators() {
+        assert_eq!(folder_parts("2024/2024-05-01_wedding"), vec!["2024", "2024-05-01_wedding"]);
+        assert_eq!(folder_parts("2024/2024-05-01_"), vec!["2024", "2024-05-01"]);
+        assert_eq!(folder_parts("/2024//_/ a b /"), vec!["2024", "a b"]);
+        assert!(folder_parts("").is_empty());
+    }
+
+    fn listing(files: &[(&str, u64, i64)]) -> Vec<(PathBuf, u64, i64)> {
+        files
+            .iter()
+            .map(|(path, size, modified)| (PathBuf::from(path), *size, *modified))
+            .collect()
+    }
+
+    #[test]
+    fn files_are_ready_once_they_hold_still_for_the_settle_time() {
+        let mut settling = Settling::default();
+        // Unknown until seen twice the same.
+        assert!(settling.observe(listing(&[("a", 10, 100)]), 200, 30).is_empty());
+        // Still growing.
+        assert!(settling.observe(listing(&[("a", 20, 110)]), 200, 30).is_empty());
+        // Unchanged, but written to 20 seconds ago.
+        assert!(settling.observe(listing(&[("a", 20, 110)]), 130, 30).is_empty());
+        assert_eq!(
+            settling.observe(listing(&[("a", 20, 110)]), 140, 30),
+            vec![(PathBuf::from("a"), 20)]
+        );
+    }
+
+    #[test]
+    fn failed_files_are_held_back_until_they_change() {
+        let mut settling = Settling::default();
+        let files = listing(&[("a", 10, 100), ("b", 10, 100)]);
+        settling.observe(files.clone(), 1000, 30);
+        assert_eq!(settling.observe(files.clone(), 1000, 30).len(), 2);
+
+        assert_eq!(settling.record_failure(7, &[PathBuf::from("a")], &files), 1);
+        // The same job is only counted once.
+        assert_eq!(settling.record_failure(7, &[PathBuf::from("a")], &files), 0);
+        assert_eq!(
+            settling.observe(files.clone(), 1000, 30),
+            vec![(PathBuf::from("b"), 10)]
+        );
+
+        // Replaced with a new copy: released once it settles again.
+        let changed = listing(&[("a", 12, 200), ("b", 10, 100)]);
+        assert_eq!(settling.observe(changed.clone(), 1000, 30), vec![(PathBuf::from("b"), 10)]);
+        assert_eq!(settling.observe(changed, 1000, 30).len(), 2);
+    }
+
+    #[test]
+    fn a_failed_file_that_comes_back_unchanged_is_picked_up() {
+        let mut settling = Settling::default();
+        let files = listing(&[("a", 10, 100)]);
+        settling.observe(files.clone(), 1000, 30);
+        settling.record_failure(3, &[PathBuf::from("a")], &files);
+        assert!(settling.observe(files.clone(), 1000, 30).is_empty());
+        // Taken out of the folder and put back as it was.
+        assert!(settling.observe(Vec::new(), 1000, 30).is_empty());
+        assert!(settling.observe(files.clone(), 1000, 30).is_empty());
+        assert_eq!(settling.observe(files, 1000, 30), vec![(PathBuf::from("a"), 10)]);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::db::{self, JournalStep};
+use anyhow::Result;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, info};
+
+const STAGING_MARKER: &str = ".raw-manager-delete-";
+const RENAME_MARKER: &str = ".raw-manager-rename-";
+
+/// Where a path waits while its delete is in flight: a hidden name in the
+/// same folder, so staging is a plain rename that can be undone.
+pub fn staging_path(path: &Path, token: &str) -> PathBuf {
+    hidden_path(path, STAGING_MARKER, token)
+}
+
+/// Where a file waits during a batch rename while the name it takes still
+/// belongs to another file of the batch, e.g. when two files swap names.
+pub fn rename_path(path: &Path, token: &str) -> PathBuf {
+    hidden_path(path, RENAME_MARKER, token)
+}
+
+fn hidden_path(path: &Path, marker: &str, token: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}{marker}{token}"))
+}
+
+/// A token that keeps staged names from different requests apart.
+pub fn staging_token() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("{nanos:x}")
+}
+
+pub fn is_staged(name: &str) -> bool {
+    name.starts_with('.') && (name.contains(STAGING_MARKER) || name.contains(RENAME_MARKER))
+}
+
+/// Performs every rename in order. When one fails, the ones already done are
+/// put back before the error is returned, so the request changes nothing.
+pub async fn apply(steps: &[JournalStep]) -> io::Result<()> {
+    for (index, step) in steps.iter().enumerate() {
+        if let Err(err) = tokio::fs::rename(&step.source, &step.target).await {
+            rollback(&steps[..index]).await;
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Undoes renames, newest first. Steps that never happened (or were already
+/// undone) are skipped.
+pub async fn rollback(steps: &[JournalStep]) {
+    for step in steps.iter().rev() {
+        let target_exists = tokio::fs::symlink_metadata(&step.target).await.is_ok();
+        let source_exists = tokio::fs::symlink_metadata(&step.source).await.is_ok();
+        if !target_exists || source_exists {
+            continue;
+        }
+        if let Err(err) = tokio::fs::rename(&step.target, &step.source).await {
+            error!(
+                "Failed to restore {} from {}: {err}",
+                step.source.display(),
+                step.target.display()
+            );
+        }
+    }
+}
+
+/// Removes staged deletes for good once the catalog no longer references them.
+pub async fn purge(steps: &[JournalStep]) {
+    for step in steps {
+        let result = if step.is_dir {
+            tokio::fs::remove_dir_all(&step.target).await
+        } else {
+            tokio::fs::remove_file(&step.target).await
+        };
+        match result {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => error!("Failed to remove {}: {err}", step.target.display()),
+        }
+    }
+}
+
+/// Finishes whatever a previous run left in the journal. Batches whose
+/// catalog update never committed are rolled back on disk; committed deletes
+/// get their staged files removed.
+pub async fn recover(pool: &SqlitePool) -> Result<()> {
+    let mut batches: BTreeMap<i64, (String, bool, Vec<JournalStep>)> = BTreeMap::new();
+    for entry in db::list_journal(pool).await? {
+        batches
+            .entry(entry.batch)
+            .or_insert_with(|| (entry.op.clone(), entry.committed, Vec::new()))
+            .2
+            .push(entry.step);
+    }
+
+    for (batch, (op, committed, steps)) in batches {
+        if committed {
+            purge(&steps).await;
+            info!("Finished interrupted {op} ({} paths)", steps.len());
+        } else {
+            rollback(&steps).await;
+            info!("Rolled back interrupted {op} ({} paths)", steps.len());
+        }
+        db::journal_clear(pool, batch).await?;
+    }
+    Ok(())
+}
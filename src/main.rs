@@ -1,25 +1,44 @@
+mod catalog;
+mod companions;
 mod db;
+mod formats;
+mod ftp;
+mod history;
+mod ingest;
+mod journal;
 mod metadata;
-
+mod migrations;
+mod rename;
+mod tags;
+mod transfer;
+mod trash;
+mod uploads;
+
+use anyhow::Context;
 use axum::{
     body::Body,
-    extract::{Multipart, Query, State},
-    http::{header, StatusCode},
+    async_trait,
+    extract::{multipart::Field, DefaultBodyLimit, FromRequestParts, Multipart, Query, State},
+    http::{header, request::Parts, StatusCode},
     response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
+use bytes::Bytes;
 use db::FileMeta;
+use futures_util::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::SqlitePool;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
 use std::env;
 use std::ffi::OsStr;
 use std::io;
 use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::RwLock;
 use tokio_util::io::ReaderStream;
@@ -34,6 +53,17 @@ struct AppState {
     library_root: Arc<RwLock<Option<PathBuf>>>,
     library_root_canon: Arc<RwLock<Option<PathBuf>>>,
     preview_dir: PathBuf,
+    xmp_writeback: bool,
+    companion_previews: bool,
+    trash_retention_days: Option<i64>,
+    formats: Arc<formats::FormatRegistry>,
+    upload_limits: uploads::UploadLimits,
+    /// Upload sessions with a chunk being written right now.
+    busy_uploads: Arc<std::sync::Mutex<HashSet<String>>>,
+    /// Folders ingest may read from, such as card mount points, as
+    /// configured. They are resolved per request since cards come and go.
+    ingest_sources: Arc<Vec<String>>,
+    ingest_template: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -42,6 +72,11 @@ struct ConfigResponse {
     library_root: Option<String>,
 }
 
+#[derive(Debug, Serialize)]
+struct FormatsResponse {
+    formats: Vec<formats::Format>,
+}
+
 #[derive(Debug, Deserialize)]
 struct BrowseQuery {
     path: Option<String>,
@@ -51,6 +86,14 @@ struct BrowseQuery {
 struct BrowseResponse {
     path: String,
     entries: Vec<BrowseEntry>,
+    saved_searches: Vec<SavedSearchSummary>,
+}
+
+#[derive(Debug, Serialize)]
+struct SavedSearchSummary {
+    #[serde(flatten)]
+    search: db::SavedSearch,
+    count: usize,
 }
 
 #[derive(Debug, Serialize)]
@@ -67,7 +110,19 @@ struct BrowseEntry {
     gps_lon: Option<f64>,
     taken_at: Option<String>,
     orientation: Option<i32>,
+    color_label: Option<String>,
+    flag: Option<String>,
     needs_scan: bool,
+    companions: Vec<CompanionFile>,
+}
+
+/// Another file of the same shot, such as the JPEG taken alongside a RAW or
+/// its XMP sidecar.
+#[derive(Debug, Serialize)]
+struct CompanionFile {
+    name: String,
+    path: String,
+    kind: companions::CompanionKind,
 }
 
 #[derive(Debug, Deserialize)]
@@ -92,14 +147,33 @@ struct FileMetaResponse {
     gps_lon: Option<f64>,
     taken_at: Option<String>,
     orientation: Option<i32>,
+    camera_make: Option<String>,
+    camera_model: Option<String>,
+    color_label: Option<String>,
+    flag: Option<String>,
+    title: Option<String>,
+    caption: Option<String>,
+    notes: Option<String>,
     file_size: i64,
     last_modified: i64,
+    companions: Vec<CompanionFile>,
+    content_mismatch: Option<ContentMismatch>,
+}
+
+/// Set when a file's content does not match its extension, such as a JPEG
+/// saved as `.NEF` or an empty `.ARW`.
+#[derive(Debug, Serialize)]
+struct ContentMismatch {
+    expected: &'static [formats::Container],
+    detected: Option<formats::Container>,
+    empty: bool,
 }
 
 #[derive(Debug, Deserialize)]
 struct RatingRequest {
     path: String,
     rating: Option<i32>,
+    companions: Option<bool>,
 }
 
 #[derive(Debug, Serialize)]
@@ -111,6 +185,56 @@ struct RatingResponse {
 struct TagsRequest {
     path: String,
     tags: Vec<String>,
+    companions: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TextRequest {
+    path: String,
+    title: Option<String>,
+    caption: Option<String>,
+    notes: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct TextResponse {
+    title: Option<String>,
+    caption: Option<String>,
+    notes: Option<String>,
+    /// Set when the XMP sidecar could not be updated; the catalog text
+    /// changed all the same.
+    writeback_errors: Vec<WritebackError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchRequest {
+    paths: Vec<String>,
+    operations: Vec<BatchOperation>,
+    companions: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum BatchOperation {
+    SetRating { rating: Option<i32> },
+    AddTags { tags: Vec<String> },
+    RemoveTags { tags: Vec<String> },
+    SetLabel { label: Option<String> },
+    SetFlag { flag: Option<String> },
+}
+
+#[derive(Debug, Serialize)]
+struct BatchResponse {
+    updated: usize,
+    results: Vec<BatchResult>,
+    writeback_errors: Vec<WritebackError>,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchResult {
+    path: String,
+    success: bool,
+    error: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -121,712 +245,4548 @@ struct TagsResponse {
 #[derive(Debug, Serialize)]
 struct TagsListResponse {
     tags: Vec<String>,
+    usage: Vec<tags::TagUsage>,
+    tree: Vec<tags::TagNode>,
 }
 
 #[derive(Debug, Deserialize)]
-struct MkdirRequest {
-    path: String,
+struct TagSuggestQuery {
+    prefix: Option<String>,
+    path: Option<String>,
+    limit: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
-struct DeleteRequest {
-    paths: Vec<String>,
-    recursive: bool,
+#[derive(Debug, Serialize)]
+struct TagSuggestResponse {
+    suggestions: Vec<tags::TagSuggestion>,
 }
 
 #[derive(Debug, Deserialize)]
-struct MoveRequest {
-    paths: Vec<String>,
-    destination: String,
+struct TagRenameRequest {
+    from: String,
+    to: String,
 }
 
-#[derive(Debug, Serialize)]
-struct FsResponse {
-    success: bool,
+#[derive(Debug, Deserialize)]
+struct TagMergeRequest {
+    sources: Vec<String>,
+    target: String,
 }
 
 #[derive(Debug, Deserialize)]
-struct UploadQuery {
-    path: Option<String>,
+struct TagDeleteRequest {
+    tag: String,
 }
 
 #[derive(Debug, Serialize)]
-struct ErrorResponse {
-    error: String,
+struct TagsUpdateResponse {
+    files_changed: usize,
+    /// Files whose XMP sidecar could not be updated; their catalog tags
+    /// changed all the same.
+    writeback_errors: Vec<WritebackError>,
 }
 
-#[derive(Debug)]
-struct ApiError {
-    status: StatusCode,
-    message: String,
+#[derive(Debug, Serialize)]
+struct WritebackError {
+    path: String,
+    error: String,
 }
 
-impl ApiError {
-    fn new(status: StatusCode, message: impl Into<String>) -> Self {
-        Self {
-            status,
-            message: message.into(),
-        }
-    }
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    tag: Option<String>,
+    min_rating: Option<i32>,
+    taken_after: Option<String>,
+    taken_before: Option<String>,
+    taken_within_days: Option<i64>,
+    camera: Option<String>,
+    folder: Option<String>,
+    color_label: Option<String>,
+    flag: Option<String>,
 }
 
-impl IntoResponse for ApiError {
-    fn into_response(self) -> Response {
-        let body = Json(ErrorResponse {
-            error: self.message,
-        });
-        (self.status, body).into_response()
-    }
+#[derive(Debug, Serialize)]
+struct SearchResponse {
+    entries: Vec<BrowseEntry>,
 }
 
-type ApiResult<T> = Result<T, ApiError>;
+#[derive(Debug, Serialize)]
+struct SavedSearchesResponse {
+    searches: Vec<SavedSearchSummary>,
+}
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "raw_manager=info,tower_http=info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+#[derive(Debug, Deserialize)]
+struct SavedSearchQuery {
+    id: i64,
+}
 
-    dotenvy::dotenv().ok();
+#[derive(Debug, Deserialize)]
+struct SavedSearchCreateRequest {
+    name: String,
+    filter: db::SearchFilter,
+}
 
-    let data_dir = env::var("RAW_MANAGER_DATA_DIR").unwrap_or_else(|_| "data".to_string());
-    let data_dir = PathBuf::from(data_dir);
-    tokio::fs::create_dir_all(&data_dir).await?;
-    let preview_dir = data_dir.join("previews");
-    tokio::fs::create_dir_all(&preview_dir).await?;
+#[derive(Debug, Deserialize)]
+struct SavedSearchUpdateRequest {
+    id: i64,
+    name: Option<String>,
+    filter: Option<db::SearchFilter>,
+}
 
-    let db_path = data_dir.join("raw-manager.db");
-    let options = SqliteConnectOptions::new()
-        .filename(db_path)
-        .create_if_missing(true);
-    let pool = SqlitePool::connect_with(options).await?;
-    db::init_db(&pool).await?;
+#[derive(Debug, Serialize)]
+struct SavedSearchResponse {
+    search: db::SavedSearch,
+}
 
-    let configured_root = read_library_root_env();
-    let (library_root, library_root_canon) = if let Some(root) = configured_root.clone() {
-        let root_path = PathBuf::from(&root);
-        match tokio::fs::canonicalize(&root_path).await {
-            Ok(canon) => (Some(root_path), Some(canon)),
-            Err(err) => {
-                error!("Failed to canonicalize configured root: {err}");
-                (None, None)
-            }
-        }
-    } else {
-        (None, None)
-    };
+#[derive(Debug, Serialize)]
+struct SavedSearchResultsResponse {
+    search: db::SavedSearch,
+    entries: Vec<BrowseEntry>,
+}
 
-    let state = AppState {
-        pool,
-        library_root: Arc::new(RwLock::new(library_root)),
-        library_root_canon: Arc::new(RwLock::new(library_root_canon)),
-        preview_dir,
-    };
+#[derive(Debug, Serialize)]
+struct AlbumsResponse {
+    albums: Vec<db::Album>,
+}
 
-    let api = Router::new()
-        .route("/config", get(get_config))
-        .route("/browse", get(browse))
-        .route("/file/metadata", get(file_metadata))
-        .route("/file/preview", get(file_preview))
-        .route("/file/download", get(file_download))
-        .route("/file/rating", post(set_rating))
-        .route("/file/tags", post(set_tags))
-        .route("/tags", get(list_tags))
-        .route("/fs/mkdir", post(fs_mkdir))
-        .route("/fs/delete", post(fs_delete))
-        .route("/fs/move", post(fs_move))
-        .route("/fs/upload", post(fs_upload))
-        .route("/health", get(health));
+#[derive(Debug, Deserialize)]
+struct AlbumQuery {
+    id: i64,
+}
 
-    let static_service = ServeDir::new("static").fallback(ServeFile::new("static/index.html"));
+#[derive(Debug, Deserialize)]
+struct AlbumCreateRequest {
+    name: String,
+    description: Option<String>,
+}
 
-    let app = Router::new()
-        .nest("/api", api)
-        .fallback_service(static_service)
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
+#[derive(Debug, Deserialize)]
+struct AlbumUpdateRequest {
+    id: i64,
+    name: Option<String>,
+    description: Option<String>,
+}
 
-    let addr = env::var("RAW_MANAGER_ADDR").unwrap_or_else(|_| "0.0.0.0:1234".to_string());
-    info!("Raw Manager listening on {addr}");
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
-    Ok(())
+#[derive(Debug, Deserialize)]
+struct AlbumItemsRequest {
+    id: i64,
+    paths: Vec<String>,
 }
 
-async fn health() -> &'static str {
-    "ok"
+#[derive(Debug, Serialize)]
+struct AlbumResponse {
+    album: db::Album,
 }
 
-async fn get_config(State(state): State<AppState>) -> ApiResult<Json<ConfigResponse>> {
-    let root = state.library_root.read().await;
-    let value = root.as_ref().and_then(|path| path.to_str().map(|s| s.to_string()));
-    Ok(Json(ConfigResponse {
-        configured: value.is_some(),
-        library_root: value,
-    }))
+#[derive(Debug, Serialize)]
+struct AlbumContentsResponse {
+    album: db::Album,
+    entries: Vec<BrowseEntry>,
 }
 
-async fn browse(
-    State(state): State<AppState>,
-    Query(query): Query<BrowseQuery>,
-) -> ApiResult<Json<BrowseResponse>> {
-    let rel_path = query.path.unwrap_or_default();
-    let root_canon = get_root_canon(&state).await?;
+#[derive(Debug, Serialize)]
+struct AlbumItemsResponse {
+    album: db::Album,
+    changed: usize,
+    results: Vec<BatchResult>,
+}
 
-    let rel = sanitize_relative(&rel_path)?;
-    let full_path = root_canon.join(&rel);
-    let full_canon = tokio::fs::canonicalize(&full_path)
-        .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Path not found"))?;
+#[derive(Debug, Deserialize)]
+struct CatalogExportQuery {
+    format: Option<String>,
+}
 
-    if !full_canon.starts_with(&root_canon) {
-        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
-    }
+#[derive(Debug, Deserialize)]
+struct CatalogImportQuery {
+    format: Option<String>,
+    dry_run: Option<bool>,
+    strategy: Option<catalog::MergeStrategy>,
+    remap_from: Option<String>,
+    remap_to: Option<String>,
+}
 
-    let mut entries = Vec::new();
-    let mut read_dir = tokio::fs::read_dir(&full_canon)
-        .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Folder not found"))?;
+#[derive(Debug, Serialize)]
+struct CatalogImportResponse {
+    dry_run: bool,
+    strategy: catalog::MergeStrategy,
+    created: usize,
+    updated: usize,
+    unchanged: usize,
+    skipped: usize,
+    failed: usize,
+    results: Vec<CatalogImportResult>,
+    writeback_errors: Vec<WritebackError>,
+}
 
-    while let Some(entry) = read_dir
-        .next_entry()
-        .await
-        .map_err(|_| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Read error"))?
-    {
-        let path = entry.path();
-        let name = entry
-            .file_name()
-            .to_string_lossy()
-            .to_string();
-        let file_type = entry
-            .file_type()
-            .await
-            .map_err(|_| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Read error"))?;
+#[derive(Debug, Serialize)]
+struct CatalogImportResult {
+    line: usize,
+    path: String,
+    action: Option<db::ImportAction>,
+    error: Option<String>,
+}
 
-        if file_type.is_dir() {
-            let rel = path
-                .strip_prefix(&root_canon)
-                .unwrap_or(&path)
-                .to_string_lossy()
-                .to_string();
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    limit: Option<i64>,
+}
 
-            entries.push(BrowseEntry {
-                name,
-                path: rel,
-                kind: "dir".to_string(),
-                size: None,
-                modified: None,
-                camera_rating: None,
-                user_rating: None,
-                tags: Vec::new(),
-                gps_lat: None,
-                gps_lon: None,
-                taken_at: None,
-                orientation: None,
-                needs_scan: false,
-            });
-            continue;
-        }
+#[derive(Debug, Deserialize)]
+struct FileHistoryQuery {
+    path: String,
+    limit: Option<i64>,
+}
 
-        if !file_type.is_file() {
-            continue;
-        }
+#[derive(Debug, Serialize)]
+struct HistoryResponse {
+    operations: Vec<db::HistoryOperation>,
+}
 
-        if !is_supported_raw(&path) {
+#[derive(Debug, Serialize)]
+struct FileHistoryResponse {
+    path: String,
+    entries: Vec<db::HistoryEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UndoRequest {
+    count: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct UndoResponse {
+    undone: Vec<i64>,
+    skipped: Vec<i64>,
+    files_restored: usize,
+    conflicts: Vec<db::HistoryEntry>,
+    writeback_errors: Vec<WritebackError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RevertRequest {
+    id: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct RevertResponse {
+    path: String,
+    field: String,
+    value: serde_json::Value,
+    writeback_errors: Vec<WritebackError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MkdirRequest {
+    path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteRequest {
+    paths: Vec<String>,
+    recursive: bool,
+    /// Also delete each file's sidecars and RAW/JPEG partner (default true).
+    companions: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MoveRequest {
+    paths: Vec<String>,
+    destination: String,
+    /// Also move each file's sidecars and RAW/JPEG partner (default true).
+    companions: Option<bool>,
+}
+
+/// What a copy does when something already has the destination name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CopyConflict {
+    Skip,
+    /// Copy under the first free `name-N.ext`.
+    Rename,
+    #[default]
+    Fail,
+}
+
+#[derive(Debug, Deserialize)]
+struct CopyRequest {
+    paths: Vec<String>,
+    destination: String,
+    #[serde(default)]
+    conflict: CopyConflict,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CopyAction {
+    Copied,
+    Renamed,
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+struct CopyResult {
+    path: String,
+    new_path: Option<String>,
+    action: CopyAction,
+}
+
+#[derive(Debug, Serialize)]
+struct CopyResponse {
+    success: bool,
+    /// The background job doing the copying; absent when everything was
+    /// skipped.
+    job: Option<db::Job>,
+    results: Vec<CopyResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RenameRequest {
+    path: String,
+    name: String,
+    /// Also rename the file's sidecars and RAW/JPEG partner (default true).
+    companions: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+struct RenameResponse {
+    path: String,
+    companions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchRenameRequest {
+    paths: Vec<String>,
+    template: String,
+    start: Option<u64>,
+    #[serde(default)]
+    dry_run: bool,
+    companions: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchRenameResponse {
+    dry_run: bool,
+    applied: bool,
+    renamed: usize,
+    results: Vec<RenameResult>,
+}
+
+#[derive(Debug, Serialize)]
+struct RenameResult {
+    path: String,
+    new_path: Option<String>,
+    error: Option<String>,
+}
+
+/// One file of a batch rename: where it is and what it will be called.
+#[derive(Debug)]
+struct RenamePlan {
+    full: PathBuf,
+    rel: String,
+    target_full: PathBuf,
+    target_rel: String,
+}
+
+#[derive(Debug, Serialize)]
+struct FsResponse {
+    success: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct MoveResponse {
+    success: bool,
+    /// Set when some paths go to another filesystem and are moved by a
+    /// background job instead.
+    job: Option<db::Job>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JobsQuery {
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct JobsResponse {
+    jobs: Vec<db::Job>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JobQuery {
+    id: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct JobResponse {
+    job: db::Job,
+    items: Vec<db::JobItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JobResumeRequest {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct IngestRequest {
+    /// Folder to read from, inside one of the configured ingest sources.
+    source: String,
+    destination: String,
+    template: Option<String>,
+    shoot: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    copyright: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct IngestResponse {
+    success: bool,
+    /// The background job doing the ingest; its items list where each file
+    /// went and which were skipped.
+    job: db::Job,
+}
+
+#[derive(Debug, Serialize)]
+struct IngestSourcesResponse {
+    sources: Vec<String>,
+    template: String,
+}
+
+#[derive(Debug, Serialize)]
+struct HotFoldersResponse {
+    folders: Vec<db::HotFolder>,
+}
+
+#[derive(Debug, Serialize)]
+struct HotFolderResponse {
+    folder: db::HotFolder,
+}
+
+#[derive(Debug, Deserialize)]
+struct HotFolderCreateRequest {
+    /// Folder to watch, inside one of the configured ingest sources.
+    path: String,
+    destination: String,
+    #[serde(default)]
+    settings: db::HotFolderSettings,
+    enabled: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HotFolderUpdateRequest {
+    id: i64,
+    destination: Option<String>,
+    settings: Option<db::HotFolderSettings>,
+    enabled: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HotFolderQuery {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct HotFolderArrivalsQuery {
+    id: i64,
+    /// Only files that arrived after this unix time.
+    since: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct HotFolderArrivalsResponse {
+    arrivals: Vec<db::HotFolderArrival>,
+}
+
+#[derive(Debug, Serialize)]
+struct TrashResponse {
+    retention_days: Option<i64>,
+    items: Vec<db::TrashItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrashRestoreRequest {
+    ids: Vec<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrashEmptyRequest {
+    ids: Option<Vec<i64>>,
+}
+
+#[derive(Debug, Serialize)]
+struct TrashUpdateResponse {
+    count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadQuery {
+    path: Option<String>,
+    #[serde(default)]
+    conflict: UploadConflict,
+}
+
+/// What an upload does when the folder already has a file with its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum UploadConflict {
+    #[default]
+    Fail,
+    Skip,
+    /// Save under the first free `name-N.ext`.
+    Rename,
+    Overwrite,
+    /// Skip when the existing file has the same SHA-256, otherwise rename.
+    SkipIdentical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum UploadAction {
+    Created,
+    Renamed,
+    Overwritten,
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+struct UploadResult {
+    name: String,
+    path: Option<String>,
+    action: Option<UploadAction>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct UploadResponse {
+    success: bool,
+    results: Vec<UploadResult>,
+    /// Set when the request broke off before every file was read.
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadCreateRequest {
+    path: Option<String>,
+    name: String,
+    size: i64,
+    sha256: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadIdQuery {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadChunkQuery {
+    id: String,
+    offset: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadCancelRequest {
+    id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct UploadStatusResponse {
+    #[serde(flatten)]
+    upload: db::UploadSession,
+    complete: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct UploadsResponse {
+    uploads: Vec<db::UploadSession>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = Json(ErrorResponse {
+            error: self.message,
+        });
+        (self.status, body).into_response()
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+/// Who made a request, for the edit history. There are no accounts, so this
+/// is whatever the client puts in the `X-Actor` header.
+#[derive(Debug, Clone)]
+struct Actor(String);
+
+impl Actor {
+    fn edit<'a>(&'a self, kind: &'a str) -> db::Edit<'a> {
+        db::Edit {
+            kind,
+            actor: &self.0,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let name = parts
+            .headers
+            .get(ACTOR_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().chars().take(64).collect::<String>())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| "anonymous".to_string());
+        Ok(Self(name))
+    }
+}
+
+const ACTOR_HEADER: &str = "x-actor";
+
+const CATALOG_EXPORT_PAGE: i64 = 500;
+const CATALOG_IMPORT_LIMIT: usize = 256 * 1024 * 1024;
+const UPLOAD_CHUNK_LIMIT: usize = 64 * 1024 * 1024;
+
+const COLOR_LABELS: [&str; 5] = ["red", "yellow", "green", "blue", "purple"];
+const FLAGS: [&str; 2] = ["pick", "reject"];
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "raw_manager=info,tower_http=info".into()),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    dotenvy::dotenv().ok();
+
+    let data_dir = env::var("RAW_MANAGER_DATA_DIR").unwrap_or_else(|_| "data".to_string());
+    let data_dir = PathBuf::from(data_dir);
+    tokio::fs::create_dir_all(&data_dir).await?;
+    let preview_dir = data_dir.join("previews");
+    tokio::fs::create_dir_all(&preview_dir).await?;
+
+    let db_path = data_dir.join("raw-manager.db");
+    let options = SqliteConnectOptions::new()
+        .filename(&db_path)
+        .create_if_missing(true);
+    let pool = SqlitePool::connect_with(options).await?;
+    db::init_db(&pool, &db_path).await?;
+    journal::recover(&pool).await?;
+
+    let configured_root = read_library_root_env();
+    let (library_root, library_root_canon) = if let Some(root) = configured_root.clone() {
+        let root_path = PathBuf::from(&root);
+        match tokio::fs::canonicalize(&root_path).await {
+            Ok(canon) => (Some(root_path), Some(canon)),
+            Err(err) => {
+                error!("Failed to canonicalize configured root: {err}");
+                (None, None)
+            }
+        }
+    } else {
+        (None, None)
+    };
+
+    let state = AppState {
+        pool,
+        library_root: Arc::new(RwLock::new(library_root)),
+        library_root_canon: Arc::new(RwLock::new(library_root_canon)),
+        preview_dir,
+        xmp_writeback: read_flag_env("RAW_MANAGER_XMP_WRITEBACK"),
+        companion_previews: read_flag_env("RAW_MANAGER_COMPANION_PREVIEWS"),
+        trash_retention_days: read_trash_retention_env(),
+        formats: Arc::new(formats::FormatRegistry::new(
+            &read_list_env("RAW_MANAGER_EXTRA_RAW_EXTENSIONS"),
+            read_flag_env("RAW_MANAGER_MANAGE_IMAGES"),
+        )),
+        upload_limits: uploads::UploadLimits {
+            max_file_bytes: read_megabytes_env("RAW_MANAGER_UPLOAD_MAX_FILE_MB", uploads::DEFAULT_MAX_FILE_MB),
+            max_request_bytes: read_megabytes_env(
+                "RAW_MANAGER_UPLOAD_MAX_REQUEST_MB",
+                uploads::DEFAULT_MAX_REQUEST_MB,
+            ),
+        },
+        busy_uploads: Arc::new(std::sync::Mutex::new(HashSet::new())),
+        ingest_sources: Arc::new(read_list_env("RAW_MANAGER_INGEST_SOURCES")),
+        ingest_template: env::var("RAW_MANAGER_INGEST_TEMPLATE")
+            .ok()
+            .map(|template| template.trim().to_string())
+            .filter(|template| !template.is_empty())
+            .unwrap_or_else(|| ingest::DEFAULT_TEMPLATE.to_string()),
+    };
+    if let Err(err) = rename::Template::parse(&state.ingest_template) {
+        anyhow::bail!("Invalid RAW_MANAGER_INGEST_TEMPLATE: {err}");
+    }
+    let upload_body_limit = match state.upload_limits.max_request_bytes {
+        Some(bytes) => DefaultBodyLimit::max(usize::try_from(bytes).unwrap_or(usize::MAX)),
+        None => DefaultBodyLimit::disable(),
+    };
+    spawn_trash_purge(state.clone());
+    spawn_upload_expiry(state.clone());
+    spawn_hot_folders(state.clone(), read_hot_folder_interval_env(), read_hot_folder_settle_env());
+    if let Some(config) = read_ftp_config_env()? {
+        ftp::spawn(state.clone(), config).await?;
+    }
+    for id in db::requeue_jobs(&state.pool).await? {
+        spawn_job(state.clone(), id).await?;
+    }
+
+    let api = Router::new()
+        .route("/config", get(get_config))
+        .route("/formats", get(list_formats))
+        .route("/browse", get(browse))
+        .route("/file/metadata", get(file_metadata))
+        .route("/file/preview", get(file_preview))
+        .route("/file/download", get(file_download))
+        .route("/file/rating", post(set_rating))
+        .route("/file/tags", post(set_tags))
+        .route("/file/text", post(set_text))
+        .route("/file/batch", post(batch_update))
+        .route("/tags", get(list_tags))
+        .route("/tags/suggest", get(suggest_tags))
+        .route("/tags/rename", post(rename_tag))
+        .route("/tags/merge", post(merge_tags))
+        .route("/tags/delete", post(delete_tag))
+        .route("/search", get(search))
+        .route("/searches", get(list_saved_searches))
+        .route("/searches/create", post(create_saved_search))
+        .route("/searches/update", post(update_saved_search))
+        .route("/searches/delete", post(delete_saved_search))
+        .route("/searches/results", get(saved_search_results))
+        .route("/albums", get(list_albums))
+        .route("/albums/create", post(create_album))
+        .route("/albums/update", post(update_album))
+        .route("/albums/delete", post(delete_album))
+        .route("/albums/items", get(album_items))
+        .route("/albums/add", post(add_album_items))
+        .route("/albums/remove", post(remove_album_items))
+        .route("/albums/reorder", post(reorder_album_items))
+        .route("/history", get(list_history))
+        .route("/history/file", get(file_history))
+        .route("/history/undo", post(undo_history))
+        .route("/history/revert", post(revert_history))
+        .route("/catalog/export", get(catalog_export))
+        .route("/catalog/import", post(catalog_import))
+        .route("/fs/mkdir", post(fs_mkdir))
+        .route("/fs/delete", post(fs_delete))
+        .route("/fs/move", post(fs_move))
+        .route("/fs/copy", post(fs_copy))
+        .route("/fs/rename", post(fs_rename))
+        .route("/fs/rename/batch", post(fs_rename_batch))
+        .route("/fs/upload", post(fs_upload).layer(upload_body_limit))
+        .route("/uploads", get(list_uploads))
+        .route("/uploads/create", post(create_upload))
+        .route("/uploads/status", get(upload_status))
+        .route("/uploads/chunk", post(upload_chunk))
+        .route("/uploads/cancel", post(cancel_upload))
+        .route("/ingest", post(start_ingest))
+        .route("/ingest/sources", get(list_ingest_sources))
+        .route("/hotfolders", get(list_hot_folders))
+        .route("/hotfolders/create", post(create_hot_folder))
+        .route("/hotfolders/update", post(update_hot_folder))
+        .route("/hotfolders/delete", post(delete_hot_folder))
+        .route("/hotfolders/arrivals", get(hot_folder_arrivals))
+        .route("/trash", get(list_trash))
+        .route("/trash/restore", post(restore_trash))
+        .route("/trash/empty", post(empty_trash))
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/status", get(job_status))
+        .route("/jobs/resume", post(resume_job))
+        .route("/health", get(health));
+
+    let static_service = ServeDir::new("static").fallback(ServeFile::new("static/index.html"));
+
+    let app = Router::new()
+        .nest("/api", api)
+        .fallback_service(static_service)
+        .layer(TraceLayer::new_for_http())
+        .with_state(state);
+
+    let addr = env::var("RAW_MANAGER_ADDR").unwrap_or_else(|_| "0.0.0.0:1234".to_string());
+    info!("Raw Manager listening on {addr}");
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn health() -> &'static str {
+    "ok"
+}
+
+async fn get_config(State(state): State<AppState>) -> ApiResult<Json<ConfigResponse>> {
+    let root = state.library_root.read().await;
+    let value = root.as_ref().and_then(|path| path.to_str().map(|s| s.to_string()));
+    Ok(Json(ConfigResponse {
+        configured: value.is_some(),
+        library_root: value,
+    }))
+}
+
+async fn list_formats(State(state): State<AppState>) -> Json<FormatsResponse> {
+    Json(FormatsResponse {
+        formats: state.formats.iter().cloned().collect(),
+    })
+}
+
+async fn browse(
+    State(state): State<AppState>,
+    Query(query): Query<BrowseQuery>,
+) -> ApiResult<Json<BrowseResponse>> {
+    let rel_path = query.path.unwrap_or_default();
+    let root_canon = get_root_canon(&state).await?;
+
+    let rel = sanitize_relative(&rel_path)?;
+    let full_path = root_canon.join(&rel);
+    let full_canon = tokio::fs::canonicalize(&full_path)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Path not found"))?;
+
+    if !full_canon.starts_with(&root_canon) {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+    }
+
+    let mut entries = Vec::new();
+    let mut file_names = Vec::new();
+    let mut read_dir = tokio::fs::read_dir(&full_canon)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Folder not found"))?;
+
+    while let Some(entry) = read_dir
+        .next_entry()
+        .await
+        .map_err(|_| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Read error"))?
+    {
+        let path = entry.path();
+        let name = entry
+            .file_name()
+            .to_string_lossy()
+            .to_string();
+        if journal::is_staged(&name)
+            || transfer::is_partial(&name)
+            || name == trash::TRASH_DIR
+            || name == uploads::STAGING_DIR
+        {
+            continue;
+        }
+        let file_type = entry
+            .file_type()
+            .await
+            .map_err(|_| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Read error"))?;
+
+        if file_type.is_dir() {
+            let rel = path
+                .strip_prefix(&root_canon)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .to_string();
+
+            entries.push(BrowseEntry {
+                name,
+                path: rel,
+                kind: "dir".to_string(),
+                size: None,
+                modified: None,
+                camera_rating: None,
+                user_rating: None,
+                tags: Vec::new(),
+                gps_lat: None,
+                gps_lon: None,
+                taken_at: None,
+                orientation: None,
+                color_label: None,
+                flag: None,
+                needs_scan: false,
+                companions: Vec::new(),
+            });
+            continue;
+        }
+
+        if !file_type.is_file() {
+            continue;
+        }
+
+        file_names.push(name.clone());
+        if !state.formats.is_managed(&path) {
+            continue;
+        }
+
+        let rel = path
+            .strip_prefix(&root_canon)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .to_string();
+
+        let meta = entry.metadata().await.ok();
+        let (size, modified) = if let Some(meta) = meta {
+            let size = meta.len() as i64;
+            let modified = to_unix_seconds(meta.modified().ok());
+            (size, modified)
+        } else {
+            (0, 0)
+        };
+
+        let db_meta = db::get_file_meta(&state.pool, &rel)
+            .await
+            .map_err(internal_error)?;
+
+        let entry = match db_meta {
+            Some(db_meta) => {
+                let is_fresh = db_meta.file_size == size
+                    && db_meta.last_modified == modified
+                    && db_meta.orientation.is_some();
+                BrowseEntry {
+                    name,
+                    size: Some(size),
+                    modified: Some(modified),
+                    needs_scan: !is_fresh,
+                    ..entry_from_meta(db_meta)
+                }
+            }
+            None => BrowseEntry {
+                name,
+                ..entry_from_meta(FileMeta {
+                    path: rel,
+                    file_size: size,
+                    last_modified: modified,
+                    ..Default::default()
+                })
+            },
+        };
+
+        entries.push(entry);
+    }
+
+    // Files of one shot share a name up to the extension; list each RAW's
+    // JPEG, HEIF and sidecars with it.
+    let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+    for name in file_names {
+        groups
+            .entry(companions::group_key(&name).to_lowercase())
+            .or_default()
+            .push(name);
+    }
+    for entry in entries.iter_mut().filter(|entry| entry.kind == "file") {
+        let Some(group) = groups.get(&companions::group_key(&entry.name).to_lowercase()) else {
+            continue;
+        };
+        let mut names: Vec<&String> = group.iter().filter(|name| **name != entry.name).collect();
+        names.sort();
+        entry.companions = names
+            .into_iter()
+            .map(|name| companion_file(&state.formats, &entry.path, name))
+            .collect();
+    }
+
+    entries.sort_by(|a, b| match (a.kind.as_str(), b.kind.as_str()) {
+        ("dir", "file") => std::cmp::Ordering::Less,
+        ("file", "dir") => std::cmp::Ordering::Greater,
+        _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+    });
+
+    // Saved searches are listed next to the top-level folders.
+    let saved_searches = if rel.as_os_str().is_empty() {
+        saved_search_summaries(&state).await?
+    } else {
+        Vec::new()
+    };
+
+    Ok(Json(BrowseResponse {
+        path: rel.to_string_lossy().to_string(),
+        entries,
+        saved_searches,
+    }))
+}
+
+async fn file_metadata(
+    State(state): State<AppState>,
+    Query(query): Query<FileQuery>,
+) -> ApiResult<Json<FileMetaResponse>> {
+    let root_canon = get_root_canon(&state).await?;
+    let rel = sanitize_relative(&query.path)?;
+    let full_path = root_canon.join(&rel);
+    let full_canon = tokio::fs::canonicalize(&full_path)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
+
+    if !full_canon.starts_with(&root_canon) {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+    }
+
+    let meta = tokio::fs::metadata(&full_canon)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
+    if !meta.is_file() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "Not a file"));
+    }
+
+    let size = meta.len() as i64;
+    let modified = to_unix_seconds(meta.modified().ok());
+
+    let meta = scan_file(&state, &query.path, &full_canon, size, modified).await?;
+
+    let name = full_path
+        .file_name()
+        .unwrap_or_else(|| OsStr::new(""))
+        .to_string_lossy()
+        .to_string();
+    let companions = find_companions(&full_canon)
+        .await?
+        .iter()
+        .map(|companion| companion_file(&state.formats, &query.path, companion))
+        .collect();
+    let content_mismatch = content_mismatch(&state.formats, &full_canon, size).await?;
+
+    Ok(Json(FileMetaResponse {
+        path: query.path,
+        name,
+        camera_rating: meta.camera_rating,
+        user_rating: meta.user_rating,
+        tags: meta.tags,
+        gps_lat: meta.gps_lat,
+        gps_lon: meta.gps_lon,
+        taken_at: meta.taken_at,
+        orientation: meta.orientation,
+        camera_make: meta.camera_make,
+        camera_model: meta.camera_model,
+        color_label: meta.color_label,
+        flag: meta.flag,
+        title: meta.title,
+        caption: meta.caption,
+        notes: meta.notes,
+        file_size: size,
+        last_modified: modified,
+        companions,
+        content_mismatch,
+    }))
+}
+
+/// Reads a file's metadata into the catalog unless its row is up to date
+/// with the file's size and mtime, and returns the row.
+async fn scan_file(
+    state: &AppState,
+    rel: &str,
+    full_canon: &Path,
+    size: i64,
+    modified: i64,
+) -> ApiResult<FileMeta> {
+    let db_meta = db::get_file_meta(&state.pool, rel)
+        .await
+        .map_err(internal_error)?;
+
+    let meta = match db_meta {
+        Some(existing)
+            if existing.file_size == size
+                && existing.last_modified == modified
+                && existing.orientation.is_some() => existing,
+        Some(existing) => {
+            let full_canon_clone = full_canon.to_path_buf();
+            let read_exif = state.formats.reads_exif(full_canon);
+            let extracted = tokio::task::spawn_blocking(move || metadata::read_metadata(&full_canon_clone, read_exif))
+                .await
+                .map_err(internal_error)?
+                .map_err(internal_error)?;
+            // Rows created by an edit before the first scan have never seen
+            // the file's XMP keywords and text, so import them now.
+            let first_scan = existing.orientation.is_none();
+            let tags = if first_scan && existing.tags.is_empty() {
+                tags::normalize_tags(&extracted.keywords)
+            } else {
+                existing.tags.clone()
+            };
+            let (title, caption) = if first_scan {
+                (
+                    existing.title.clone().or(extracted.title),
+                    existing.caption.clone().or(extracted.caption),
+                )
+            } else {
+                (existing.title.clone(), existing.caption.clone())
+            };
+            let new_meta = FileMeta {
+                path: rel.to_string(),
+                camera_rating: extracted.camera_rating,
+                tags,
+                title,
+                caption,
+                gps_lat: extracted.gps_lat,
+                gps_lon: extracted.gps_lon,
+                taken_at: extracted.taken_at,
+                orientation: extracted.orientation.or(Some(0)),
+                camera_make: extracted.camera_make,
+                camera_model: extracted.camera_model,
+                file_size: size,
+                last_modified: modified,
+                ..existing
+            };
+            db::upsert_file_meta(&state.pool, &new_meta)
+                .await
+                .map_err(internal_error)?;
+            // The pair was brought in line when this row was created, so an
+            // empty field here may have been cleared on purpose.
+            new_meta
+        }
+        None => {
+            let full_canon_clone = full_canon.to_path_buf();
+            let read_exif = state.formats.reads_exif(full_canon);
+            let extracted = tokio::task::spawn_blocking(move || metadata::read_metadata(&full_canon_clone, read_exif))
+                .await
+                .map_err(internal_error)?
+                .map_err(internal_error)?;
+            let new_meta = FileMeta {
+                path: rel.to_string(),
+                camera_rating: extracted.camera_rating,
+                tags: tags::normalize_tags(&extracted.keywords),
+                title: extracted.title,
+                caption: extracted.caption,
+                gps_lat: extracted.gps_lat,
+                gps_lon: extracted.gps_lon,
+                taken_at: extracted.taken_at,
+                orientation: extracted.orientation.or(Some(0)),
+                camera_make: extracted.camera_make,
+                camera_model: extracted.camera_model,
+                file_size: size,
+                last_modified: modified,
+                ..Default::default()
+            };
+            db::upsert_file_meta(&state.pool, &new_meta)
+                .await
+                .map_err(internal_error)?;
+            reconcile_pair(state, rel, full_canon, new_meta).await?
+        }
+    };
+    Ok(meta)
+}
+
+/// Brings a file that just got its catalog row and the other half of its
+/// RAW+JPEG or RAW+HEIF pair in line: a rating, tags, label or flag that
+/// only one of them has is given to the other, and a partner without a row
+/// gets one. Values both already have are left alone. Returns the scanned
+/// file's row.
+async fn reconcile_pair(state: &AppState, rel: &str, full: &Path, mut meta: FileMeta) -> ApiResult<FileMeta> {
+    let kind = companion_kind(&state.formats, rel);
+    for companion in find_companions(full).await? {
+        if !kind.pairs_with(companion_kind(&state.formats, &companion)) {
+            continue;
+        }
+        let partner_rel = rel_to_string(&Path::new(rel).with_file_name(&companion));
+        let existing = db::get_file_meta(&state.pool, &partner_rel)
+            .await
+            .map_err(internal_error)?;
+        let is_new = existing.is_none();
+        let mut partner = match existing {
+            Some(partner) => partner,
+            None => {
+                let Ok(partner_meta) = tokio::fs::metadata(full.with_file_name(&companion)).await else {
+                    continue;
+                };
+                // Not scanned yet: the missing orientation makes the next
+                // browse or scan read the file.
+                FileMeta {
+                    path: partner_rel,
+                    file_size: partner_meta.len() as i64,
+                    last_modified: to_unix_seconds(partner_meta.modified().ok()),
+                    ..Default::default()
+                }
+            }
+        };
+        if fill_pair_gaps(&mut meta, &partner) {
+            db::upsert_file_meta(&state.pool, &meta)
+                .await
+                .map_err(internal_error)?;
+        }
+        if fill_pair_gaps(&mut partner, &meta) || is_new {
+            db::upsert_file_meta(&state.pool, &partner)
+                .await
+                .map_err(internal_error)?;
+        }
+    }
+    Ok(meta)
+}
+
+/// Copies the pair-shared fields `meta` has no value for from `from`.
+/// Returns whether anything changed.
+fn fill_pair_gaps(meta: &mut FileMeta, from: &FileMeta) -> bool {
+    let mut changed = false;
+    if meta.user_rating.is_none() && from.user_rating.is_some() {
+        meta.user_rating = from.user_rating;
+        changed = true;
+    }
+    if meta.tags.is_empty() && !from.tags.is_empty() {
+        meta.tags = from.tags.clone();
+        changed = true;
+    }
+    if meta.color_label.is_none() && from.color_label.is_some() {
+        meta.color_label = from.color_label.clone();
+        changed = true;
+    }
+    if meta.flag.is_none() && from.flag.is_some() {
+        meta.flag = from.flag.clone();
+        changed = true;
+    }
+    changed
+}
+
+async fn sniff_path(full: &Path) -> ApiResult<Option<formats::Container>> {
+    let full = full.to_path_buf();
+    tokio::task::spawn_blocking(move || formats::sniff_file(&full))
+        .await
+        .map_err(internal_error)?
+        .map_err(|err| map_fs_error(err, "Unable to read file"))
+}
+
+async fn content_mismatch(
+    formats: &formats::FormatRegistry,
+    full: &Path,
+    size: i64,
+) -> ApiResult<Option<ContentMismatch>> {
+    let Some(format) = formats.get(full) else {
+        return Ok(None);
+    };
+    let detected = sniff_path(full).await?;
+    let empty = size == 0;
+    Ok((!format.accepts(detected, empty)).then_some(ContentMismatch {
+        expected: format.containers,
+        detected,
+        empty,
+    }))
+}
+
+async fn file_preview(
+    State(state): State<AppState>,
+    Query(query): Query<PreviewQuery>,
+) -> ApiResult<Response> {
+    let root_canon = get_root_canon(&state).await?;
+    let rel = sanitize_relative(&query.path)?;
+    let full_path = root_canon.join(&rel);
+    let full_canon = tokio::fs::canonicalize(&full_path)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
+
+    if !full_canon.starts_with(&root_canon) {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+    }
+
+    let kind = match query.kind.as_deref() {
+        Some("thumb") => metadata::PreviewKind::Thumb,
+        _ => metadata::PreviewKind::Full,
+    };
+    let preview_path = ensure_file_preview(&state, &query.path, full_canon, kind)
+        .await?
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "No preview available"))?;
+
+    let file = tokio::fs::File::open(&preview_path)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Preview not found"))?;
+    let stream = ReaderStream::new(file);
+    let body = Body::from_stream(stream);
+    let mut response = Response::new(body);
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("image/jpeg"),
+    );
+    Ok(response)
+}
+
+/// Builds the cached preview of a file if it is missing or stale and returns
+/// its path, or `None` when the file has no preview to offer.
+async fn ensure_file_preview(
+    state: &AppState,
+    rel: &str,
+    full_canon: PathBuf,
+    kind: metadata::PreviewKind,
+) -> ApiResult<Option<PathBuf>> {
+    // The camera's own JPEG is usually better than the preview embedded in
+    // the RAW, so it is used instead when enabled.
+    let (source, cache_key) = match companion_preview_source(state, rel, &full_canon).await? {
+        Some((path, key)) => (PreviewSource::Jpeg(path), key),
+        None => {
+            // Content decides over the extension, so a JPEG saved as `.NEF`
+            // is previewed as the JPEG it is.
+            let detected = sniff_path(&full_canon).await?;
+            let capabilities = state.formats.get(&full_canon).map(|format| format.capabilities);
+            match capabilities {
+                _ if detected == Some(formats::Container::Jpeg) => (PreviewSource::Jpeg(full_canon), rel.to_string()),
+                Some(capabilities) if capabilities.decode => (PreviewSource::Jpeg(full_canon), rel.to_string()),
+                Some(capabilities) if !capabilities.preview => return Ok(None),
+                _ => (PreviewSource::Embedded(full_canon), rel.to_string()),
+            }
+        }
+    };
+    let preview_path = metadata::preview_cache_path(&state.preview_dir, &cache_key, kind);
+    let preview_path_clone = preview_path.clone();
+    let generated = tokio::task::spawn_blocking(move || match source {
+        PreviewSource::Embedded(path) => metadata::ensure_preview(&path, &preview_path_clone, kind),
+        PreviewSource::Jpeg(path) => metadata::ensure_jpeg_preview(&path, &preview_path_clone, kind),
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    Ok(generated.then_some(preview_path))
+}
+
+/// Where a preview comes from: a JPEG embedded in the file, or a JPEG that
+/// is the image itself.
+enum PreviewSource {
+    Embedded(PathBuf),
+    Jpeg(PathBuf),
+}
+
+async fn file_download(
+    State(state): State<AppState>,
+    Query(query): Query<FileQuery>,
+) -> ApiResult<Response> {
+    let root_canon = get_root_canon(&state).await?;
+    let rel = sanitize_relative(&query.path)?;
+    let full_path = root_canon.join(&rel);
+    let full_canon = tokio::fs::canonicalize(&full_path)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
+
+    if !full_canon.starts_with(&root_canon) {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+    }
+
+    let file = tokio::fs::File::open(&full_canon)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
+    let stream = ReaderStream::new(file);
+    let body = Body::from_stream(stream);
+
+    let filename = full_canon
+        .file_name()
+        .unwrap_or_else(|| OsStr::new("raw"))
+        .to_string_lossy()
+        .to_string();
+
+    let mut response = Response::new(body);
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_str(
+            mime_guess::from_path(&full_canon)
+                .first_or_octet_stream()
+                .as_ref(),
+        )
+        .unwrap_or_else(|_| header::HeaderValue::from_static("application/octet-stream")),
+    );
+    response.headers_mut().insert(
+        header::CONTENT_DISPOSITION,
+        header::HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))
+            .unwrap_or_else(|_| header::HeaderValue::from_static("attachment")),
+    );
+    Ok(response)
+}
+
+async fn set_rating(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<RatingRequest>,
+) -> ApiResult<Json<RatingResponse>> {
+    let root_canon = get_root_canon(&state).await?;
+    let rating = validate_rating(payload.rating)?;
+    let target = resolve_file_target(&root_canon, &payload.path).await?;
+    let targets = with_pair_targets(&state.formats, &root_canon, vec![target], payload.companions).await?;
+
+    db::update_files(&state.pool, actor.edit("rating"), &targets, |meta| {
+        meta.user_rating = rating;
+    })
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Json(RatingResponse {
+        user_rating: rating,
+    }))
+}
+
+async fn set_tags(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<TagsRequest>,
+) -> ApiResult<Json<TagsResponse>> {
+    let root_canon = get_root_canon(&state).await?;
+    let target = resolve_file_target(&root_canon, &payload.path).await?;
+    let targets = with_pair_targets(&state.formats, &root_canon, vec![target], payload.companions).await?;
+    let tags = tags::normalize_tags(&payload.tags);
+
+    db::update_files(&state.pool, actor.edit("tags"), &targets, |meta| {
+        meta.tags = tags.clone();
+    })
+    .await
+    .map_err(internal_error)?;
+
+    if state.xmp_writeback {
+        let tags_clone = tags.clone();
+        let full_paths: Vec<PathBuf> = targets.iter().map(|target| root_canon.join(&target.path)).collect();
+        tokio::task::spawn_blocking(move || {
+            full_paths
+                .iter()
+                .try_for_each(|full_path| metadata::write_sidecar_keywords(full_path, &tags_clone))
+        })
+        .await
+        .map_err(internal_error)?
+        .map_err(internal_error)?;
+    }
+
+    Ok(Json(TagsResponse { tags }))
+}
+
+async fn set_text(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<TextRequest>,
+) -> ApiResult<Json<TextResponse>> {
+    let root_canon = get_root_canon(&state).await?;
+    let target = resolve_file_target(&root_canon, &payload.path).await?;
+
+    // Omitted fields stay as they are; an empty string clears the field.
+    let normalize = |value: Option<String>| value.map(|value| value.trim().to_string());
+    let title = normalize(payload.title);
+    let caption = normalize(payload.caption);
+    let notes = normalize(payload.notes);
+
+    let mut updated = db::update_files(&state.pool, actor.edit("text"), &[target], |meta| {
+        if let Some(title) = &title {
+            meta.title = Some(title.clone()).filter(|value| !value.is_empty());
+        }
+        if let Some(caption) = &caption {
+            meta.caption = Some(caption.clone()).filter(|value| !value.is_empty());
+        }
+        if let Some(notes) = &notes {
+            meta.notes = Some(notes.clone()).filter(|value| !value.is_empty());
+        }
+    })
+    .await
+    .map_err(internal_error)?;
+    let meta = updated
+        .pop()
+        .ok_or_else(|| internal_error("file row missing after update"))?;
+
+    // Only the fields the request set go to the sidecar, so editing the
+    // caption leaves a title the catalog does not know about in place.
+    let change = db::TextChange::of(&meta, title.is_some(), caption.is_some());
+    let writeback_errors = if change.title.is_some() || change.caption.is_some() {
+        write_back_text_changes(&state, vec![change]).await
+    } else {
+        Vec::new()
+    };
+
+    Ok(Json(TextResponse {
+        title: meta.title,
+        caption: meta.caption,
+        notes: meta.notes,
+        writeback_errors,
+    }))
+}
+
+async fn batch_update(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<BatchRequest>,
+) -> ApiResult<Json<BatchResponse>> {
+    if payload.paths.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "No paths provided",
+        ));
+    }
+    if payload.operations.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "No operations provided",
+        ));
+    }
+
+    let mut operations = Vec::with_capacity(payload.operations.len());
+    for operation in payload.operations {
+        operations.push(normalize_batch_operation(operation)?);
+    }
+    let touches_tags = operations.iter().any(|operation| {
+        matches!(
+            operation,
+            BatchOperation::AddTags { .. } | BatchOperation::RemoveTags { .. }
+        )
+    });
+
+    let root_canon = get_root_canon(&state).await?;
+    let mut targets = Vec::new();
+    let mut results = Vec::with_capacity(payload.paths.len());
+    for path in payload.paths {
+        match resolve_file_target(&root_canon, &path).await {
+            Ok(target) => {
+                targets.push(target);
+                results.push(BatchResult {
+                    path,
+                    success: true,
+                    error: None,
+                });
+            }
+            Err(err) => results.push(BatchResult {
+                path,
+                success: false,
+                error: Some(err.message),
+            }),
+        }
+    }
+
+    let targets = with_pair_targets(&state.formats, &root_canon, targets, payload.companions).await?;
+    // Remember which files the operations actually changed: adding a tag a
+    // file already has, or setting the rating it already has, is no update
+    // and needs no XMP write.
+    let mut changed = HashSet::new();
+    let mut retagged = HashSet::new();
+    let updated = db::update_files(&state.pool, actor.edit("batch"), &targets, |meta| {
+        let before = meta.clone();
+        for operation in &operations {
+            apply_batch_operation(meta, operation);
+        }
+        if !history::diff(&before, meta).is_empty() {
+            changed.insert(meta.path.clone());
+        }
+        if meta.tags != before.tags {
+            retagged.insert(meta.path.clone());
+        }
+    })
+    .await
+    .map_err(internal_error)?;
+
+    let mut writeback_errors = Vec::new();
+    if touches_tags {
+        let changes: Vec<db::TagChange> = updated
+            .iter()
+            .filter(|meta| retagged.contains(&meta.path))
+            .map(|meta| db::TagChange {
+                path: meta.path.clone(),
+                tags: meta.tags.clone(),
+            })
+            .collect();
+        writeback_errors = write_back_tag_changes(&state, &changes).await;
+    }
+
+    Ok(Json(BatchResponse {
+        updated: changed.len(),
+        results,
+        writeback_errors,
+    }))
+}
+
+fn normalize_batch_operation(operation: BatchOperation) -> ApiResult<BatchOperation> {
+    match operation {
+        BatchOperation::SetRating { rating } => Ok(BatchOperation::SetRating {
+            rating: validate_rating(rating)?,
+        }),
+        BatchOperation::AddTags { tags } => Ok(BatchOperation::AddTags {
+            tags: tags::normalize_tags(&tags),
+        }),
+        BatchOperation::RemoveTags { tags } => Ok(BatchOperation::RemoveTags {
+            tags: tags::normalize_tags(&tags),
+        }),
+        BatchOperation::SetLabel { label } => Ok(BatchOperation::SetLabel {
+            label: normalize_label(label)?,
+        }),
+        BatchOperation::SetFlag { flag } => Ok(BatchOperation::SetFlag {
+            flag: normalize_flag(flag)?,
+        }),
+    }
+}
+
+fn validate_rating(rating: Option<i32>) -> ApiResult<Option<i32>> {
+    if let Some(rating) = rating {
+        if !(0..=5).contains(&rating) {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Rating must be 0-5",
+            ));
+        }
+    }
+    Ok(rating)
+}
+
+fn normalize_label(label: Option<String>) -> ApiResult<Option<String>> {
+    let label = label.map(|label| label.trim().to_lowercase()).filter(|label| !label.is_empty());
+    if let Some(label) = &label {
+        if !COLOR_LABELS.contains(&label.as_str()) {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Label must be red, yellow, green, blue or purple",
+            ));
+        }
+    }
+    Ok(label)
+}
+
+fn normalize_flag(flag: Option<String>) -> ApiResult<Option<String>> {
+    let flag = flag.map(|flag| flag.trim().to_lowercase()).filter(|flag| !flag.is_empty());
+    if let Some(flag) = &flag {
+        if !FLAGS.contains(&flag.as_str()) {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Flag must be pick or reject",
+            ));
+        }
+    }
+    Ok(flag)
+}
+
+fn apply_batch_operation(meta: &mut FileMeta, operation: &BatchOperation) {
+    match operation {
+        BatchOperation::SetRating { rating } => meta.user_rating = *rating,
+        BatchOperation::AddTags { tags } => {
+            meta.tags = tags::normalize_tags(meta.tags.iter().chain(tags));
+        }
+        BatchOperation::RemoveTags { tags } => {
+            // Removing a parent removes its whole branch, the same way a
+            // search for it matches the descendants.
+            meta.tags.retain(|tag| !tags.iter().any(|removed| tags::matches_tag(tag, removed)));
+        }
+        BatchOperation::SetLabel { label } => meta.color_label = label.clone(),
+        BatchOperation::SetFlag { flag } => meta.flag = flag.clone(),
+    }
+}
+
+async fn resolve_file_target(root_canon: &Path, path: &str) -> ApiResult<db::BatchTarget> {
+    let rel = sanitize_relative(path)?;
+    let full_canon = tokio::fs::canonicalize(root_canon.join(&rel))
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
+    if !full_canon.starts_with(root_canon) {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+    }
+
+    let meta = tokio::fs::metadata(&full_canon)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
+    if !meta.is_file() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "Not a file"));
+    }
+
+    Ok(db::BatchTarget {
+        path: rel_to_string(&rel),
+        file_size: meta.len() as i64,
+        last_modified: to_unix_seconds(meta.modified().ok()),
+    })
+}
+
+async fn list_tags(State(state): State<AppState>) -> ApiResult<Json<TagsListResponse>> {
+    let files = db::list_tagged_files(&state.pool)
+        .await
+        .map_err(internal_error)?;
+    let usage = tags::usage(
+        files
+            .iter()
+            .map(|file| (file.tags.as_slice(), file.tags_updated_at)),
+    );
+    let tags = usage.iter().map(|entry| entry.tag.clone()).collect();
+    let tree = tags::build_tree(files.iter().map(|file| file.tags.as_slice()));
+    Ok(Json(TagsListResponse { tags, usage, tree }))
+}
+
+async fn suggest_tags(
+    State(state): State<AppState>,
+    Query(query): Query<TagSuggestQuery>,
+) -> ApiResult<Json<TagSuggestResponse>> {
+    let usage = db::list_tags(&state.pool)
+        .await
+        .map_err(internal_error)?;
+
+    let current = match query.path.as_deref() {
+        Some(path) => {
+            let rel = sanitize_relative(path)?;
+            db::get_file_meta(&state.pool, &rel_to_string(&rel))
+                .await
+                .map_err(internal_error)?
+                .map(|meta| meta.tags)
+                .unwrap_or_default()
+        }
+        None => Vec::new(),
+    };
+    // Only files sharing a tag with this one can add related tags.
+    let related = if current.is_empty() {
+        HashMap::new()
+    } else {
+        let files = db::list_tagged_files_matching(&state.pool, &current)
+            .await
+            .map_err(internal_error)?;
+        tags::cooccurrence(files.iter().map(|file| file.tags.as_slice()), &current)
+    };
+    let exclude = current.iter().map(|tag| tag.to_lowercase()).collect();
+
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+    let now = to_unix_seconds(Some(SystemTime::now()));
+    let suggestions = tags::suggest(
+        &usage,
+        query.prefix.as_deref().unwrap_or(""),
+        &related,
+        &exclude,
+        now,
+        limit,
+    );
+    Ok(Json(TagSuggestResponse { suggestions }))
+}
+
+async fn rename_tag(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<TagRenameRequest>,
+) -> ApiResult<Json<TagsUpdateResponse>> {
+    let from = required_tag(&payload.from)?;
+    let to = required_tag(&payload.to)?;
+    if to == from || tags::is_below(&to, &from) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "A tag cannot be renamed to itself or into its own branch",
+        ));
+    }
+    let sources = vec![from];
+
+    let changes = db::rewrite_tags(&state.pool, actor.edit("tag_rename"), |current| {
+        tags::retag(current, &sources, Some(&to))
+    })
+        .await
+        .map_err(internal_error)?;
+    let writeback_errors = write_back_tag_changes(&state, &changes).await;
+
+    Ok(Json(TagsUpdateResponse {
+        files_changed: changes.len(),
+        writeback_errors,
+    }))
+}
+
+async fn merge_tags(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<TagMergeRequest>,
+) -> ApiResult<Json<TagsUpdateResponse>> {
+    let sources = tags::normalize_tags(&payload.sources);
+    if sources.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "No source tags provided",
+        ));
+    }
+    let target = required_tag(&payload.target)?;
+    if sources.iter().any(|source| tags::is_below(&target, source)) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Tags cannot be merged into their own branch",
+        ));
+    }
+
+    let changes = db::rewrite_tags(&state.pool, actor.edit("tag_merge"), |current| {
+        tags::retag(current, &sources, Some(&target))
+    })
+        .await
+        .map_err(internal_error)?;
+    let writeback_errors = write_back_tag_changes(&state, &changes).await;
+
+    Ok(Json(TagsUpdateResponse {
+        files_changed: changes.len(),
+        writeback_errors,
+    }))
+}
+
+async fn delete_tag(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<TagDeleteRequest>,
+) -> ApiResult<Json<TagsUpdateResponse>> {
+    let sources = vec![required_tag(&payload.tag)?];
+
+    let changes = db::rewrite_tags(&state.pool, actor.edit("tag_delete"), |current| {
+        tags::retag(current, &sources, None)
+    })
+        .await
+        .map_err(internal_error)?;
+    let writeback_errors = write_back_tag_changes(&state, &changes).await;
+
+    Ok(Json(TagsUpdateResponse {
+        files_changed: changes.len(),
+        writeback_errors,
+    }))
+}
+
+/// Writes changed tags to the files' XMP sidecars and returns the files
+/// whose sidecar could not be written.
+async fn write_back_tag_changes(state: &AppState, changes: &[db::TagChange]) -> Vec<WritebackError> {
+    if !state.xmp_writeback || changes.is_empty() {
+        return Vec::new();
+    }
+    let Ok(root_canon) = get_root_canon(state).await else {
+        return Vec::new();
+    };
+
+    let changes = changes.to_vec();
+    let result = tokio::task::spawn_blocking(move || {
+        let mut failures = Vec::new();
+        for change in changes {
+            let full_path = root_canon.join(&change.path);
+            if !full_path.is_file() {
+                continue;
+            }
+            if let Err(err) = metadata::write_sidecar_keywords(&full_path, &change.tags) {
+                error!("Failed to write XMP sidecar for {}: {err}", change.path);
+                failures.push(WritebackError {
+                    path: change.path,
+                    error: format!("{err:#}"),
+                });
+            }
+        }
+        failures
+    })
+    .await;
+    result.unwrap_or_else(|err| {
+        error!("XMP write-back task failed: {err}");
+        vec![WritebackError {
+            path: String::new(),
+            error: err.to_string(),
+        }]
+    })
+}
+
+/// Writes changed titles and captions to the files' XMP sidecars and
+/// returns the files whose sidecar could not be written.
+async fn write_back_text_changes(state: &AppState, changes: Vec<db::TextChange>) -> Vec<WritebackError> {
+    if !state.xmp_writeback || changes.is_empty() {
+        return Vec::new();
+    }
+    let Ok(root_canon) = get_root_canon(state).await else {
+        return Vec::new();
+    };
+
+    let result = tokio::task::spawn_blocking(move || {
+        let mut failures = Vec::new();
+        for change in changes {
+            let full_path = root_canon.join(&change.path);
+            if !full_path.is_file() {
+                continue;
+            }
+            let title = change.title.as_ref().map(Option::as_deref);
+            let caption = change.caption.as_ref().map(Option::as_deref);
+            if let Err(err) = metadata::write_sidecar_text(&full_path, title, caption) {
+                error!("Failed to write XMP sidecar for {}: {err}", change.path);
+                failures.push(WritebackError {
+                    path: change.path,
+                    error: format!("{err:#}"),
+                });
+            }
+        }
+        failures
+    })
+    .await;
+    result.unwrap_or_else(|err| {
+        error!("XMP write-back task failed: {err}");
+        vec![WritebackError {
+            path: String::new(),
+            error: err.to_string(),
+        }]
+    })
+}
+
+async fn search(
+    State(state): State<AppState>,
+    Query(query): Query<SearchQuery>,
+) -> ApiResult<Json<SearchResponse>> {
+    let filter = normalize_search_filter(db::SearchFilter {
+        tags: query.tag.into_iter().collect(),
+        min_rating: query.min_rating,
+        taken_after: query.taken_after,
+        taken_before: query.taken_before,
+        taken_within_days: query.taken_within_days,
+        camera: query.camera,
+        folder: query.folder,
+        color_label: query.color_label,
+        flag: query.flag,
+    })?;
+    let files = db::search_files(&state.pool, &filter)
+        .await
+        .map_err(internal_error)?;
+    let entries = files.into_iter().map(entry_from_meta).collect();
+    Ok(Json(SearchResponse { entries }))
+}
+
+async fn list_saved_searches(
+    State(state): State<AppState>,
+) -> ApiResult<Json<SavedSearchesResponse>> {
+    let searches = saved_search_summaries(&state).await?;
+    Ok(Json(SavedSearchesResponse { searches }))
+}
+
+async fn create_saved_search(
+    State(state): State<AppState>,
+    Json(payload): Json<SavedSearchCreateRequest>,
+) -> ApiResult<Json<SavedSearchResponse>> {
+    let name = required_name(&payload.name, "Search name is required")?;
+    let filter = normalize_search_filter(payload.filter)?;
+    let search = db::create_saved_search(&state.pool, &name, &filter)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(SavedSearchResponse { search }))
+}
+
+async fn update_saved_search(
+    State(state): State<AppState>,
+    Json(payload): Json<SavedSearchUpdateRequest>,
+) -> ApiResult<Json<SavedSearchResponse>> {
+    let name = payload
+        .name
+        .as_deref()
+        .map(|name| required_name(name, "Search name is required"))
+        .transpose()?;
+    let filter = payload.filter.map(normalize_search_filter).transpose()?;
+    let search = db::update_saved_search(&state.pool, payload.id, name.as_deref(), filter.as_ref())
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(saved_search_not_found)?;
+    Ok(Json(SavedSearchResponse { search }))
+}
+
+async fn delete_saved_search(
+    State(state): State<AppState>,
+    Json(payload): Json<SavedSearchQuery>,
+) -> ApiResult<Json<FsResponse>> {
+    let deleted = db::delete_saved_search(&state.pool, payload.id)
+        .await
+        .map_err(internal_error)?;
+    if !deleted {
+        return Err(saved_search_not_found());
+    }
+    Ok(Json(FsResponse { success: true }))
+}
+
+async fn saved_search_results(
+    State(state): State<AppState>,
+    Query(query): Query<SavedSearchQuery>,
+) -> ApiResult<Json<SavedSearchResultsResponse>> {
+    let search = db::get_saved_search(&state.pool, query.id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(saved_search_not_found)?;
+    let files = db::search_files(&state.pool, &search.filter)
+        .await
+        .map_err(internal_error)?;
+    let entries = files.into_iter().map(entry_from_meta).collect();
+    Ok(Json(SavedSearchResultsResponse { search, entries }))
+}
+
+async fn saved_search_summaries(state: &AppState) -> ApiResult<Vec<SavedSearchSummary>> {
+    let searches = db::list_saved_searches(&state.pool)
+        .await
+        .map_err(internal_error)?;
+    let mut summaries = Vec::with_capacity(searches.len());
+    for search in searches {
+        let count = db::count_search_files(&state.pool, &search.filter)
+            .await
+            .map_err(internal_error)?;
+        summaries.push(SavedSearchSummary { search, count });
+    }
+    Ok(summaries)
+}
+
+fn normalize_search_filter(filter: db::SearchFilter) -> ApiResult<db::SearchFilter> {
+    let text = |value: Option<String>| {
+        value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    if let Some(rating) = filter.min_rating {
+        if !(0..=5).contains(&rating) {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Rating must be 0-5",
+            ));
+        }
+    }
+    let folder = match text(filter.folder) {
+        Some(folder) => Some(rel_to_string(&sanitize_relative(&folder)?)).filter(|value| !value.is_empty()),
+        None => None,
+    };
+
+    Ok(db::SearchFilter {
+        tags: tags::normalize_tags(&filter.tags),
+        min_rating: filter.min_rating,
+        taken_after: text(filter.taken_after),
+        taken_before: text(filter.taken_before),
+        taken_within_days: filter.taken_within_days,
+        camera: text(filter.camera),
+        folder,
+        color_label: text(filter.color_label).map(|label| label.to_lowercase()),
+        flag: text(filter.flag).map(|flag| flag.to_lowercase()),
+    })
+}
+
+fn saved_search_not_found() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "Saved search not found")
+}
+
+async fn list_albums(State(state): State<AppState>) -> ApiResult<Json<AlbumsResponse>> {
+    let albums = db::list_albums(&state.pool)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(AlbumsResponse { albums }))
+}
+
+async fn create_album(
+    State(state): State<AppState>,
+    Json(payload): Json<AlbumCreateRequest>,
+) -> ApiResult<Json<AlbumResponse>> {
+    let name = required_name(&payload.name, "Album name is required")?;
+    let description = payload
+        .description
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty());
+    let album = db::create_album(&state.pool, &name, description)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(AlbumResponse { album }))
+}
+
+async fn update_album(
+    State(state): State<AppState>,
+    Json(payload): Json<AlbumUpdateRequest>,
+) -> ApiResult<Json<AlbumResponse>> {
+    let name = payload
+        .name
+        .as_deref()
+        .map(|name| required_name(name, "Album name is required"))
+        .transpose()?;
+    // An empty description clears it; omitting the field leaves it unchanged.
+    let description = payload
+        .description
+        .as_deref()
+        .map(|value| Some(value.trim()).filter(|value| !value.is_empty()));
+    let album = db::update_album(&state.pool, payload.id, name.as_deref(), description)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(album_not_found)?;
+    Ok(Json(AlbumResponse { album }))
+}
+
+async fn delete_album(
+    State(state): State<AppState>,
+    Json(payload): Json<AlbumQuery>,
+) -> ApiResult<Json<FsResponse>> {
+    let deleted = db::delete_album(&state.pool, payload.id)
+        .await
+        .map_err(internal_error)?;
+    if !deleted {
+        return Err(album_not_found());
+    }
+    Ok(Json(FsResponse { success: true }))
+}
+
+async fn album_items(
+    State(state): State<AppState>,
+    Query(query): Query<AlbumQuery>,
+) -> ApiResult<Json<AlbumContentsResponse>> {
+    let album = load_album(&state, query.id).await?;
+    let entries = album_entries(&state, album.id).await?;
+    Ok(Json(AlbumContentsResponse { album, entries }))
+}
+
+async fn add_album_items(
+    State(state): State<AppState>,
+    Json(payload): Json<AlbumItemsRequest>,
+) -> ApiResult<Json<AlbumItemsResponse>> {
+    load_album(&state, payload.id).await?;
+    let root_canon = get_root_canon(&state).await?;
+
+    let mut paths = Vec::new();
+    let mut results = Vec::with_capacity(payload.paths.len());
+    for path in payload.paths {
+        match resolve_file_target(&root_canon, &path).await {
+            Ok(target) => {
+                paths.push(target.path);
+                results.push(BatchResult {
+                    path,
+                    success: true,
+                    error: None,
+                });
+            }
+            Err(err) => results.push(BatchResult {
+                path,
+                success: false,
+                error: Some(err.message),
+            }),
+        }
+    }
+
+    let changed = db::add_album_items(&state.pool, payload.id, &paths)
+        .await
+        .map_err(internal_error)?;
+    let album = load_album(&state, payload.id).await?;
+    Ok(Json(AlbumItemsResponse {
+        album,
+        changed,
+        results,
+    }))
+}
+
+async fn remove_album_items(
+    State(state): State<AppState>,
+    Json(payload): Json<AlbumItemsRequest>,
+) -> ApiResult<Json<AlbumItemsResponse>> {
+    load_album(&state, payload.id).await?;
+    let mut paths = Vec::with_capacity(payload.paths.len());
+    for path in &payload.paths {
+        paths.push(rel_to_string(&sanitize_relative(path)?));
+    }
+
+    let changed = db::remove_album_items(&state.pool, payload.id, &paths)
+        .await
+        .map_err(internal_error)?;
+    let album = load_album(&state, payload.id).await?;
+    Ok(Json(AlbumItemsResponse {
+        album,
+        changed,
+        results: Vec::new(),
+    }))
+}
+
+async fn reorder_album_items(
+    State(state): State<AppState>,
+    Json(payload): Json<AlbumItemsRequest>,
+) -> ApiResult<Json<AlbumContentsResponse>> {
+    load_album(&state, payload.id).await?;
+    let mut paths = Vec::with_capacity(payload.paths.len());
+    for path in &payload.paths {
+        paths.push(rel_to_string(&sanitize_relative(path)?));
+    }
+
+    db::reorder_album_items(&state.pool, payload.id, &paths)
+        .await
+        .map_err(internal_error)?;
+    let album = load_album(&state, payload.id).await?;
+    let entries = album_entries(&state, album.id).await?;
+    Ok(Json(AlbumContentsResponse { album, entries }))
+}
+
+async fn load_album(state: &AppState, id: i64) -> ApiResult<db::Album> {
+    db::get_album(&state.pool, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(album_not_found)
+}
+
+async fn album_entries(state: &AppState, id: i64) -> ApiResult<Vec<BrowseEntry>> {
+    let paths = db::list_album_paths(&state.pool, id)
+        .await
+        .map_err(internal_error)?;
+    let mut entries = Vec::with_capacity(paths.len());
+    for path in paths {
+        let meta = db::get_file_meta(&state.pool, &path)
+            .await
+            .map_err(internal_error)?
+            .unwrap_or_else(|| FileMeta {
+                path,
+                ..Default::default()
+            });
+        entries.push(entry_from_meta(meta));
+    }
+    Ok(entries)
+}
+
+fn required_name(raw: &str, message: &str) -> ApiResult<String> {
+    let name = raw.trim();
+    if name.is_empty() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, message));
+    }
+    Ok(name.to_string())
+}
+
+fn album_not_found() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "Album not found")
+}
+
+async fn list_history(
+    State(state): State<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> ApiResult<Json<HistoryResponse>> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let operations = db::list_history(&state.pool, limit)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(HistoryResponse { operations }))
+}
+
+async fn file_history(
+    State(state): State<AppState>,
+    Query(query): Query<FileHistoryQuery>,
+) -> ApiResult<Json<FileHistoryResponse>> {
+    let rel = sanitize_relative(&query.path)?;
+    let path = rel_to_string(&rel);
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let entries = db::list_file_history(&state.pool, &path, limit)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(FileHistoryResponse { path, entries }))
+}
+
+async fn undo_history(
+    State(state): State<AppState>,
+    Json(payload): Json<UndoRequest>,
+) -> ApiResult<Json<UndoResponse>> {
+    let count = payload.count.unwrap_or(1);
+    if !(1..=100).contains(&count) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Count must be 1-100",
+        ));
+    }
+
+    let outcome = db::undo_history(&state.pool, count)
+        .await
+        .map_err(internal_error)?;
+    let writeback_errors = write_back_restored(&state, &outcome.restored).await;
+
+    Ok(Json(UndoResponse {
+        undone: outcome.operations,
+        skipped: outcome.skipped,
+        files_restored: outcome.restored.len(),
+        conflicts: outcome.conflicts,
+        writeback_errors,
+    }))
+}
+
+/// Sets one field back to the value it had before a recorded change. Unlike
+/// undo this works on any entry and is itself recorded as a new operation.
+async fn revert_history(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<RevertRequest>,
+) -> ApiResult<Json<RevertResponse>> {
+    let entry = db::get_history_entry(&state.pool, payload.id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "History entry not found"))?;
+    let root_canon = get_root_canon(&state).await?;
+    let target = resolve_file_target(&root_canon, &entry.path).await?;
+
+    let mut updated = db::update_files(&state.pool, actor.edit("revert"), &[target], |meta| {
+        history::set_field(meta, &entry.field, &entry.old_value);
+    })
+    .await
+    .map_err(internal_error)?;
+    let meta = updated
+        .pop()
+        .ok_or_else(|| internal_error("file row missing after update"))?;
+    let value = history::field_value(&meta, &entry.field);
+    let writeback_errors = write_back_restored(
+        &state,
+        &[db::RestoredFile {
+            meta,
+            fields: vec![entry.field.clone()],
+        }],
+    )
+    .await;
+
+    Ok(Json(RevertResponse {
+        path: entry.path,
+        field: entry.field,
+        value,
+        writeback_errors,
+    }))
+}
+
+async fn write_back_restored(state: &AppState, restored: &[db::RestoredFile]) -> Vec<WritebackError> {
+    let touches = |file: &&db::RestoredFile, fields: &[&str]| {
+        file.fields.iter().any(|field| fields.contains(&field.as_str()))
+    };
+    let tag_changes: Vec<db::TagChange> = restored
+        .iter()
+        .filter(|file| touches(file, &["tags"]))
+        .map(|file| db::TagChange {
+            path: file.meta.path.clone(),
+            tags: file.meta.tags.clone(),
+        })
+        .collect();
+    let mut errors = write_back_tag_changes(state, &tag_changes).await;
+    let text_changes: Vec<db::TextChange> = restored
+        .iter()
+        .filter(|file| touches(file, &["title", "caption"]))
+        .map(|file| db::TextChange::of(&file.meta, touches(&file, &["title"]), touches(&file, &["caption"])))
+        .collect();
+    errors.extend(write_back_text_changes(state, text_changes).await);
+    errors
+}
+
+async fn catalog_export(
+    State(state): State<AppState>,
+    Query(query): Query<CatalogExportQuery>,
+) -> ApiResult<Response> {
+    let format = catalog::CatalogFormat::parse(query.format.as_deref())
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Format must be jsonl or csv"))?;
+
+    // Rows are read page by page as the body is sent, so large catalogs are
+    // never held in memory at once. A failed page ends the body with an
+    // error, which aborts the response instead of leaving the client a
+    // file that looks complete.
+    let pool = state.pool.clone();
+    let header = stream::iter(catalog::header(format).map(|header| Ok(Bytes::from(header))));
+    let pages = stream::unfold(Some(String::new()), move |after| {
+        let pool = pool.clone();
+        async move {
+            let after = after?;
+            match db::list_files_after(&pool, &after, CATALOG_EXPORT_PAGE).await {
+                Ok(page) => {
+                    let last = page.last()?.path.clone();
+                    let chunk: String = page.iter().map(|meta| catalog::encode(format, meta)).collect();
+                    Some((Ok(Bytes::from(chunk)), Some(last)))
+                }
+                Err(err) => {
+                    error!("Catalog export failed: {err}");
+                    Some((Err(io::Error::other(format!("Catalog export failed: {err}"))), None))
+                }
+            }
+        }
+    });
+
+    let mut response = Response::new(Body::from_stream(header.chain(pages)));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static(format.content_type()),
+    );
+    let filename = format!("raw-manager-catalog.{}", format.extension());
+    response.headers_mut().insert(
+        header::CONTENT_DISPOSITION,
+        header::HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))
+            .unwrap_or_else(|_| header::HeaderValue::from_static("attachment")),
+    );
+    Ok(response)
+}
+
+async fn catalog_import(
+    State(state): State<AppState>,
+    actor: Actor,
+    Query(query): Query<CatalogImportQuery>,
+    body: Body,
+) -> ApiResult<Json<CatalogImportResponse>> {
+    let format = catalog::CatalogFormat::parse(query.format.as_deref())
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Format must be jsonl or csv"))?;
+    let strategy = query.strategy.unwrap_or_default();
+    let dry_run = query.dry_run.unwrap_or(false);
+    let remap_from = query.remap_from.unwrap_or_default();
+    let remap_to = query.remap_to.unwrap_or_default();
+    let root_canon = get_root_canon(&state).await?;
+
+    let bytes = axum::body::to_bytes(body, CATALOG_IMPORT_LIMIT)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "Catalog file is too large"))?;
+    let text = String::from_utf8(bytes.to_vec())
+        .map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, "Catalog must be UTF-8 text"))?;
+
+    let mut results = Vec::new();
+    let mut records = Vec::new();
+    let mut record_lines = Vec::new();
+    for (line, record) in catalog::decode(format, &text) {
+        let prepared = match record {
+            Ok(record) => {
+                let path = catalog::remap_path(&record.meta.path, &remap_from, &remap_to);
+                match normalize_import_record(&root_canon, record, &path).await {
+                    Ok(prepared) => Ok(prepared),
+                    Err(err) => Err((path, err.message)),
+                }
+            }
+            Err(err) => Err((String::new(), err)),
+        };
+        match prepared {
+            Ok(prepared) => {
+                records.push(prepared);
+                record_lines.push(line);
+            }
+            Err((path, error)) => results.push(CatalogImportResult {
+                line,
+                path,
+                action: None,
+                error: Some(error),
+            }),
+        }
+    }
+    let failed = results.len();
+
+    let changes = db::import_files(&state.pool, actor.edit("catalog_import"), &records, strategy, dry_run)
+        .await
+        .map_err(internal_error)?;
+
+    let count = |action: db::ImportAction| changes.iter().filter(|change| change.action == action).count();
+    let (created, updated, unchanged, skipped) = (
+        count(db::ImportAction::Created),
+        count(db::ImportAction::Updated),
+        count(db::ImportAction::Unchanged),
+        count(db::ImportAction::Skipped),
+    );
+
+    let mut writeback_errors = Vec::new();
+    if !dry_run {
+        let tag_changes: Vec<db::TagChange> = changes
+            .iter()
+            .filter(|change| change.tags_changed)
+            .map(|change| db::TagChange {
+                path: change.meta.path.clone(),
+                tags: change.meta.tags.clone(),
+            })
+            .collect();
+        writeback_errors = write_back_tag_changes(&state, &tag_changes).await;
+        let text_changes: Vec<db::TextChange> = changes
+            .iter()
+            .filter(|change| change.title_changed || change.caption_changed)
+            .map(|change| db::TextChange::of(&change.meta, change.title_changed, change.caption_changed))
+            .collect();
+        writeback_errors.extend(write_back_text_changes(&state, text_changes).await);
+    }
+
+    results.extend(changes.into_iter().zip(record_lines).map(|(change, line)| CatalogImportResult {
+        line,
+        path: change.meta.path,
+        action: Some(change.action),
+        error: None,
+    }));
+    results.sort_by_key(|result| result.line);
+
+    Ok(Json(CatalogImportResponse {
+        dry_run,
+        strategy,
+        created,
+        updated,
+        unchanged,
+        skipped,
+        failed,
+        results,
+        writeback_errors,
+    }))
+}
+
+/// Validates one imported record the same way the edit endpoints would and
+/// resolves it to a file under the library root.
+async fn normalize_import_record(
+    root_canon: &Path,
+    mut record: catalog::CatalogRecord,
+    path: &str,
+) -> ApiResult<(db::BatchTarget, catalog::CatalogRecord)> {
+    if path.is_empty() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "Path is required"));
+    }
+    let target = resolve_file_target(root_canon, path).await?;
+    let text = |value: Option<String>| value.map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+    let meta = record.meta;
+    record.meta = FileMeta {
+        path: target.path.clone(),
+        user_rating: validate_rating(meta.user_rating)?,
+        tags: tags::normalize_tags(&meta.tags),
+        color_label: normalize_label(meta.color_label)?,
+        flag: normalize_flag(meta.flag)?,
+        title: text(meta.title),
+        caption: text(meta.caption),
+        notes: text(meta.notes),
+        ..meta
+    };
+    Ok((target, record))
+}
+
+async fn fs_mkdir(
+    State(state): State<AppState>,
+    Json(payload): Json<MkdirRequest>,
+) -> ApiResult<Json<FsResponse>> {
+    let root_canon = get_root_canon(&state).await?;
+    let rel = sanitize_relative(&payload.path)?;
+    if rel.as_os_str().is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Folder name is required",
+        ));
+    }
+
+    let full_path = root_canon.join(&rel);
+    let parent = full_path.parent().ok_or_else(|| {
+        ApiError::new(StatusCode::BAD_REQUEST, "Invalid folder path")
+    })?;
+    let parent_canon = tokio::fs::canonicalize(parent)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Parent folder not found"))?;
+
+    if !parent_canon.starts_with(&root_canon) {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+    }
+
+    if let Err(err) = tokio::fs::create_dir_all(&full_path).await {
+        return Err(map_fs_error(err, "Unable to create folder"));
+    }
+
+    Ok(Json(FsResponse { success: true }))
+}
+
+async fn fs_delete(
+    State(state): State<AppState>,
+    Json(payload): Json<DeleteRequest>,
+) -> ApiResult<Json<FsResponse>> {
+    if payload.paths.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "No paths provided",
+        ));
+    }
+    let root_canon = get_root_canon(&state).await?;
+    let paths = if payload.companions.unwrap_or(true) {
+        with_companions(&state.formats, &root_canon, payload.paths).await?
+    } else {
+        payload.paths
+    };
+
+    // Check every path before touching any of them, so a bad entry fails the
+    // whole request instead of leaving it half done.
+    let mut victims: Vec<(PathBuf, String, bool)> = Vec::new();
+    for path in paths {
+        let rel = sanitize_relative(&path)?;
+        if rel.as_os_str().is_empty() {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Invalid path",
+            ));
+        }
+        let rel_str = rel_to_string(&rel);
+        let full_path = root_canon.join(&rel);
+        let full_canon = tokio::fs::canonicalize(&full_path)
+            .await
+            .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Path not found"))?;
+
+        if !full_canon.starts_with(&root_canon) {
+            return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+        }
+
+        let meta = tokio::fs::metadata(&full_canon)
+            .await
+            .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Path not found"))?;
+
+        if meta.is_dir() && !payload.recursive {
+            let mut read_dir = tokio::fs::read_dir(&full_canon)
+                .await
+                .map_err(|err| map_fs_error(err, "Unable to delete folder"))?;
+            let has_entries = read_dir
+                .next_entry()
+                .await
+                .map_err(|err| map_fs_error(err, "Unable to delete folder"))?
+                .is_some();
+            if has_entries {
+                return Err(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    "Directory not empty. Enable recursive delete.",
+                ));
+            }
+        }
+        victims.push((full_canon, rel_str, meta.is_dir()));
+    }
+
+    // A path inside another selected folder goes away with that folder.
+    let folders: Vec<PathBuf> = victims
+        .iter()
+        .filter(|(_, _, is_dir)| *is_dir)
+        .map(|(full, _, _)| full.clone())
+        .collect();
+    victims.retain(|(full, _, _)| !folders.iter().any(|folder| full != folder && full.starts_with(folder)));
+    let mut seen = HashSet::new();
+    victims.retain(|(full, _, _)| seen.insert(full.clone()));
+
+    // Everything goes to the trash; only emptying the trash removes files.
+    let token = journal::staging_token();
+    let mut steps = Vec::with_capacity(victims.len());
+    let mut changes = Vec::with_capacity(victims.len());
+    for (index, (full, rel, is_dir)) in victims.into_iter().enumerate() {
+        let name = full
+            .file_name()
+            .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Invalid path"))?;
+        // Each volume mounted inside the library has its own trash, since
+        // a rename cannot cross filesystems.
+        let root_clone = root_canon.clone();
+        let full_clone = full.clone();
+        let volume = tokio::task::spawn_blocking(move || trash::volume_root(&root_clone, &full_clone))
+            .await
+            .map_err(internal_error)?
+            .map_err(|err| map_fs_error(err, "Unable to delete path"))?;
+        let volume_rel = volume.strip_prefix(&root_canon).unwrap_or(Path::new(""));
+        let trash_rel = trash::item_path(volume_rel, &token, index, name);
+        let trash_full = root_canon.join(&trash_rel);
+        if let Some(parent) = trash_full.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|err| map_fs_error(err, "Unable to create trash folder"))?;
+        }
+        let full_clone = full.clone();
+        let size = tokio::task::spawn_blocking(move || trash::disk_usage(&full_clone))
+            .await
+            .map_err(internal_error)?
+            .unwrap_or(0);
+        steps.push(db::JournalStep {
+            source: full,
+            target: trash_full,
+            is_dir,
+        });
+        changes.push(db::PathChange::Trash {
+            from: rel,
+            to: rel_to_string(&trash_rel),
+            is_dir,
+            size: size as i64,
+        });
+    }
+    run_journaled(&state, "trash", &steps, &changes, "Unable to delete path").await?;
+
+    Ok(Json(FsResponse { success: true }))
+}
+
+async fn list_trash(State(state): State<AppState>) -> ApiResult<Json<TrashResponse>> {
+    let items = db::list_trash(&state.pool)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(TrashResponse {
+        retention_days: state.trash_retention_days,
+        items,
+    }))
+}
+
+async fn restore_trash(
+    State(state): State<AppState>,
+    Json(payload): Json<TrashRestoreRequest>,
+) -> ApiResult<Json<TrashUpdateResponse>> {
+    if payload.ids.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "No items provided",
+        ));
+    }
+    let root_canon = get_root_canon(&state).await?;
+
+    let mut items = Vec::new();
+    let mut steps: Vec<db::JournalStep> = Vec::new();
+    let mut changes = Vec::new();
+    for id in payload.ids {
+        let item = db::get_trash_item(&state.pool, id)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "Trash item not found"))?;
+        let trash_full = root_canon.join(&item.trash_path);
+        let original_full = root_canon.join(&item.original_path);
+        if tokio::fs::symlink_metadata(&trash_full).await.is_err() {
+            return Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                "Trashed file is missing",
+            ));
+        }
+        if tokio::fs::symlink_metadata(&original_full).await.is_ok()
+            || steps.iter().any(|step| step.target == original_full)
+        {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("{} already exists", item.original_path),
+            ));
+        }
+        // The folder it was deleted from may be gone too; recreate it.
+        if let Some(parent) = original_full.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|err| map_fs_error(err, "Unable to restore path"))?;
+        }
+
+        steps.push(db::JournalStep {
+            source: trash_full,
+            target: original_full,
+            is_dir: item.is_dir,
+        });
+        changes.push(db::PathChange::Restore {
+            id: item.id,
+            from: item.trash_path.clone(),
+            to: item.original_path.clone(),
+            is_dir: item.is_dir,
+        });
+        items.push(item);
+    }
+
+    run_journaled(&state, "restore", &steps, &changes, "Unable to restore path").await?;
+    remove_trash_folders(&root_canon, &items).await;
+
+    Ok(Json(TrashUpdateResponse {
+        count: items.len(),
+    }))
+}
+
+async fn empty_trash(
+    State(state): State<AppState>,
+    Json(payload): Json<TrashEmptyRequest>,
+) -> ApiResult<Json<TrashUpdateResponse>> {
+    let items = match payload.ids {
+        Some(ids) => {
+            let mut items = Vec::with_capacity(ids.len());
+            for id in ids {
+                let item = db::get_trash_item(&state.pool, id)
+                    .await
+                    .map_err(internal_error)?
+                    .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "Trash item not found"))?;
+                items.push(item);
+            }
+            items
+        }
+        None => db::list_trash(&state.pool)
+            .await
+            .map_err(internal_error)?,
+    };
+    let count = items.len();
+    purge_trash(&state, items).await?;
+    Ok(Json(TrashUpdateResponse { count }))
+}
+
+/// Permanently removes trashed items and their catalog rows.
+async fn purge_trash(state: &AppState, items: Vec<db::TrashItem>) -> ApiResult<()> {
+    if items.is_empty() {
+        return Ok(());
+    }
+    let root_canon = get_root_canon(state).await?;
+    let token = journal::staging_token();
+    let mut steps = Vec::with_capacity(items.len());
+    let mut changes = Vec::with_capacity(items.len());
+    for item in &items {
+        let trash_full = root_canon.join(&item.trash_path);
+        changes.push(db::PathChange::Delete {
+            path: item.trash_path.clone(),
+            is_dir: item.is_dir,
+        });
+        // Already gone from disk: only the catalog rows need to go.
+        if tokio::fs::symlink_metadata(&trash_full).await.is_err() {
+            continue;
+        }
+        steps.push(db::JournalStep {
+            target: journal::staging_path(&trash_full, &token),
+            source: trash_full,
+            is_dir: item.is_dir,
+        });
+    }
+
+    let batch = run_journaled(state, "delete", &steps, &changes, "Unable to delete path").await?;
+    // The catalog no longer has these paths; anything left over here is
+    // retried at the next startup.
+    journal::purge(&steps).await;
+    db::journal_clear(&state.pool, batch)
+        .await
+        .map_err(internal_error)?;
+    remove_trash_folders(&root_canon, &items).await;
+    Ok(())
+}
+
+/// Drops the per-item folders inside the trash once they are empty.
+async fn remove_trash_folders(root_canon: &Path, items: &[db::TrashItem]) {
+    for item in items {
+        if let Some(parent) = root_canon.join(&item.trash_path).parent() {
+            let _ = tokio::fs::remove_dir(parent).await;
+        }
+    }
+}
+
+/// Empties trash items older than the retention period once an hour.
+fn spawn_trash_purge(state: AppState) {
+    let Some(days) = state.trash_retention_days else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            let cutoff = to_unix_seconds(Some(SystemTime::now())) - days * 86_400;
+            let expired = match db::list_trash(&state.pool).await {
+                Ok(items) => items
+                    .into_iter()
+                    .filter(|item| item.deleted_at < cutoff)
+                    .collect::<Vec<_>>(),
+                Err(err) => {
+                    error!("Failed to list trash: {err}");
+                    continue;
+                }
+            };
+            if expired.is_empty() {
+                continue;
+            }
+            let count = expired.len();
+            match purge_trash(&state, expired).await {
+                Ok(()) => info!("Purged {count} expired trash items"),
+                Err(err) => error!("Failed to purge trash: {}", err.message),
+            }
+        }
+    });
+}
+
+async fn fs_move(
+    State(state): State<AppState>,
+    Json(payload): Json<MoveRequest>,
+) -> ApiResult<Json<MoveResponse>> {
+    if payload.paths.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "No paths provided",
+        ));
+    }
+    let root_canon = get_root_canon(&state).await?;
+    let (dest_rel, dest_canon) = resolve_destination(&root_canon, &payload.destination).await?;
+    let paths = if payload.companions.unwrap_or(true) {
+        with_companions(&state.formats, &root_canon, payload.paths).await?
+    } else {
+        payload.paths
+    };
+
+    let mut steps = Vec::new();
+    let mut changes = Vec::new();
+    let mut crosses_device = false;
+    for path in paths {
+        let rel = sanitize_relative(&path)?;
+        if rel.as_os_str().is_empty() {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Invalid path",
+            ));
+        }
+        let rel_str = rel_to_string(&rel);
+        let full_path = root_canon.join(&rel);
+        let full_canon = tokio::fs::canonicalize(&full_path)
+            .await
+            .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Path not found"))?;
+
+        if !full_canon.starts_with(&root_canon) {
+            return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+        }
+
+        let meta = tokio::fs::metadata(&full_canon)
+            .await
+            .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Path not found"))?;
+
+        if meta.is_dir() && dest_canon.starts_with(&full_canon) {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Cannot move a folder into itself",
+            ));
+        }
+
+        let name = full_canon
+            .file_name()
+            .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Invalid path"))?;
+        let target_rel = join_rel(&dest_rel, name);
+        let target_rel_str = rel_to_string(&target_rel);
+        let target_full = root_canon.join(&target_rel);
+
+        if tokio::fs::metadata(&target_full).await.is_ok()
+            || steps.iter().any(|step: &db::JournalStep| step.target == target_full)
+        {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Destination already exists",
+            ));
+        }
+
+        crosses_device |= !transfer::same_device(&full_canon, &dest_canon)
+            .map_err(|err| map_fs_error(err, "Unable to move path"))?;
+        steps.push(db::JournalStep {
+            source: full_canon,
+            target: target_full,
+            is_dir: meta.is_dir(),
+        });
+        changes.push(db::PathChange::Move {
+            from: rel_str,
+            to: target_rel_str,
+            is_dir: meta.is_dir(),
+        });
+    }
+
+    if !crosses_device {
+        run_journaled(&state, "move", &steps, &changes, "Unable to move path").await?;
+        return Ok(Json(MoveResponse { success: true, job: None }));
+    }
+
+    // When anything has to be copied to another disk, the whole request
+    // becomes one job, so it is never left half done by a failed copy after
+    // the renames went through. The job still renames what it can.
+    let mut transfers = Vec::with_capacity(steps.len());
+    for (step, change) in steps.into_iter().zip(changes) {
+        let db::PathChange::Move { from, to, is_dir } = change else {
+            continue;
+        };
+        let size = tokio::task::spawn_blocking(move || trash::disk_usage(&step.source))
+            .await
+            .map_err(internal_error)?
+            .map_err(|err| map_fs_error(err, "Unable to move path"))?;
+        transfers.push(db::JobItem::new(from, to, is_dir, size as i64));
+    }
+    let job = start_job(&state, "move", &transfers, None).await?;
+
+    Ok(Json(MoveResponse { success: true, job: Some(job) }))
+}
+
+/// Copies files and folders into a folder. The copy runs as a background
+/// job, the same way a move to another disk does, and each copied file gets
+/// the original's catalog row.
+async fn fs_copy(
+    State(state): State<AppState>,
+    Json(payload): Json<CopyRequest>,
+) -> ApiResult<Json<CopyResponse>> {
+    if payload.paths.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "No paths provided",
+        ));
+    }
+    let root_canon = get_root_canon(&state).await?;
+    let (dest_rel, dest_canon) = resolve_destination(&root_canon, &payload.destination).await?;
+
+    let mut items = Vec::new();
+    let mut results = Vec::new();
+    let mut claimed = HashSet::new();
+    for path in payload.paths {
+        let (rel, full_canon, is_dir) = resolve_existing_path(&root_canon, &path).await?;
+        if is_dir && dest_canon.starts_with(&full_canon) {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Cannot copy a folder into itself",
+            ));
+        }
+
+        let name = full_canon
+            .file_name()
+            .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Invalid path"))?
+            .to_string_lossy()
+            .to_string();
+        let mut target_name = name.clone();
+        let mut action = CopyAction::Copied;
+        let target_full = dest_canon.join(&name);
+        if claimed.contains(&target_full) || tokio::fs::symlink_metadata(&target_full).await.is_ok() {
+            match payload.conflict {
+                CopyConflict::Fail => {
+                    return Err(ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        "Destination already exists",
+                    ));
+                }
+                CopyConflict::Skip => {
+                    results.push(CopyResult {
+                        path: rel,
+                        new_path: None,
+                        action: CopyAction::Skipped,
+                    });
+                    continue;
+                }
+                CopyConflict::Rename => {
+                    target_name = uploads::free_name(&dest_canon, &name, &claimed).await?;
+                    action = CopyAction::Renamed;
+                }
+            }
+        }
+        claimed.insert(dest_canon.join(&target_name));
+
+        let full_clone = full_canon.clone();
+        let size = tokio::task::spawn_blocking(move || trash::disk_usage(&full_clone))
+            .await
+            .map_err(internal_error)?
+            .map_err(|err| map_fs_error(err, "Unable to copy path"))?;
+        let target_rel = rel_to_string(&join_rel(&dest_rel, OsStr::new(&target_name)));
+        items.push(db::JobItem::new(rel.clone(), target_rel.clone(), is_dir, size as i64));
+        results.push(CopyResult {
+            path: rel,
+            new_path: Some(target_rel),
+            action,
+        });
+    }
+
+    let job = if items.is_empty() {
+        None
+    } else {
+        Some(start_job(&state, "copy", &items, None).await?)
+    };
+
+    Ok(Json(CopyResponse {
+        success: true,
+        job,
+        results,
+    }))
+}
+
+async fn fs_rename(
+    State(state): State<AppState>,
+    Json(payload): Json<RenameRequest>,
+) -> ApiResult<Json<RenameResponse>> {
+    let root_canon = get_root_canon(&state).await?;
+    let (rel, full_canon, is_dir) = resolve_existing_path(&root_canon, &payload.path).await?;
+    let name = validate_file_name(&payload.name)?;
+    let (target_rel, target_full) = sibling_path(&rel, &full_canon, &name);
+    if target_rel == rel {
+        return Ok(Json(RenameResponse {
+            path: rel,
+            companions: Vec::new(),
+        }));
+    }
+    let plan = RenamePlan {
+        full: full_canon,
+        rel,
+        target_full,
+        target_rel,
+    };
+    let extra = if !is_dir && payload.companions.unwrap_or(true) {
+        companion_renames(&state.formats, &plan).await?
+    } else {
+        Vec::new()
+    };
+
+    let mut steps = Vec::with_capacity(extra.len() + 1);
+    let mut changes = Vec::with_capacity(extra.len() + 1);
+    let mut companion_paths = Vec::with_capacity(extra.len());
+    let new_path = plan.target_rel.clone();
+    for (index, plan) in std::iter::once(plan).chain(extra).enumerate() {
+        if plan.target_rel == plan.rel {
+            continue;
+        }
+        if occupied_by_other(&plan.target_full, &plan.full).await {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("{} already exists", plan.target_rel),
+            ));
+        }
+        if index > 0 {
+            companion_paths.push(plan.target_rel.clone());
+        }
+        steps.push(db::JournalStep {
+            source: plan.full,
+            target: plan.target_full,
+            is_dir,
+        });
+        changes.push(db::PathChange::Move {
+            from: plan.rel,
+            to: plan.target_rel,
+            is_dir,
+        });
+    }
+    run_journaled(&state, "rename", &steps, &changes, "Unable to rename path").await?;
+
+    Ok(Json(RenameResponse {
+        path: new_path,
+        companions: companion_paths,
+    }))
+}
+
+/// Renames files from a template. Every new name is worked out and checked
+/// first; the renames only happen when none of them collide or fail, and
+/// never with `dry_run`.
+async fn fs_rename_batch(
+    State(state): State<AppState>,
+    Json(payload): Json<BatchRenameRequest>,
+) -> ApiResult<Json<BatchRenameResponse>> {
+    if payload.paths.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "No paths provided",
+        ));
+    }
+    let template = rename::Template::parse(&payload.template)
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))?;
+    let root_canon = get_root_canon(&state).await?;
+    let start = payload.start.unwrap_or(1);
+    let follow_companions = payload.companions.unwrap_or(true);
+
+    // A RAW listed together with its JPEG or sidecar is one shot: the RAW is
+    // renamed from the template and the rest follow it.
+    let shot_of = |path: &str| {
+        let rel = sanitize_relative(path).ok()?;
+        let name = rel.file_name()?.to_string_lossy().to_string();
+        let kind = companion_kind(&state.formats, &name);
+        let shot = (rel.parent().map(Path::to_path_buf), companions::group_key(&name).to_lowercase());
+        Some((shot, kind, name))
+    };
+    let mut raw_shots: HashMap<_, Vec<String>> = HashMap::new();
+    for (shot, kind, name) in payload.paths.iter().filter_map(|path| shot_of(path)) {
+        if kind == companions::CompanionKind::Raw {
+            raw_shots.entry(shot).or_default().push(name);
+        }
+    }
+    let mut paths = Vec::with_capacity(payload.paths.len());
+    let mut listed = HashSet::new();
+    for path in &payload.paths {
+        if follow_companions {
+            if !listed.insert(path.as_str()) {
+                continue;
+            }
+            if let Some((shot, kind, name)) = shot_of(path) {
+                let taken = raw_shots
+                    .get(&shot)
+                    .is_some_and(|raws| raws.iter().any(|raw| companions::follows(raw, &name)));
+                if taken && companions::CompanionKind::Raw.takes_along(kind) {
+                    continue;
+                }
+            }
+        }
+        paths.push(path);
+    }
+
+    let mut results = Vec::with_capacity(paths.len());
+    let mut planned = Vec::with_capacity(paths.len());
+    for (index, path) in paths.into_iter().enumerate() {
+        match plan_rename(&state, &root_canon, &template, path, start + index as u64).await {
+            Ok(plan) => {
+                let extra = if follow_companions {
+                    companion_renames(&state.formats, &plan).await?
+                } else {
+                    Vec::new()
+                };
+                for plan in std::iter::once(plan).chain(extra) {
+                    results.push(RenameResult {
+                        path: plan.rel.clone(),
+                        new_path: Some(plan.target_rel.clone()),
+                        error: None,
+                    });
+                    planned.push(Some(plan));
+                }
+            }
+            Err(err) => {
+                results.push(RenameResult {
+                    path: path.clone(),
+                    new_path: None,
+                    error: Some(err.message),
+                });
+                planned.push(None);
+            }
+        }
+    }
+
+    // Collisions: with another file in the batch, or with anything already
+    // on disk. Renames that keep their name are fine, and so is a name that
+    // another file of the batch gives up.
+    let renamed: HashSet<PathBuf> = planned
+        .iter()
+        .flatten()
+        .filter(|plan| plan.target_full != plan.full)
+        .map(|plan| plan.full.clone())
+        .collect();
+    let mut sources = HashSet::new();
+    let mut targets: HashMap<PathBuf, usize> = HashMap::new();
+    for plan in planned.iter().flatten() {
+        *targets.entry(plan.target_full.clone()).or_default() += 1;
+    }
+    for (result, plan) in results.iter_mut().zip(&planned) {
+        let Some(plan) = plan else {
+            continue;
+        };
+        let error = if !sources.insert(plan.full.clone()) {
+            Some("Listed more than once")
+        } else if targets.get(&plan.target_full).copied().unwrap_or(0) > 1 {
+            Some("Name collides with another file in the batch")
+        } else if plan.target_full != plan.full
+            && !renamed.contains(&plan.target_full)
+            && occupied_by_other(&plan.target_full, &plan.full).await
+        {
+            Some("Destination already exists")
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            result.error = Some(error.to_string());
+        }
+    }
+
+    // A file taking a name that another file of the batch gives up first
+    // moves to a temporary name and takes the new one once the others are
+    // done, so chains and swaps never overwrite anything.
+    let failed = results.iter().any(|result| result.error.is_some());
+    let token = journal::staging_token();
+    let mut steps = Vec::new();
+    let mut changes = Vec::new();
+    let mut parked = Vec::new();
+    let mut count = 0;
+    for plan in planned.into_iter().flatten() {
+        if plan.target_rel == plan.rel {
+            continue;
+        }
+        count += 1;
+        let waits = renamed.contains(&plan.target_full);
+        let (source, from) = if waits {
+            let temp_full = journal::rename_path(&plan.full, &token);
+            let temp_name = temp_full.file_name().unwrap_or_default();
+            let temp_rel = rel_to_string(&Path::new(&plan.rel).with_file_name(temp_name));
+            steps.push(db::JournalStep {
+                source: plan.full,
+                target: temp_full.clone(),
+                is_dir: false,
+            });
+            changes.push(db::PathChange::Move {
+                from: plan.rel,
+                to: temp_rel.clone(),
+                is_dir: false,
+            });
+            (temp_full, temp_rel)
+        } else {
+            (plan.full, plan.rel)
+        };
+        let step = db::JournalStep {
+            source,
+            target: plan.target_full,
+            is_dir: false,
+        };
+        let change = db::PathChange::Move {
+            from,
+            to: plan.target_rel,
+            is_dir: false,
+        };
+        if waits {
+            parked.push((step, change));
+        } else {
+            steps.push(step);
+            changes.push(change);
+        }
+    }
+    for (step, change) in parked {
+        steps.push(step);
+        changes.push(change);
+    }
+
+    if failed {
+        steps.clear();
+        changes.clear();
+        count = 0;
+    }
+    let applied = !payload.dry_run && !failed;
+    if applied {
+        run_journaled(&state, "rename", &steps, &changes, "Unable to rename path").await?;
+    }
+
+    Ok(Json(BatchRenameResponse {
+        dry_run: payload.dry_run,
+        applied,
+        renamed: count,
+        results,
+    }))
+}
+
+/// Works out the new name for one file of a batch rename.
+async fn plan_rename(
+    state: &AppState,
+    root_canon: &Path,
+    template: &rename::Template,
+    path: &str,
+    seq: u64,
+) -> ApiResult<RenamePlan> {
+    let (rel, full_canon, is_dir) = resolve_existing_path(root_canon, path).await?;
+    if is_dir {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Only files can be renamed from a template",
+        ));
+    }
+
+    let file_path = Path::new(&rel);
+    let stem = file_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = file_path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut camera = (None, None, None);
+    if template.needs_metadata() {
+        let stored = db::get_file_meta(&state.pool, &rel)
+            .await
+            .map_err(internal_error)?
+            // Rows scanned before camera make and model were extracted have
+            // neither; read those from the file.
+            .filter(|meta| {
+                meta.orientation.is_some() && (meta.camera_make.is_some() || meta.camera_model.is_some())
+            });
+        camera = match stored {
+            Some(meta) => (meta.taken_at, meta.camera_make, meta.camera_model),
+            None => {
+                let full_clone = full_canon.clone();
+                let read_exif = state.formats.reads_exif(&full_canon);
+                let extracted = tokio::task::spawn_blocking(move || metadata::read_metadata(&full_clone, read_exif))
+                    .await
+                    .map_err(internal_error)?
+                    .map_err(internal_error)?;
+                (extracted.taken_at, extracted.camera_make, extracted.camera_model)
+            }
+        };
+    }
+
+    let fields = rename::RenameFields {
+        stem: &stem,
+        ext: &ext,
+        seq,
+        taken_at: camera.0.as_deref(),
+        make: camera.1.as_deref(),
+        model: camera.2.as_deref(),
+        shoot: None,
+    };
+    let name = template
+        .render(&fields)
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))?;
+    let name = validate_file_name(&name)?;
+    let (target_rel, target_full) = sibling_path(&rel, &full_canon, &name);
+    Ok(RenamePlan {
+        full: full_canon,
+        rel,
+        target_full,
+        target_rel,
+    })
+}
+
+/// The renames that keep a RAW's companions named after it once it is
+/// renamed as `plan` says.
+async fn companion_renames(formats: &formats::FormatRegistry, plan: &RenamePlan) -> ApiResult<Vec<RenamePlan>> {
+    let new_name = Path::new(&plan.target_rel)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let new_key = companions::group_key(&new_name);
+    let mut plans = Vec::new();
+    for companion in find_followers(formats, &plan.full).await? {
+        let (rel, full) = sibling_path(&plan.rel, &plan.full, &companion);
+        let (target_rel, target_full) = sibling_path(&plan.rel, &plan.full, &companions::renamed(&companion, new_key));
+        plans.push(RenamePlan {
+            full,
+            rel,
+            target_full,
+            target_rel,
+        });
+    }
+    Ok(plans)
+}
+
+/// Names of the files next to `full` that belong to the same shot.
+async fn find_companions(full: &Path) -> ApiResult<Vec<String>> {
+    let (Some(dir), Some(name)) = (full.parent(), full.file_name()) else {
+        return Ok(Vec::new());
+    };
+    let dir = dir.to_path_buf();
+    let name = name.to_string_lossy().to_string();
+    tokio::task::spawn_blocking(move || companions::find(&dir, &name))
+        .await
+        .map_err(internal_error)?
+        .map_err(|err| map_fs_error(err, "Unable to list companion files"))
+}
+
+/// The companions that go along when the file at `full` is moved, deleted
+/// or renamed: a RAW's JPEG, HEIF and sidecars, nothing for other files.
+async fn find_followers(formats: &formats::FormatRegistry, full: &Path) -> ApiResult<Vec<String>> {
+    let kind = companion_kind(formats, &full.to_string_lossy());
+    if kind != companions::CompanionKind::Raw {
+        return Ok(Vec::new());
+    }
+    let name = full.file_name().unwrap_or_default().to_string_lossy();
+    Ok(find_companions(full)
+        .await?
+        .into_iter()
+        .filter(|companion| {
+            kind.takes_along(companion_kind(formats, companion))
+                && companions::follows(&name, companion)
+        })
+        .collect())
+}
+
+fn companion_kind(formats: &formats::FormatRegistry, name: &str) -> companions::CompanionKind {
+    if formats.is_raw(Path::new(name)) {
+        companions::CompanionKind::Raw
+    } else {
+        companions::CompanionKind::of(name)
+    }
+}
+
+/// Describes companion `name` of the file at library path `rel`.
+fn companion_file(formats: &formats::FormatRegistry, rel: &str, name: &str) -> CompanionFile {
+    CompanionFile {
+        name: name.to_string(),
+        path: rel_to_string(&Path::new(rel).with_file_name(name)),
+        kind: companion_kind(formats, name),
+    }
+}
+
+/// Adds the other half of every RAW+JPEG or RAW+HEIF pair among `targets`,
+/// so a rating or tag given to one file of a pair lands on both. Skipped
+/// when the request turns companions off.
+async fn with_pair_targets(
+    formats: &formats::FormatRegistry,
+    root_canon: &Path,
+    targets: Vec<db::BatchTarget>,
+    companions: Option<bool>,
+) -> ApiResult<Vec<db::BatchTarget>> {
+    if !companions.unwrap_or(true) {
+        return Ok(targets);
+    }
+    let mut seen: HashSet<String> = targets.iter().map(|target| target.path.clone()).collect();
+    let mut expanded = Vec::with_capacity(targets.len());
+    for target in targets {
+        let kind = companion_kind(formats, &target.path);
+        let full = root_canon.join(&target.path);
+        let mut partners = Vec::new();
+        for companion in find_companions(&full).await? {
+            if !kind.pairs_with(companion_kind(formats, &companion)) {
+                continue;
+            }
+            let rel = rel_to_string(&Path::new(&target.path).with_file_name(&companion));
+            if seen.insert(rel.clone()) {
+                partners.push(resolve_file_target(root_canon, &rel).await?);
+            }
+        }
+        expanded.push(target);
+        expanded.extend(partners);
+    }
+    Ok(expanded)
+}
+
+/// The JPEG shot alongside a RAW, and the key its previews are cached under,
+/// when companion previews are enabled and there is one.
+async fn companion_preview_source(
+    state: &AppState,
+    rel: &str,
+    full: &Path,
+) -> ApiResult<Option<(PathBuf, String)>> {
+    if !state.companion_previews || !state.formats.is_raw(full) {
+        return Ok(None);
+    }
+    let jpeg = find_companions(full)
+        .await?
+        .into_iter()
+        .find(|name| companions::CompanionKind::of(name) == companions::CompanionKind::Jpeg);
+    Ok(jpeg.map(|name| {
+        let key = rel_to_string(&Path::new(rel).with_file_name(&name));
+        (full.with_file_name(&name), key)
+    }))
+}
+
+/// Adds the companions of every listed file right after it, so a shot's
+/// sidecars and JPEG go wherever its RAW goes. Paths come back sanitized and
+/// without duplicates; ones that do not resolve are kept for the caller to
+/// report.
+async fn with_companions(
+    formats: &formats::FormatRegistry,
+    root_canon: &Path,
+    paths: Vec<String>,
+) -> ApiResult<Vec<String>> {
+    let mut expanded = Vec::with_capacity(paths.len());
+    let mut seen = HashSet::new();
+    for path in paths {
+        let rel = sanitize_relative(&path)?;
+        let rel_str = rel_to_string(&rel);
+        if !seen.insert(rel_str.clone()) {
+            continue;
+        }
+        expanded.push(rel_str);
+
+        let full = root_canon.join(&rel);
+        let is_file = tokio::fs::metadata(&full)
+            .await
+            .map(|meta| meta.is_file())
+            .unwrap_or(false);
+        if !is_file {
             continue;
         }
+        for companion in find_followers(formats, &full).await? {
+            let companion_rel = rel_to_string(&rel.with_file_name(&companion));
+            if seen.insert(companion_rel.clone()) {
+                expanded.push(companion_rel);
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+/// Checks a path the same way the other file operations do and returns its
+/// library path, canonical full path and whether it is a folder.
+async fn resolve_existing_path(root_canon: &Path, path: &str) -> ApiResult<(String, PathBuf, bool)> {
+    let rel = sanitize_relative(path)?;
+    if rel.as_os_str().is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Invalid path",
+        ));
+    }
+    let full_canon = tokio::fs::canonicalize(root_canon.join(&rel))
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Path not found"))?;
+    if !full_canon.starts_with(root_canon) {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+    }
+    let meta = tokio::fs::metadata(&full_canon)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Path not found"))?;
+    Ok((rel_to_string(&rel), full_canon, meta.is_dir()))
+}
 
-        let rel = path
-            .strip_prefix(&root_canon)
-            .unwrap_or(&path)
-            .to_string_lossy()
-            .to_string();
+/// Checks that a destination is an existing folder inside the library and
+/// returns its library path and canonical full path.
+async fn resolve_destination(root_canon: &Path, destination: &str) -> ApiResult<(PathBuf, PathBuf)> {
+    let dest_rel = sanitize_relative(destination)?;
+    let dest_canon = tokio::fs::canonicalize(root_canon.join(&dest_rel))
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Destination not found"))?;
 
-        let meta = entry.metadata().await.ok();
-        let (size, modified) = if let Some(meta) = meta {
-            let size = meta.len() as i64;
-            let modified = to_unix_seconds(meta.modified().ok());
-            (size, modified)
-        } else {
-            (0, 0)
-        };
+    if !dest_canon.starts_with(root_canon) {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid destination"));
+    }
 
-        let db_meta = db::get_file_meta(&state.pool, &rel)
-            .await
-            .map_err(internal_error)?;
+    let dest_meta = tokio::fs::metadata(&dest_canon)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Destination not found"))?;
+    if !dest_meta.is_dir() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Destination must be a folder",
+        ));
+    }
+    Ok((dest_rel, dest_canon))
+}
 
-        let (camera_rating, user_rating, tags, gps_lat, gps_lon, taken_at, orientation, needs_scan) =
-            match db_meta {
-                Some(db_meta) => {
-                    let is_fresh = db_meta.file_size == size
-                        && db_meta.last_modified == modified
-                        && db_meta.orientation.is_some();
-                    (
-                        db_meta.camera_rating,
-                        db_meta.user_rating,
-                        db_meta.tags,
-                        db_meta.gps_lat,
-                        db_meta.gps_lon,
-                        db_meta.taken_at,
-                        db_meta.orientation,
-                        !is_fresh,
-                    )
-                }
-                None => (None, None, Vec::new(), None, None, None, None, true),
-            };
+/// The library path and full path of `name` in the same folder as `rel`.
+fn sibling_path(rel: &str, full: &Path, name: &str) -> (String, PathBuf) {
+    let parent = Path::new(rel).parent().unwrap_or_else(|| Path::new(""));
+    (
+        rel_to_string(&join_rel(parent, OsStr::new(name))),
+        full.with_file_name(name),
+    )
+}
 
-        entries.push(BrowseEntry {
-            name,
-            path: rel,
-            kind: "file".to_string(),
-            size: Some(size),
-            modified: Some(modified),
-            camera_rating,
-            user_rating,
-            tags,
-            gps_lat,
-            gps_lon,
-            taken_at,
-            orientation,
-            needs_scan,
-        });
+/// Whether `target` exists as something other than `source`. A name that
+/// only differs in case resolves to the source itself on case-insensitive
+/// filesystems.
+async fn occupied_by_other(target: &Path, source: &Path) -> bool {
+    if tokio::fs::symlink_metadata(target).await.is_err() {
+        return false;
     }
+    tokio::fs::canonicalize(target).await.ok().as_deref() != Some(source)
+}
 
-    entries.sort_by(|a, b| match (a.kind.as_str(), b.kind.as_str()) {
-        ("dir", "file") => std::cmp::Ordering::Less,
-        ("file", "dir") => std::cmp::Ordering::Greater,
-        _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
-    });
-
-    Ok(Json(BrowseResponse {
-        path: rel.to_string_lossy().to_string(),
-        entries,
-    }))
+/// A single path component usable as a file or folder name.
+fn validate_file_name(raw: &str) -> ApiResult<String> {
+    let name = raw.trim();
+    let invalid = name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.contains(['/', '\\', '\0'])
+        || name == trash::TRASH_DIR
+        || name == uploads::STAGING_DIR
+        || journal::is_staged(name)
+        || transfer::is_partial(name);
+    if invalid {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid name: {name}"),
+        ));
+    }
+    Ok(name.to_string())
 }
 
-async fn file_metadata(
+async fn list_jobs(
     State(state): State<AppState>,
-    Query(query): Query<FileQuery>,
-) -> ApiResult<Json<FileMetaResponse>> {
-    let root_canon = get_root_canon(&state).await?;
-    let rel = sanitize_relative(&query.path)?;
-    let full_path = root_canon.join(&rel);
-    let full_canon = tokio::fs::canonicalize(&full_path)
+    Query(query): Query<JobsQuery>,
+) -> ApiResult<Json<JobsResponse>> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let jobs = db::list_jobs(&state.pool, limit)
         .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
+        .map_err(internal_error)?;
+    Ok(Json(JobsResponse { jobs }))
+}
 
-    if !full_canon.starts_with(&root_canon) {
-        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
-    }
+async fn job_status(
+    State(state): State<AppState>,
+    Query(query): Query<JobQuery>,
+) -> ApiResult<Json<JobResponse>> {
+    Ok(Json(load_job(&state, query.id).await?))
+}
 
-    let meta = tokio::fs::metadata(&full_canon)
+async fn resume_job(
+    State(state): State<AppState>,
+    Json(payload): Json<JobResumeRequest>,
+) -> ApiResult<Json<JobResponse>> {
+    load_job(&state, payload.id).await?;
+    let started = spawn_job(state.clone(), payload.id)
         .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
-    if !meta.is_file() {
-        return Err(ApiError::new(StatusCode::BAD_REQUEST, "Not a file"));
+        .map_err(internal_error)?;
+    if !started {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "Job is already running or finished",
+        ));
     }
+    Ok(Json(load_job(&state, payload.id).await?))
+}
 
-    let size = meta.len() as i64;
-    let modified = to_unix_seconds(meta.modified().ok());
+async fn load_job(state: &AppState, id: i64) -> ApiResult<JobResponse> {
+    let job = db::get_job(&state.pool, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "Job not found"))?;
+    let items = db::list_job_items(&state.pool, id)
+        .await
+        .map_err(internal_error)?;
+    Ok(JobResponse { job, items })
+}
 
-    let db_meta = db::get_file_meta(&state.pool, &query.path)
+async fn start_job(
+    state: &AppState,
+    kind: &str,
+    items: &[db::JobItem],
+    options: Option<&serde_json::Value>,
+) -> ApiResult<db::Job> {
+    let job = db::create_job(&state.pool, kind, items, options)
         .await
         .map_err(internal_error)?;
+    spawn_job(state.clone(), job.id)
+        .await
+        .map_err(internal_error)?;
+    Ok(job)
+}
 
-    let (camera_rating, gps_lat, gps_lon, taken_at, orientation, user_rating, tags) = match db_meta {
-        Some(existing)
-            if existing.file_size == size
-                && existing.last_modified == modified
-                && existing.orientation.is_some() =>
-        (
-            existing.camera_rating,
-            existing.gps_lat,
-            existing.gps_lon,
-            existing.taken_at,
-            existing.orientation,
-            existing.user_rating,
-            existing.tags,
-        ),
-        Some(existing) => {
-            let full_canon_clone = full_canon.clone();
-            let extracted = tokio::task::spawn_blocking(move || metadata::read_metadata(&full_canon_clone))
-                .await
-                .map_err(internal_error)?
-                .map_err(internal_error)?;
-            let new_meta = FileMeta {
-                path: query.path.clone(),
-                camera_rating: extracted.camera_rating,
-                user_rating: existing.user_rating,
-                tags: existing.tags.clone(),
-                gps_lat: extracted.gps_lat,
-                gps_lon: extracted.gps_lon,
-                taken_at: extracted.taken_at,
-                orientation: extracted.orientation.or(Some(0)),
-                file_size: size,
-                last_modified: modified,
-            };
-            db::upsert_file_meta(&state.pool, &new_meta)
-                .await
-                .map_err(internal_error)?;
-            (
-                new_meta.camera_rating,
-                new_meta.gps_lat,
-                new_meta.gps_lon,
-                new_meta.taken_at,
-                new_meta.orientation,
-                new_meta.user_rating,
-                new_meta.tags,
-            )
+/// Claims a queued or failed job and works on it in the background. Returns
+/// false when the job is not in a state that can be started.
+async fn spawn_job(state: AppState, id: i64) -> anyhow::Result<bool> {
+    if !db::claim_job(&state.pool, id).await? {
+        return Ok(false);
+    }
+    tokio::spawn(async move {
+        let error = run_transfer_job(&state, id).await.err().map(|err| format!("{err:#}"));
+        match &error {
+            Some(message) => error!("Job {id} failed: {message}"),
+            None => info!("Job {id} finished"),
         }
-        None => {
-            let full_canon_clone = full_canon.clone();
-            let extracted = tokio::task::spawn_blocking(move || metadata::read_metadata(&full_canon_clone))
-                .await
-                .map_err(internal_error)?
-                .map_err(internal_error)?;
-            let new_meta = FileMeta {
-                path: query.path.clone(),
-                camera_rating: extracted.camera_rating,
-                user_rating: None,
-                tags: Vec::new(),
-                gps_lat: extracted.gps_lat,
-                gps_lon: extracted.gps_lon,
-                taken_at: extracted.taken_at,
-                orientation: extracted.orientation.or(Some(0)),
-                file_size: size,
-                last_modified: modified,
-            };
-            db::upsert_file_meta(&state.pool, &new_meta)
-                .await
-                .map_err(internal_error)?;
-            (
-                new_meta.camera_rating,
-                new_meta.gps_lat,
-                new_meta.gps_lon,
-                new_meta.taken_at,
-                new_meta.orientation,
-                new_meta.user_rating,
-                new_meta.tags,
-            )
+        if let Err(err) = db::finish_job(&state.pool, id, error.as_deref()).await {
+            error!("Failed to record the end of job {id}: {err}");
         }
-    };
-
-    let name = full_path
-        .file_name()
-        .unwrap_or_else(|| OsStr::new(""))
-        .to_string_lossy()
-        .to_string();
-
-    Ok(Json(FileMetaResponse {
-        path: query.path,
-        name,
-        camera_rating,
-        user_rating,
-        tags,
-        gps_lat,
-        gps_lon,
-        taken_at,
-        orientation,
-        file_size: size,
-        last_modified: modified,
-    }))
+    });
+    Ok(true)
 }
 
-async fn file_preview(
-    State(state): State<AppState>,
-    Query(query): Query<PreviewQuery>,
-) -> ApiResult<Response> {
-    let root_canon = get_root_canon(&state).await?;
-    let rel = sanitize_relative(&query.path)?;
-    let full_path = root_canon.join(&rel);
-    let full_canon = tokio::fs::canonicalize(&full_path)
+/// Works through a move or copy job. Moves use this when the destination is
+/// on another filesystem, where a rename is impossible. Every file is
+/// copied and verified, and for a move removed from the source, before its
+/// catalog row follows it, so the job can stop at any point and continue
+/// later with whatever is left. Ingest jobs are handed to `run_ingest_job`.
+async fn run_transfer_job(state: &AppState, id: i64) -> anyhow::Result<()> {
+    let root_canon = get_root_canon(state)
         .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
-
-    if !full_canon.starts_with(&root_canon) {
-        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+        .map_err(|err| anyhow::anyhow!(err.message))?;
+    let job = db::get_job(&state.pool, id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("job {id} not found"))?;
+    if job.kind == "ingest" {
+        return run_ingest_job(state, &root_canon, &job).await;
+    }
+    let copy = job.kind == "copy";
+    for item in db::list_job_items(&state.pool, id).await? {
+        if item.state == "done" {
+            continue;
+        }
+        let source = root_canon.join(&item.source);
+        let target = root_canon.join(&item.target);
+        if !copy && rename_job_item(&source, &target).await {
+            db::add_job_bytes(&state.pool, id, item.size).await?;
+        } else if item.is_dir {
+            transfer_tree(state, id, copy, &item, &source, &target).await?;
+        } else {
+            transfer_file(state, id, copy, &item.source, &item.target, &source, &target).await?;
+        }
+        db::finish_job_item(&state.pool, &item, !copy).await?;
     }
+    Ok(())
+}
 
-    let kind = match query.kind.as_deref() {
-        Some("thumb") => metadata::PreviewKind::Thumb,
-        _ => metadata::PreviewKind::Full,
+/// Moves an item of a move job with a plain rename when it stays on its
+/// filesystem. Returns false when it has to be copied instead.
+async fn rename_job_item(source: &Path, target: &Path) -> bool {
+    let Some(parent) = target.parent() else {
+        return false;
     };
-    let preview_path = metadata::preview_cache_path(&state.preview_dir, &query.path, kind);
-    let full_canon_clone = full_canon.clone();
-    let preview_path_clone = preview_path.clone();
-    let generated = tokio::task::spawn_blocking(move || {
-        metadata::ensure_preview(&full_canon_clone, &preview_path_clone, kind)
-    })
-    .await
-    .map_err(internal_error)?
-    .map_err(internal_error)?;
-
-    if !generated {
-        return Err(ApiError::new(
-            StatusCode::NOT_FOUND,
-            "No preview available",
-        ));
+    if tokio::fs::symlink_metadata(target).await.is_ok() {
+        return false;
     }
-
-    let file = tokio::fs::File::open(&preview_path)
+    let (source_clone, parent_clone) = (source.to_path_buf(), parent.to_path_buf());
+    let same_device = tokio::task::spawn_blocking(move || transfer::same_device(&source_clone, &parent_clone))
         .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Preview not found"))?;
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
-    let mut response = Response::new(body);
-    response.headers_mut().insert(
-        header::CONTENT_TYPE,
-        header::HeaderValue::from_static("image/jpeg"),
-    );
-    Ok(response)
+        .ok()
+        .and_then(|result| result.ok());
+    same_device == Some(true) && tokio::fs::rename(source, target).await.is_ok()
 }
 
-async fn file_download(
-    State(state): State<AppState>,
-    Query(query): Query<FileQuery>,
-) -> ApiResult<Response> {
-    let root_canon = get_root_canon(&state).await?;
-    let rel = sanitize_relative(&query.path)?;
-    let full_path = root_canon.join(&rel);
-    let full_canon = tokio::fs::canonicalize(&full_path)
+async fn transfer_tree(
+    state: &AppState,
+    id: i64,
+    copy: bool,
+    item: &db::JobItem,
+    source: &Path,
+    target: &Path,
+) -> anyhow::Result<()> {
+    let source_clone = source.to_path_buf();
+    let entries = match tokio::task::spawn_blocking(move || transfer::list_tree(&source_clone)).await? {
+        Ok(entries) => entries,
+        // A move finished before an interruption; only the catalog is left.
+        Err(err) if !copy && err.kind() == io::ErrorKind::NotFound && tokio::fs::metadata(target).await.is_ok() => {
+            return Ok(());
+        }
+        Err(err) => return Err(anyhow::Error::new(err).context(format!("Unable to read {}", item.source))),
+    };
+
+    tokio::fs::create_dir_all(target)
         .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
+        .with_context(|| format!("Unable to create {}", item.target))?;
+    let mut folders = Vec::new();
+    for entry in entries {
+        match entry {
+            transfer::TreeEntry::Dir(rel) => {
+                tokio::fs::create_dir_all(target.join(&rel)).await?;
+                folders.push(rel);
+            }
+            transfer::TreeEntry::File(rel) => {
+                let from = rel_to_string(&Path::new(&item.source).join(&rel));
+                let to = rel_to_string(&Path::new(&item.target).join(&rel));
+                transfer_file(state, id, copy, &from, &to, &source.join(&rel), &target.join(&rel)).await?;
+            }
+            transfer::TreeEntry::Symlink(rel) => {
+                let (link_source, link_target) = (source.join(&rel), target.join(&rel));
+                let link_source_clone = link_source.clone();
+                tokio::task::spawn_blocking(move || transfer::copy_symlink(&link_source_clone, &link_target))
+                    .await??;
+                if !copy {
+                    tokio::fs::remove_file(&link_source).await?;
+                }
+            }
+        }
+    }
+    if copy {
+        return Ok(());
+    }
 
-    if !full_canon.starts_with(&root_canon) {
-        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+    // Deepest folders first. A folder that gained files while the job ran
+    // is left in place rather than deleted with them.
+    for rel in folders.iter().rev() {
+        let _ = tokio::fs::remove_dir(source.join(rel)).await;
     }
+    if let Err(err) = tokio::fs::remove_dir(source).await {
+        error!("Left {} in place after moving it: {err}", source.display());
+    }
+    Ok(())
+}
 
-    let file = tokio::fs::File::open(&full_canon)
-        .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
+async fn transfer_file(
+    state: &AppState,
+    id: i64,
+    copy: bool,
+    from: &str,
+    to: &str,
+    source: &Path,
+    target: &Path,
+) -> anyhow::Result<()> {
+    if tokio::fs::symlink_metadata(source).await.is_err() {
+        if !copy && tokio::fs::symlink_metadata(target).await.is_ok() {
+            return Ok(());
+        }
+        anyhow::bail!("{from} is missing");
+    }
+    let (source_clone, target_clone) = (source.to_path_buf(), target.to_path_buf());
+    let size = tokio::task::spawn_blocking(move || transfer::copy_verified(&source_clone, &target_clone))
+        .await?
+        .with_context(|| format!("Unable to copy {from} to {to}"))?;
+    if copy {
+        db::copy_job_file(&state.pool, id, from, to, size as i64).await?;
+    } else {
+        tokio::fs::remove_file(source)
+            .await
+            .with_context(|| format!("Unable to remove {from}"))?;
+        db::move_job_file(&state.pool, id, from, to, size as i64).await?;
+    }
+    Ok(())
+}
 
-    let filename = full_canon
-        .file_name()
-        .unwrap_or_else(|| OsStr::new("raw"))
-        .to_string_lossy()
-        .to_string();
+async fn list_ingest_sources(State(state): State<AppState>) -> Json<IngestSourcesResponse> {
+    Json(IngestSourcesResponse {
+        sources: state.ingest_sources.to_vec(),
+        template: state.ingest_template.clone(),
+    })
+}
 
-    let mut response = Response::new(body);
-    response.headers_mut().insert(
-        header::CONTENT_TYPE,
-        header::HeaderValue::from_str(
-            mime_guess::from_path(&full_canon)
-                .first_or_octet_stream()
-                .as_ref(),
-        )
-        .unwrap_or_else(|_| header::HeaderValue::from_static("application/octet-stream")),
-    );
-    response.headers_mut().insert(
-        header::CONTENT_DISPOSITION,
-        header::HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))
-            .unwrap_or_else(|_| header::HeaderValue::from_static("attachment")),
-    );
-    Ok(response)
+/// Starts copying a memory card or drop folder into dated folders under a
+/// library folder. The files are listed now and copied by a background job.
+async fn start_ingest(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<IngestRequest>,
+) -> ApiResult<Json<IngestResponse>> {
+    let root_canon = get_root_canon(&state).await?;
+    let source = resolve_ingest_source(&state, &payload.source).await?;
+    let (dest_rel, _) = resolve_destination(&root_canon, &payload.destination).await?;
+    let non_empty = |value: Option<String>| {
+        value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let template = non_empty(payload.template).unwrap_or_else(|| state.ingest_template.clone());
+    rename::Template::parse(&template).map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))?;
+
+    let formats = state.formats.clone();
+    let source_clone = source.clone();
+    let items = tokio::task::spawn_blocking(move || list_ingest_files(&formats, &source_clone))
+        .await
+        .map_err(internal_error)?
+        .map_err(|err| map_fs_error(err, "Unable to read source"))?;
+    if items.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("No supported files in {}", source.display()),
+        ));
+    }
+
+    let options = ingest::IngestOptions {
+        destination: rel_to_string(&dest_rel),
+        template,
+        shoot: non_empty(payload.shoot),
+        tags: tags::normalize_tags(&payload.tags),
+        copyright: non_empty(payload.copyright),
+        actor: actor.0,
+        hot_folder: None,
+        move_source: false,
+    };
+    let options = serde_json::to_value(&options).map_err(internal_error)?;
+    let job = start_job(&state, "ingest", &items, Some(&options)).await?;
+    Ok(Json(IngestResponse { success: true, job }))
 }
 
-async fn set_rating(
-    State(state): State<AppState>,
-    Json(payload): Json<RatingRequest>,
-) -> ApiResult<Json<RatingResponse>> {
-    let root_canon = get_root_canon(&state).await?;
-    let rel = sanitize_relative(&payload.path)?;
-    let full_path = root_canon.join(&rel);
-    let full_canon = tokio::fs::canonicalize(&full_path)
+/// Checks that an ingest source is an existing folder inside one of the
+/// configured sources and returns its canonical path.
+async fn resolve_ingest_source(state: &AppState, source: &str) -> ApiResult<PathBuf> {
+    if state.ingest_sources.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "No ingest sources are configured",
+        ));
+    }
+    let source = tokio::fs::canonicalize(source.trim())
         .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
-
-    if !full_canon.starts_with(&root_canon) {
-        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Source not found"))?;
+    // Canonicalized now rather than at startup: a card inserted after the
+    // server started is only there from then on.
+    let mut allowed = false;
+    for configured in state.ingest_sources.iter() {
+        if let Ok(canon) = tokio::fs::canonicalize(configured).await {
+            if source.starts_with(&canon) {
+                allowed = true;
+                break;
+            }
+        }
+    }
+    if !allowed {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "Source is outside the ingest sources",
+        ));
     }
+    let meta = tokio::fs::metadata(&source)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Source not found"))?;
+    if !meta.is_dir() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Source must be a folder",
+        ));
+    }
+    Ok(source)
+}
 
-    if let Some(rating) = payload.rating {
-        if !(0..=5).contains(&rating) {
-            return Err(ApiError::new(
-                StatusCode::BAD_REQUEST,
-                "Rating must be 0-5",
-            ));
+/// The files under `source` to ingest: every supported format, plus the
+/// sidecars and JPEG or HEIF files shot alongside them. Hidden files and
+/// folders (`.Trashes` and the like) are left out. Files of one shot are
+/// kept together in `ingest::copy_rank` order.
+fn list_ingest_files(formats: &formats::FormatRegistry, source: &Path) -> io::Result<Vec<db::JobItem>> {
+    let files: Vec<PathBuf> = transfer::list_tree(source)?
+        .into_iter()
+        .filter_map(|entry| match entry {
+            transfer::TreeEntry::File(rel) => Some(rel),
+            _ => None,
+        })
+        .filter(|rel| {
+            !rel.components().any(|part| part.as_os_str().to_string_lossy().starts_with('.'))
+        })
+        .map(|rel| source.join(rel))
+        .collect();
+
+    let managed: HashSet<(String, String)> = files
+        .iter()
+        .filter(|path| formats.is_managed(path))
+        .map(|path| ingest::shot_key(&path.to_string_lossy()))
+        .collect();
+    let mut items = Vec::new();
+    for path in files {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let source = path.to_string_lossy().to_string();
+        let companion = matches!(
+            companions::CompanionKind::of(&name),
+            companions::CompanionKind::Sidecar | companions::CompanionKind::Jpeg | companions::CompanionKind::Heif
+        ) && managed.contains(&ingest::shot_key(&source));
+        if !formats.is_managed(&path) && !companion {
+            continue;
         }
+        let size = std::fs::metadata(&path)?.len() as i64;
+        items.push(db::JobItem::new(source, String::new(), false, size));
     }
 
-    let meta = tokio::fs::metadata(&full_canon)
-        .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
-    let size = meta.len() as i64;
-    let modified = to_unix_seconds(meta.modified().ok());
+    let rank = |item: &db::JobItem| {
+        let kind = companion_kind(formats, &item.source);
+        (ingest::shot_key(&item.source), ingest::copy_rank(kind), item.source.clone())
+    };
+    items.sort_by_cached_key(rank);
+    Ok(items)
+}
 
-    db::upsert_user_rating(&state.pool, &payload.path, payload.rating, size, modified)
+/// Where the files of one shot go: a folder rendered from the template and,
+/// when its name was taken by different content, a new shot name.
+struct ShotPlacement {
+    folder_rel: PathBuf,
+    folder_full: PathBuf,
+    key: Option<String>,
+}
+
+/// Works through an ingest job. Each file is hashed first and skipped when
+/// the same content was ingested before and is still in the library, or
+/// already sits at its target. Everything else is copied and verified, read
+/// into the catalog and given the copyright; ingests that move files then
+/// remove the source. Tags are applied once all files are in, as a single
+/// edit.
+async fn run_ingest_job(state: &AppState, root_canon: &Path, job: &db::Job) -> anyhow::Result<()> {
+    let options: ingest::IngestOptions = serde_json::from_value(
+        job.options
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("ingest job {} has no options", job.id))?,
+    )?;
+    let template = rename::Template::parse(&options.template).map_err(anyhow::Error::msg)?;
+    let (dest_rel, dest_canon) = resolve_destination(root_canon, &options.destination)
         .await
-        .map_err(internal_error)?;
+        .map_err(|err| anyhow::anyhow!(err.message))?;
+    let items = db::list_job_items(&state.pool, job.id).await?;
+
+    let run = IngestRun {
+        state,
+        root_canon,
+        job_id: job.id,
+        options: &options,
+        template: &template,
+        dest_rel: &dest_rel,
+        dest_canon: &dest_canon,
+        items: &items,
+    };
+    let mut shots: HashMap<(String, String), ShotPlacement> = HashMap::new();
+    let mut failures = Vec::new();
+    for item in &items {
+        if item.state != "pending" {
+            continue;
+        }
+        if let Err(err) = ingest_item(&run, item, &mut shots).await {
+            // One unreadable file must not keep the rest of the card out.
+            error!("Ingest job {}: {err:#}", job.id);
+            db::fail_job_item(&state.pool, item).await?;
+            failures.push(format!("{err:#}"));
+        }
+    }
 
-    Ok(Json(RatingResponse {
-        user_rating: payload.rating,
-    }))
+    if !options.tags.is_empty() {
+        tag_ingested_files(state, root_canon, job.id, &options).await?;
+    }
+    match failures.first() {
+        Some(first) => anyhow::bail!("{} files could not be ingested, the first: {first}", failures.len()),
+        None => Ok(()),
+    }
 }
 
-async fn set_tags(
-    State(state): State<AppState>,
-    Json(payload): Json<TagsRequest>,
-) -> ApiResult<Json<TagsResponse>> {
-    let root_canon = get_root_canon(&state).await?;
-    let rel = sanitize_relative(&payload.path)?;
-    let full_path = root_canon.join(&rel);
-    let full_canon = tokio::fs::canonicalize(&full_path)
+/// Whether the library file at `rel`, where a file was ingested to before,
+/// still has that file's size and SHA-256. A file in the trash, or one
+/// replaced since, does not count, so the ingest copies the file again.
+async fn holds_ingested(root_canon: &Path, rel: &str, expected: (u64, [u8; 32])) -> bool {
+    if trash::is_trash_path(Path::new(rel)) {
+        return false;
+    }
+    let full = root_canon.join(rel);
+    match tokio::fs::metadata(&full).await {
+        Ok(meta) if meta.is_file() && meta.len() == expected.0 => {}
+        _ => return false,
+    }
+    tokio::task::spawn_blocking(move || transfer::file_digest(&full))
         .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
+        .ok()
+        .and_then(|digest| digest.ok())
+        == Some(expected)
+}
 
-    if !full_canon.starts_with(&root_canon) {
-        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
-    }
+/// What the files of one ingest job share.
+struct IngestRun<'a> {
+    state: &'a AppState,
+    root_canon: &'a Path,
+    job_id: i64,
+    options: &'a ingest::IngestOptions,
+    template: &'a rename::Template,
+    dest_rel: &'a Path,
+    dest_canon: &'a Path,
+    items: &'a [db::JobItem],
+}
 
-    let mut seen = HashSet::new();
-    let mut tags = Vec::new();
-    for tag in payload.tags {
-        let trimmed = tag.trim();
-        if trimmed.is_empty() {
-            continue;
+/// Copies one file of an ingest job into the folder of its shot, or skips
+/// it when the library already has it.
+async fn ingest_item(
+    run: &IngestRun<'_>,
+    item: &db::JobItem,
+    shots: &mut HashMap<(String, String), ShotPlacement>,
+) -> anyhow::Result<()> {
+    let IngestRun {
+        state,
+        root_canon,
+        job_id,
+        options,
+        template,
+        dest_rel,
+        dest_canon,
+        items,
+    } = *run;
+    let source = PathBuf::from(&item.source);
+    let source_clone = source.clone();
+    let (size, digest) = tokio::task::spawn_blocking(move || transfer::file_digest(&source_clone))
+        .await?
+        .with_context(|| format!("Unable to read {}", item.source))?;
+    let hash = hex::encode(digest);
+    if let Some(existing) = db::find_ingested(&state.pool, &hash).await? {
+        if holds_ingested(root_canon, &existing, (size, digest)).await {
+            db::finish_ingest_item(&state.pool, job_id, item, &existing, true, &hash).await?;
+            release_ingest_source(options, item).await?;
+            return Ok(());
         }
-        let normalized = trimmed.to_string();
-        if seen.insert(normalized.to_lowercase()) {
-            tags.push(normalized);
+    }
+
+    let shot_key = ingest::shot_key(&item.source);
+    if !shots.contains_key(&shot_key) {
+        let placement = place_shot(state, template, options, items, item, (dest_rel, dest_canon)).await?;
+        shots.insert(shot_key.clone(), placement);
+    }
+    let shot = shots.get_mut(&shot_key).expect("shot placed above");
+    let source_name = source
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut name = match &shot.key {
+        Some(key) => companions::renamed(&source_name, key),
+        None => source_name.clone(),
+    };
+
+    let target = shot.folder_full.join(&name);
+    if tokio::fs::symlink_metadata(&target).await.is_ok() {
+        let target_clone = target.clone();
+        let existing = tokio::task::spawn_blocking(move || transfer::file_digest(&target_clone)).await?;
+        if existing.ok() == Some((size, digest)) {
+            let rel = rel_to_string(&shot.folder_rel.join(&name));
+            db::finish_ingest_item(&state.pool, job_id, item, &rel, true, &hash).await?;
+            release_ingest_source(options, item).await?;
+            return Ok(());
         }
+        // Different content under the same name: the whole shot moves
+        // to a free name so its files stay together.
+        let base = companions::group_key(&name).to_string();
+        let key = (1..10_000)
+            .map(|n| format!("{base}-{n}"))
+            .find(|key| !shot.folder_full.join(companions::renamed(&name, key)).exists())
+            .ok_or_else(|| anyhow::anyhow!("No free name for {name}"))?;
+        name = companions::renamed(&name, &key);
+        shot.key = Some(key);
     }
 
-    let meta = tokio::fs::metadata(&full_canon)
+    let target = shot.folder_full.join(&name);
+    let rel = rel_to_string(&shot.folder_rel.join(&name));
+    let (source_clone, target_clone) = (source.clone(), target.clone());
+    tokio::task::spawn_blocking(move || transfer::copy_verified(&source_clone, &target_clone))
+        .await?
+        .with_context(|| format!("Unable to copy {} to {rel}", item.source))?;
+    db::finish_ingest_item(&state.pool, job_id, item, &rel, false, &hash).await?;
+    release_ingest_source(options, item).await?;
+
+    if !state.formats.is_managed(&target) {
+        return Ok(());
+    }
+    let meta = tokio::fs::metadata(&target).await?;
+    scan_file(state, &rel, &target, meta.len() as i64, to_unix_seconds(meta.modified().ok()))
         .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "File not found"))?;
-    let size = meta.len() as i64;
-    let modified = to_unix_seconds(meta.modified().ok());
+        .map_err(|err| anyhow::anyhow!(err.message))?;
+    if let Some(copyright) = options.copyright.clone() {
+        let target_clone = target.clone();
+        tokio::task::spawn_blocking(move || metadata::write_sidecar_rights(&target_clone, &copyright))
+            .await?
+            .with_context(|| format!("Unable to write the copyright of {rel}"))?;
+    }
+    spawn_file_scan(state.clone(), rel, target);
+    Ok(())
+}
 
-    db::upsert_tags(&state.pool, &payload.path, &tags, size, modified)
+async fn list_hot_folders(State(state): State<AppState>) -> ApiResult<Json<HotFoldersResponse>> {
+    let folders = db::list_hot_folders(&state.pool)
         .await
         .map_err(internal_error)?;
-
-    Ok(Json(TagsResponse { tags }))
+    Ok(Json(HotFoldersResponse { folders }))
 }
 
-async fn list_tags(State(state): State<AppState>) -> ApiResult<Json<TagsListResponse>> {
-    let tags = db::list_tags(&state.pool)
+/// Starts watching a folder. Settled RAW files that appear in it are moved
+/// into the destination the way an ingest files them.
+async fn create_hot_folder(
+    State(state): State<AppState>,
+    Json(payload): Json<HotFolderCreateRequest>,
+) -> ApiResult<Json<HotFolderResponse>> {
+    let path = resolve_ingest_source(&state, &payload.path).await?;
+    let destination = resolve_hot_folder_destination(&state, &payload.destination).await?;
+    let settings = normalize_hot_folder_settings(payload.settings)?;
+    let path = path.to_string_lossy().to_string();
+    let exists = db::list_hot_folders(&state.pool)
         .await
-        .map_err(internal_error)?;
-    Ok(Json(TagsListResponse { tags }))
+        .map_err(internal_error)?
+        .iter()
+        .any(|folder| folder.path == path);
+    if exists {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "Folder is already a hot folder",
+        ));
+    }
+    let folder = db::create_hot_folder(
+        &state.pool,
+        &path,
+        &destination,
+        &settings,
+        payload.enabled.unwrap_or(true),
+    )
+    .await
+    .map_err(internal_error)?;
+    Ok(Json(HotFolderResponse { folder }))
 }
 
-async fn fs_mkdir(
+async fn update_hot_folder(
     State(state): State<AppState>,
-    Json(payload): Json<MkdirRequest>,
+    Json(payload): Json<HotFolderUpdateRequest>,
+) -> ApiResult<Json<HotFolderResponse>> {
+    let destination = match payload.destination {
+        Some(destination) => Some(resolve_hot_folder_destination(&state, &destination).await?),
+        None => None,
+    };
+    let settings = payload.settings.map(normalize_hot_folder_settings).transpose()?;
+    let folder = db::update_hot_folder(
+        &state.pool,
+        payload.id,
+        destination.as_deref(),
+        settings.as_ref(),
+        payload.enabled,
+    )
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(hot_folder_not_found)?;
+    Ok(Json(HotFolderResponse { folder }))
+}
+
+async fn delete_hot_folder(
+    State(state): State<AppState>,
+    Json(payload): Json<HotFolderQuery>,
 ) -> ApiResult<Json<FsResponse>> {
-    let root_canon = get_root_canon(&state).await?;
-    let rel = sanitize_relative(&payload.path)?;
-    if rel.as_os_str().is_empty() {
-        return Err(ApiError::new(
-            StatusCode::BAD_REQUEST,
-            "Folder name is required",
-        ));
+    let deleted = db::delete_hot_folder(&state.pool, payload.id)
+        .await
+        .map_err(internal_error)?;
+    if !deleted {
+        return Err(hot_folder_not_found());
     }
+    Ok(Json(FsResponse { success: true }))
+}
 
-    let full_path = root_canon.join(&rel);
-    let parent = full_path.parent().ok_or_else(|| {
-        ApiError::new(StatusCode::BAD_REQUEST, "Invalid folder path")
-    })?;
-    let parent_canon = tokio::fs::canonicalize(parent)
+/// Files a hot folder has moved into the library, newest first.
+async fn hot_folder_arrivals(
+    State(state): State<AppState>,
+    Query(query): Query<HotFolderArrivalsQuery>,
+) -> ApiResult<Json<HotFolderArrivalsResponse>> {
+    db::get_hot_folder(&state.pool, query.id)
         .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Parent folder not found"))?;
+        .map_err(internal_error)?
+        .ok_or_else(hot_folder_not_found)?;
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let arrivals = db::list_hot_folder_arrivals(&state.pool, query.id, query.since, limit)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(HotFolderArrivalsResponse { arrivals }))
+}
 
-    if !parent_canon.starts_with(&root_canon) {
-        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
-    }
+async fn resolve_hot_folder_destination(state: &AppState, destination: &str) -> ApiResult<String> {
+    let root_canon = get_root_canon(state).await?;
+    let (dest_rel, _) = resolve_destination(&root_canon, destination).await?;
+    Ok(rel_to_string(&dest_rel))
+}
 
-    if let Err(err) = tokio::fs::create_dir_all(&full_path).await {
-        return Err(map_fs_error(err, "Unable to create folder"));
+fn normalize_hot_folder_settings(settings: db::HotFolderSettings) -> ApiResult<db::HotFolderSettings> {
+    let non_empty = |value: Option<String>| {
+        value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let template = non_empty(settings.template);
+    if let Some(template) = &template {
+        rename::Template::parse(template).map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))?;
     }
+    Ok(db::HotFolderSettings {
+        template,
+        shoot: non_empty(settings.shoot),
+        tags: tags::normalize_tags(&settings.tags),
+        copyright: non_empty(settings.copyright),
+    })
+}
 
-    Ok(Json(FsResponse { success: true }))
+fn hot_folder_not_found() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "Hot folder not found")
 }
 
-async fn fs_delete(
-    State(state): State<AppState>,
-    Json(payload): Json<DeleteRequest>,
-) -> ApiResult<Json<FsResponse>> {
-    if payload.paths.is_empty() {
-        return Err(ApiError::new(
-            StatusCode::BAD_REQUEST,
-            "No paths provided",
-        ));
+/// Polls the enabled hot folders and starts an ingest job for the RAW files
+/// that have settled in each. A folder with an ingest still running is left
+/// until the next poll, so no file is picked up twice.
+fn spawn_hot_folders(state: AppState, interval_secs: u64, settle_secs: i64) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+        let mut settling: HashMap<i64, ingest::Settling> = HashMap::new();
+        loop {
+            interval.tick().await;
+            let folders = match db::list_hot_folders(&state.pool).await {
+                Ok(folders) => folders,
+                Err(err) => {
+                    error!("Failed to list hot folders: {err}");
+                    continue;
+                }
+            };
+            settling.retain(|id, _| folders.iter().any(|folder| folder.id == *id && folder.enabled));
+            for folder in folders.iter().filter(|folder| folder.enabled) {
+                let last_job = match db::last_hot_folder_job(&state.pool, folder.id).await {
+                    Ok(job) => job,
+                    Err(err) => {
+                        error!("Failed to check the jobs of hot folder {}: {err}", folder.path);
+                        continue;
+                    }
+                };
+                if last_job
+                    .as_ref()
+                    .is_some_and(|job| matches!(job.state.as_str(), "pending" | "running"))
+                {
+                    continue;
+                }
+                let failed_job = last_job.filter(|job| job.state == "failed");
+                let tracker = settling.entry(folder.id).or_default();
+                match poll_hot_folder(&state, folder, tracker, failed_job.as_ref(), settle_secs).await {
+                    Ok(Some(job)) => info!("Hot folder {} started ingest job {}", folder.path, job.id),
+                    Ok(None) => {}
+                    Err(err) => error!("Hot folder {}: {}", folder.path, err.message),
+                }
+            }
+        }
+    });
+}
+
+async fn poll_hot_folder(
+    state: &AppState,
+    folder: &db::HotFolder,
+    settling: &mut ingest::Settling,
+    failed_job: Option<&db::Job>,
+    settle_secs: i64,
+) -> ApiResult<Option<db::Job>> {
+    let root_canon = get_root_canon(state).await?;
+    let source = resolve_ingest_source(state, &folder.path).await?;
+    let formats = state.formats.clone();
+    let files = tokio::task::spawn_blocking(move || list_hot_folder_files(&formats, &source))
+        .await
+        .map_err(internal_error)?
+        .map_err(|err| map_fs_error(err, "Unable to read hot folder"))?;
+    if let Some(job) = failed_job {
+        // The files the job failed on stay in the folder; wait for them to
+        // change before trying them again.
+        let sources: Vec<PathBuf> = db::list_job_items(&state.pool, job.id)
+            .await
+            .map_err(internal_error)?
+            .into_iter()
+            .filter(|item| item.state == "failed")
+            .map(|item| PathBuf::from(item.source))
+            .collect();
+        let held = settling.record_failure(job.id, &sources, &files);
+        if held > 0 {
+            info!(
+                "Hot folder {}: holding back {held} files until they change, after ingest job {} failed",
+                folder.path, job.id
+            );
+        }
+    }
+    let now = to_unix_seconds(Some(SystemTime::now()));
+    let ready = settling.observe(files, now, settle_secs);
+    if ready.is_empty() {
+        return Ok(None);
     }
-    let root_canon = get_root_canon(&state).await?;
 
-    for path in payload.paths {
-        let rel = sanitize_relative(&path)?;
-        if rel.as_os_str().is_empty() {
-            return Err(ApiError::new(
-                StatusCode::BAD_REQUEST,
-                "Invalid path",
-            ));
+    let (dest_rel, _) = resolve_destination(&root_canon, &folder.destination).await?;
+    let items: Vec<db::JobItem> = ready
+        .into_iter()
+        .map(|(path, size)| db::JobItem::new(path.to_string_lossy().to_string(), String::new(), false, size as i64))
+        .collect();
+    let settings = &folder.settings;
+    let options = ingest::IngestOptions {
+        destination: rel_to_string(&dest_rel),
+        template: settings
+            .template
+            .clone()
+            .unwrap_or_else(|| state.ingest_template.clone()),
+        shoot: settings.shoot.clone(),
+        tags: settings.tags.clone(),
+        copyright: settings.copyright.clone(),
+        actor: "hot-folder".to_string(),
+        hot_folder: Some(folder.id),
+        move_source: true,
+    };
+    let options = serde_json::to_value(&options).map_err(internal_error)?;
+    Ok(Some(start_job(state, "ingest", &items, Some(&options)).await?))
+}
+
+/// The RAW files under a hot folder with their sizes and mtimes. Hidden
+/// files, such as partial copies some tools write, are left out.
+fn list_hot_folder_files(formats: &formats::FormatRegistry, folder: &Path) -> io::Result<Vec<(PathBuf, u64, i64)>> {
+    let mut files = Vec::new();
+    for entry in transfer::list_tree(folder)? {
+        let transfer::TreeEntry::File(rel) = entry else {
+            continue;
+        };
+        let hidden = rel
+            .components()
+            .any(|part| part.as_os_str().to_string_lossy().starts_with('.'));
+        if hidden || !formats.is_raw(&rel) {
+            continue;
         }
-        let rel_str = rel_to_string(&rel);
-        let full_path = root_canon.join(&rel);
-        let full_canon = tokio::fs::canonicalize(&full_path)
-            .await
-            .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Path not found"))?;
+        let path = folder.join(rel);
+        // Gone since the listing: moved by hand or by the previous job.
+        let Ok(meta) = std::fs::metadata(&path) else {
+            continue;
+        };
+        files.push((path, meta.len(), to_unix_seconds(meta.modified().ok())));
+    }
+    Ok(files)
+}
 
-        if !full_canon.starts_with(&root_canon) {
-            return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+/// Removes an ingested file from its source when the ingest moves files.
+async fn release_ingest_source(options: &ingest::IngestOptions, item: &db::JobItem) -> anyhow::Result<()> {
+    if !options.move_source {
+        return Ok(());
+    }
+    match tokio::fs::remove_file(&item.source).await {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            Err(anyhow::Error::new(err).context(format!("Unable to remove {}", item.source)))
         }
+        _ => Ok(()),
+    }
+}
 
-        let meta = tokio::fs::metadata(&full_canon)
-            .await
-            .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Path not found"))?;
+/// Decides the folder for the shot `item` belongs to. A shot that already
+/// has files in the library from an earlier run of the job stays where they
+/// are; otherwise the template is rendered from the shot's RAW (or first
+/// supported file), using the file's mtime when it has no capture date.
+async fn place_shot(
+    state: &AppState,
+    template: &rename::Template,
+    options: &ingest::IngestOptions,
+    items: &[db::JobItem],
+    item: &db::JobItem,
+    (dest_rel, dest_canon): (&Path, &Path),
+) -> anyhow::Result<ShotPlacement> {
+    let shot_key = ingest::shot_key(&item.source);
+    let shot: Vec<&db::JobItem> = items
+        .iter()
+        .filter(|other| ingest::shot_key(&other.source) == shot_key)
+        .collect();
+
+    if let Some(done) = shot.iter().find(|other| other.state == "done") {
+        let target = Path::new(&done.target);
+        let folder_rel = target.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        let target_name = target
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let source_name = Path::new(&done.source)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let key = companions::group_key(&target_name);
+        return Ok(ShotPlacement {
+            folder_full: dest_canon.join(folder_rel.strip_prefix(dest_rel).unwrap_or(&folder_rel)),
+            key: (key != companions::group_key(&source_name)).then(|| key.to_string()),
+            folder_rel,
+        });
+    }
 
-        if meta.is_dir() {
-            if payload.recursive {
-                if let Err(err) = tokio::fs::remove_dir_all(&full_canon).await {
-                    return Err(map_fs_error(err, "Unable to delete folder"));
-                }
-                db::delete_meta_prefix(&state.pool, &rel_str)
-                    .await
-                    .map_err(internal_error)?;
-            } else {
-                if let Err(err) = tokio::fs::remove_dir(&full_canon).await {
-                    if err.kind() == io::ErrorKind::DirectoryNotEmpty {
-                        return Err(ApiError::new(
-                            StatusCode::BAD_REQUEST,
-                            "Directory not empty. Enable recursive delete.",
-                        ));
-                    }
-                    return Err(map_fs_error(err, "Unable to delete folder"));
-                }
-            }
-        } else {
-            if let Err(err) = tokio::fs::remove_file(&full_canon).await {
-                return Err(map_fs_error(err, "Unable to delete file"));
-            }
-            db::delete_meta(&state.pool, &rel_str)
-                .await
-                .map_err(internal_error)?;
+    let primary = shot
+        .iter()
+        .find(|other| state.formats.is_raw(Path::new(&other.source)))
+        .or_else(|| shot.iter().find(|other| state.formats.is_managed(Path::new(&other.source))))
+        .copied()
+        .unwrap_or(item);
+    let primary_path = PathBuf::from(&primary.source);
+    let primary_clone = primary_path.clone();
+    let read_exif = state.formats.reads_exif(&primary_path);
+    let extracted = tokio::task::spawn_blocking(move || metadata::read_metadata(&primary_clone, read_exif))
+        .await?
+        .ok();
+    let (taken_at, make, model) = match extracted {
+        Some(meta) => (meta.taken_at, meta.camera_make, meta.camera_model),
+        None => (None, None, None),
+    };
+    let taken_at = match taken_at {
+        Some(taken_at) => taken_at,
+        None => {
+            let modified = tokio::fs::metadata(&primary_path).await?.modified().ok();
+            ingest::unix_to_taken(to_unix_seconds(modified))
         }
+    };
+
+    let stem = primary_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = primary_path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let fields = rename::RenameFields {
+        stem: &stem,
+        ext: &ext,
+        seq: 1,
+        taken_at: Some(&taken_at),
+        make: make.as_deref(),
+        model: model.as_deref(),
+        shoot: options.shoot.as_deref(),
+    };
+    let rendered = template.render(&fields).map_err(anyhow::Error::msg)?;
+    let mut folder = PathBuf::new();
+    for part in ingest::folder_parts(&rendered) {
+        let part = validate_file_name(&part).map_err(|err| anyhow::anyhow!(err.message))?;
+        folder.push(part);
     }
 
-    Ok(Json(FsResponse { success: true }))
+    let folder_full = dest_canon.join(&folder);
+    tokio::fs::create_dir_all(&folder_full)
+        .await
+        .with_context(|| format!("Unable to create {}", folder.display()))?;
+    if !tokio::fs::canonicalize(&folder_full).await?.starts_with(dest_canon) {
+        anyhow::bail!("{} leads outside the destination", folder.display());
+    }
+    Ok(ShotPlacement {
+        folder_rel: dest_rel.join(&folder),
+        folder_full,
+        key: None,
+    })
 }
 
-async fn fs_move(
-    State(state): State<AppState>,
-    Json(payload): Json<MoveRequest>,
-) -> ApiResult<Json<FsResponse>> {
-    if payload.paths.is_empty() {
-        return Err(ApiError::new(
-            StatusCode::BAD_REQUEST,
-            "No paths provided",
-        ));
+/// Adds the ingest's tags to every file it copied, as one edit in the
+/// history. Files skipped as duplicates keep their tags.
+async fn tag_ingested_files(
+    state: &AppState,
+    root_canon: &Path,
+    id: i64,
+    options: &ingest::IngestOptions,
+) -> anyhow::Result<()> {
+    let mut targets = Vec::new();
+    for item in db::list_job_items(&state.pool, id).await? {
+        if item.state != "done" || !state.formats.is_managed(Path::new(&item.target)) {
+            continue;
+        }
+        let Ok(meta) = tokio::fs::metadata(root_canon.join(&item.target)).await else {
+            continue;
+        };
+        targets.push(db::BatchTarget {
+            path: item.target,
+            file_size: meta.len() as i64,
+            last_modified: to_unix_seconds(meta.modified().ok()),
+        });
+    }
+    let edit = db::Edit {
+        kind: "ingest",
+        actor: &options.actor,
+    };
+    let updated = db::update_files(&state.pool, edit, &targets, |meta| {
+        meta.tags = tags::normalize_tags(meta.tags.iter().chain(&options.tags));
+    })
+    .await?;
+
+    if state.xmp_writeback {
+        let root_canon = root_canon.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            updated
+                .iter()
+                .try_for_each(|meta| metadata::write_sidecar_keywords(&root_canon.join(&meta.path), &meta.tags))
+        })
+        .await??;
+    }
+    Ok(())
+}
+
+/// Runs a set of renames and the matching catalog update as one unit: the
+/// renames are journaled first, undone if any of them or the database update
+/// fails, and finished or rolled back at startup after a crash. Returns the
+/// journal batch id.
+async fn run_journaled(
+    state: &AppState,
+    op: &str,
+    steps: &[db::JournalStep],
+    changes: &[db::PathChange],
+    error_message: &str,
+) -> ApiResult<i64> {
+    let batch = db::journal_begin(&state.pool, op, steps)
+        .await
+        .map_err(internal_error)?;
+
+    if let Err(err) = journal::apply(steps).await {
+        discard_journal(state, batch).await;
+        if is_cross_device_link(&err) {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Cross-device move not supported",
+            ));
+        }
+        return Err(map_fs_error(err, error_message));
+    }
+
+    if let Err(err) = db::apply_path_changes(&state.pool, batch, changes).await {
+        journal::rollback(steps).await;
+        discard_journal(state, batch).await;
+        return Err(internal_error(err));
+    }
+    Ok(batch)
+}
+
+async fn discard_journal(state: &AppState, batch: i64) {
+    if let Err(err) = db::journal_clear(&state.pool, batch).await {
+        error!("Failed to clear journal batch {batch}: {err}");
     }
+}
+
+async fn fs_upload(
+    State(state): State<AppState>,
+    Query(query): Query<UploadQuery>,
+    mut multipart: Multipart,
+) -> ApiResult<Json<UploadResponse>> {
     let root_canon = get_root_canon(&state).await?;
-    let dest_rel = sanitize_relative(&payload.destination)?;
+    let dest_rel = sanitize_relative(query.path.as_deref().unwrap_or(""))?;
     let dest_full = root_canon.join(&dest_rel);
     let dest_canon = tokio::fs::canonicalize(&dest_full)
         .await
@@ -839,7 +4799,6 @@ async fn fs_move(
     let dest_meta = tokio::fs::metadata(&dest_canon)
         .await
         .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Destination not found"))?;
-
     if !dest_meta.is_dir() {
         return Err(ApiError::new(
             StatusCode::BAD_REQUEST,
@@ -847,151 +4806,567 @@ async fn fs_move(
         ));
     }
 
-    for path in payload.paths {
-        let rel = sanitize_relative(&path)?;
-        if rel.as_os_str().is_empty() {
-            return Err(ApiError::new(
-                StatusCode::BAD_REQUEST,
-                "Invalid path",
-            ));
+    let mut sha256 = None;
+    let mut results = Vec::new();
+    let mut claimed = HashSet::new();
+    let mut request_error = None;
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            // Nothing saved yet: fail the request as a whole.
+            Err(err) if results.is_empty() => return Err(multipart_error(err)),
+            Err(err) => {
+                request_error = Some(multipart_error(err).message);
+                break;
+            }
+        };
+        // A `sha256` text field is the checksum of the file that follows it.
+        if field.file_name().is_none() && field.name() == Some("sha256") {
+            let value = field.text().await.map_err(multipart_error)?;
+            sha256 = parse_sha256(&value)?;
+            continue;
         }
-        let rel_str = rel_to_string(&rel);
-        let full_path = root_canon.join(&rel);
-        let full_canon = tokio::fs::canonicalize(&full_path)
-            .await
-            .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Path not found"))?;
+        let expected_sha256 = sha256.take();
+        let name = field.file_name().unwrap_or_default().to_string();
 
-        if !full_canon.starts_with(&root_canon) {
-            return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid path"));
+        let upload = UploadTarget {
+            dest_rel: &dest_rel,
+            dest_canon: &dest_canon,
+            conflict: query.conflict,
+        };
+        let result = match upload_file(&state, &mut field, &upload, expected_sha256.as_deref(), &mut claimed).await {
+            Ok((path, action)) => UploadResult {
+                name,
+                path: Some(path),
+                action: Some(action),
+                error: None,
+            },
+            Err(err) => UploadResult {
+                name,
+                path: None,
+                action: None,
+                error: Some(err.message),
+            },
+        };
+        results.push(result);
+    }
+
+    Ok(Json(UploadResponse {
+        success: request_error.is_none() && results.iter().all(|result| result.error.is_none()),
+        results,
+        error: request_error,
+    }))
+}
+
+/// The folder a multipart upload goes to and what to do about names that
+/// are taken.
+struct UploadTarget<'a> {
+    dest_rel: &'a Path,
+    dest_canon: &'a Path,
+    conflict: UploadConflict,
+}
+
+/// Saves one file of a multipart upload according to the conflict policy and
+/// returns its library path and what was done. A file that is written is
+/// scanned into the catalog in the background.
+async fn upload_file(
+    state: &AppState,
+    field: &mut Field<'_>,
+    upload: &UploadTarget<'_>,
+    expected_sha256: Option<&str>,
+    claimed: &mut HashSet<PathBuf>,
+) -> ApiResult<(String, UploadAction)> {
+    let file_name = field
+        .file_name()
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Missing file name"))?;
+    let safe_name = Path::new(file_name)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.to_string())
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Invalid file name"))?;
+    let safe_name = validate_file_name(&safe_name)?;
+    let format = state.formats.get(Path::new(&safe_name)).ok_or_else(|| {
+        ApiError::new(StatusCode::BAD_REQUEST, "Unsupported file type")
+    })?;
+
+    let existing = upload.dest_canon.join(&safe_name);
+    let taken = claimed.contains(&existing) || tokio::fs::symlink_metadata(&existing).await.is_ok();
+    let (mut target_name, mut action) = (safe_name.clone(), UploadAction::Created);
+    if taken {
+        match upload.conflict {
+            UploadConflict::Fail => {
+                return Err(ApiError::new(StatusCode::CONFLICT, "File already exists"));
+            }
+            UploadConflict::Skip => {
+                let rel = join_rel(upload.dest_rel, OsStr::new(&safe_name));
+                return Ok((rel_to_string(&rel), UploadAction::Skipped));
+            }
+            UploadConflict::Rename => {
+                target_name = uploads::free_name(upload.dest_canon, &safe_name, claimed).await?;
+                action = UploadAction::Renamed;
+            }
+            UploadConflict::Overwrite => action = UploadAction::Overwritten,
+            // Decided once the upload's checksum is known.
+            UploadConflict::SkipIdentical => {}
         }
+    }
 
-        let meta = tokio::fs::metadata(&full_canon)
+    // The file only appears under its name once it is complete and checked;
+    // anything that goes wrong before that removes the partial.
+    let (partial, file) = uploads::create_partial(&upload.dest_canon.join(&target_name))
+        .await
+        .map_err(|err| map_fs_error(err, "Unable to create file"))?;
+    let digest = match receive_upload(field, file, &safe_name, format, state.upload_limits.max_file_bytes, expected_sha256).await {
+        Ok(digest) => digest,
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(err);
+        }
+    };
+
+    if taken && upload.conflict == UploadConflict::SkipIdentical {
+        let existing_clone = existing.clone();
+        let same = !claimed.contains(&existing)
+            && tokio::task::spawn_blocking(move || transfer::file_digest(&existing_clone))
+                .await
+                .map_err(internal_error)?
+                .map(|(_, existing_digest)| existing_digest == digest)
+                .unwrap_or(false);
+        if same {
+            let _ = tokio::fs::remove_file(&partial).await;
+            let rel = join_rel(upload.dest_rel, OsStr::new(&safe_name));
+            return Ok((rel_to_string(&rel), UploadAction::Skipped));
+        }
+        target_name = uploads::free_name(upload.dest_canon, &safe_name, claimed).await?;
+        action = UploadAction::Renamed;
+    }
+
+    let target = upload.dest_canon.join(&target_name);
+    let placed = if action != UploadAction::Overwritten && tokio::fs::symlink_metadata(&target).await.is_ok() {
+        Err(ApiError::new(StatusCode::CONFLICT, "File already exists"))
+    } else {
+        tokio::fs::rename(&partial, &target)
             .await
-            .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Path not found"))?;
+            .map_err(|err| map_fs_error(err, "Unable to save file"))
+    };
+    if let Err(err) = placed {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(err);
+    }
+    claimed.insert(target.clone());
 
-        if meta.is_dir() && dest_canon.starts_with(&full_canon) {
-            return Err(ApiError::new(
-                StatusCode::BAD_REQUEST,
-                "Cannot move a folder into itself",
-            ));
+    let rel = rel_to_string(&join_rel(upload.dest_rel, OsStr::new(&target_name)));
+    spawn_file_scan(state.clone(), rel.clone(), target);
+    Ok((rel, action))
+}
+
+/// Reads a newly added file's metadata into the catalog and builds its
+/// thumbnail, in the background so uploads and imports do not wait for it.
+fn spawn_file_scan(state: AppState, rel: String, full: PathBuf) {
+    tokio::spawn(async move {
+        let meta = match tokio::fs::metadata(&full).await {
+            Ok(meta) => meta,
+            Err(err) => {
+                error!("Failed to scan {rel}: {err}");
+                return;
+            }
+        };
+        let size = meta.len() as i64;
+        let modified = to_unix_seconds(meta.modified().ok());
+        if let Err(err) = scan_file(&state, &rel, &full, size, modified).await {
+            error!("Failed to read metadata of {rel}: {}", err.message);
+        }
+        if let Err(err) = ensure_file_preview(&state, &rel, full, metadata::PreviewKind::Thumb).await {
+            error!("Failed to build thumbnail of {rel}: {}", err.message);
         }
+    });
+}
 
-        let name = full_canon
-            .file_name()
-            .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Invalid path"))?;
-        let target_rel = join_rel(&dest_rel, name);
-        let target_rel_str = rel_to_string(&target_rel);
-        let target_full = root_canon.join(&target_rel);
+/// Streams one multipart file into the partial `file`, checking its content
+/// type as soon as the first bytes arrive, its size as it grows and, when
+/// given, its SHA-256 once it is complete. Returns the SHA-256; the partial
+/// file is left for the caller to rename or remove.
+async fn receive_upload(
+    field: &mut Field<'_>,
+    mut file: tokio::fs::File,
+    name: &str,
+    format: &formats::Format,
+    max_bytes: Option<u64>,
+    expected_sha256: Option<&str>,
+) -> ApiResult<[u8; 32]> {
+    let mut head = Vec::new();
+    while head.len() < formats::SNIFF_LEN {
+        match field.chunk().await.map_err(multipart_error)? {
+            Some(chunk) => head.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    let detected = formats::sniff(&head);
+    if !format.accepts(detected, head.is_empty()) {
+        return Err(uploads::content_error(name, format, detected, head.is_empty()));
+    }
+
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    let mut next = Some(bytes::Bytes::from(head));
+    while let Some(chunk) = next {
+        size += chunk.len() as u64;
+        if let Some(max) = max_bytes.filter(|max| size > *max) {
+            return Err(file_too_large(name, max));
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk)
+            .await
+            .map_err(|err| map_fs_error(err, "Unable to write file"))?;
+        next = field.chunk().await.map_err(multipart_error)?;
+    }
+    file.sync_all()
+        .await
+        .map_err(|err| map_fs_error(err, "Unable to write file"))?;
 
-        if tokio::fs::metadata(&target_full).await.is_ok() {
+    let digest: [u8; 32] = hasher.finalize().into();
+    if let Some(expected) = expected_sha256 {
+        if hex::encode(digest) != expected {
             return Err(ApiError::new(
                 StatusCode::BAD_REQUEST,
-                "Destination already exists",
+                format!("{name} does not match its SHA-256 checksum"),
             ));
         }
+    }
+    Ok(digest)
+}
 
-        if let Err(err) = tokio::fs::rename(&full_canon, &target_full).await {
-            if is_cross_device_link(&err) {
-                return Err(ApiError::new(
-                    StatusCode::BAD_REQUEST,
-                    "Cross-device move not supported",
-                ));
-            }
-            return Err(map_fs_error(err, "Unable to move path"));
-        }
+/// A client-supplied SHA-256 in hex; blank means none.
+fn parse_sha256(raw: &str) -> ApiResult<Option<String>> {
+    let hash = raw.trim().to_lowercase();
+    if hash.is_empty() {
+        return Ok(None);
+    }
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "sha256 must be 64 hex digits"));
+    }
+    Ok(Some(hash))
+}
 
-        if meta.is_dir() {
-            db::move_meta_prefix(&state.pool, &rel_str, &target_rel_str)
-                .await
-                .map_err(internal_error)?;
-        } else {
-            db::move_meta(&state.pool, &rel_str, &target_rel_str)
-                .await
-                .map_err(internal_error)?;
-        }
+fn file_too_large(name: &str, max_bytes: u64) -> ApiError {
+    ApiError::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("{name} is larger than the {} MB upload limit", max_bytes / (1024 * 1024)),
+    )
+}
+
+/// Keeps the status axum gives a failed multipart read, so hitting the body
+/// limit is a 413 rather than a 500.
+fn multipart_error(err: axum::extract::multipart::MultipartError) -> ApiError {
+    if err.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        return ApiError::new(err.status(), "Upload is larger than the request size limit");
     }
+    ApiError::new(err.status(), err.body_text())
+}
 
-    Ok(Json(FsResponse { success: true }))
+async fn list_uploads(State(state): State<AppState>) -> ApiResult<Json<UploadsResponse>> {
+    let uploads = db::list_uploads(&state.pool)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(UploadsResponse { uploads }))
 }
 
-async fn fs_upload(
+/// Starts a resumable upload of `name` into folder `path`. The client then
+/// sends the file in pieces to `/uploads/chunk`, each at the offset the
+/// session reports, and can ask `/uploads/status` where to continue after a
+/// dropped connection.
+async fn create_upload(
     State(state): State<AppState>,
-    Query(query): Query<UploadQuery>,
-    mut multipart: Multipart,
-) -> ApiResult<Json<FsResponse>> {
+    Json(payload): Json<UploadCreateRequest>,
+) -> ApiResult<Json<UploadStatusResponse>> {
     let root_canon = get_root_canon(&state).await?;
-    let dest_rel = sanitize_relative(query.path.as_deref().unwrap_or(""))?;
-    let dest_full = root_canon.join(&dest_rel);
-    let dest_canon = tokio::fs::canonicalize(&dest_full)
+    let (dest_rel, dest_canon) =
+        resolve_destination(&root_canon, payload.path.as_deref().unwrap_or("")).await?;
+    let name = validate_file_name(&payload.name)?;
+    if !state.formats.is_managed(Path::new(&name)) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "Unsupported file type"));
+    }
+    if payload.size <= 0 {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, format!("{name} is empty")));
+    }
+    if let Some(max) = state.upload_limits.max_file_bytes {
+        if payload.size as u64 > max {
+            return Err(file_too_large(&name, max));
+        }
+    }
+    let sha256 = payload.sha256.as_deref().map(parse_sha256).transpose()?.flatten();
+    if tokio::fs::symlink_metadata(dest_canon.join(&name)).await.is_ok() {
+        return Err(ApiError::new(StatusCode::CONFLICT, "File already exists"));
+    }
+
+    let id = uploads::new_id().map_err(internal_error)?;
+    let staged = uploads::staging_path(&root_canon, &id);
+    if let Some(parent) = staged.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|err| map_fs_error(err, "Unable to create upload folder"))?;
+    }
+    tokio::fs::File::create(&staged)
         .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Destination not found"))?;
+        .map_err(|err| map_fs_error(err, "Unable to create upload file"))?;
+    let upload = db::create_upload(
+        &state.pool,
+        &id,
+        &rel_to_string(&dest_rel),
+        &name,
+        payload.size,
+        sha256.as_deref(),
+    )
+    .await
+    .map_err(internal_error)?;
 
-    if !dest_canon.starts_with(&root_canon) {
-        return Err(ApiError::new(StatusCode::FORBIDDEN, "Invalid destination"));
+    Ok(Json(UploadStatusResponse {
+        upload,
+        complete: false,
+    }))
+}
+
+async fn upload_status(
+    State(state): State<AppState>,
+    Query(query): Query<UploadIdQuery>,
+) -> ApiResult<Json<UploadStatusResponse>> {
+    let root_canon = get_root_canon(&state).await?;
+    let upload = load_upload(&state, &root_canon, &query.id).await?;
+    Ok(Json(UploadStatusResponse {
+        complete: upload.path.is_some(),
+        upload,
+    }))
+}
+
+/// Appends one piece of an upload. `offset` must be where the session left
+/// off; the last piece moves the finished file into the library.
+async fn upload_chunk(
+    State(state): State<AppState>,
+    Query(query): Query<UploadChunkQuery>,
+    body: Body,
+) -> ApiResult<Json<UploadStatusResponse>> {
+    let root_canon = get_root_canon(&state).await?;
+    let _busy = UploadGuard::claim(&state, &query.id)?;
+    let upload = load_upload(&state, &root_canon, &query.id).await?;
+    // The last piece was stored but its response never arrived.
+    if upload.path.is_some() {
+        return Ok(Json(UploadStatusResponse {
+            upload,
+            complete: true,
+        }));
+    }
+    if query.offset != upload.received {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("Upload is at offset {}", upload.received),
+        ));
     }
 
-    let dest_meta = tokio::fs::metadata(&dest_canon)
+    let bytes = axum::body::to_bytes(body, UPLOAD_CHUNK_LIMIT)
         .await
-        .map_err(|_| ApiError::new(StatusCode::NOT_FOUND, "Destination not found"))?;
-    if !dest_meta.is_dir() {
+        .map_err(|_| ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "Chunk is too large"))?;
+    let received = upload.received + bytes.len() as i64;
+    if received > upload.size {
         return Err(ApiError::new(
             StatusCode::BAD_REQUEST,
-            "Destination must be a folder",
+            "Chunk goes past the end of the file",
         ));
     }
 
-    while let Some(field) = multipart
-        .next_field()
+    let staged = uploads::staging_path(&root_canon, &upload.id);
+    let mut file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(&staged)
         .await
-        .map_err(internal_error)?
-    {
-        let file_name = field
-            .file_name()
-            .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Missing file name"))?;
-        let safe_name = Path::new(file_name)
-            .file_name()
-            .and_then(|name| name.to_str())
-            .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Invalid file name"))?;
+        .map_err(|err| map_fs_error(err, "Unable to open upload file"))?;
+    file.write_all(&bytes)
+        .await
+        .map_err(|err| map_fs_error(err, "Unable to write upload file"))?;
+    file.sync_data()
+        .await
+        .map_err(|err| map_fs_error(err, "Unable to write upload file"))?;
+    db::set_upload_received(&state.pool, &upload.id, received)
+        .await
+        .map_err(internal_error)?;
+    let upload = db::UploadSession { received, ..upload };
 
-        if !is_supported_raw(Path::new(safe_name)) {
-            return Err(ApiError::new(
-                StatusCode::BAD_REQUEST,
-                "Unsupported file type",
-            ));
+    if received < upload.size {
+        return Ok(Json(UploadStatusResponse {
+            upload,
+            complete: false,
+        }));
+    }
+    let path = finish_upload(&state, &root_canon, &upload).await?;
+    Ok(Json(UploadStatusResponse {
+        upload: db::UploadSession {
+            path: Some(path),
+            ..upload
+        },
+        complete: true,
+    }))
+}
+
+async fn cancel_upload(
+    State(state): State<AppState>,
+    Json(payload): Json<UploadCancelRequest>,
+) -> ApiResult<Json<FsResponse>> {
+    let root_canon = get_root_canon(&state).await?;
+    let _busy = UploadGuard::claim(&state, &payload.id)?;
+    let upload = load_upload(&state, &root_canon, &payload.id).await?;
+    discard_upload(&state, &root_canon, &upload.id).await?;
+    Ok(Json(FsResponse { success: true }))
+}
+
+/// Marks an upload session as in use for one request, so two requests never
+/// write the same partial file at once.
+struct UploadGuard {
+    busy: Arc<std::sync::Mutex<HashSet<String>>>,
+    id: String,
+}
+
+impl UploadGuard {
+    fn claim(state: &AppState, id: &str) -> ApiResult<Self> {
+        let mut busy = state.busy_uploads.lock().map_err(internal_error)?;
+        if !busy.insert(id.to_string()) {
+            return Err(ApiError::new(StatusCode::CONFLICT, "Upload is busy"));
         }
+        Ok(Self {
+            busy: state.busy_uploads.clone(),
+            id: id.to_string(),
+        })
+    }
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        if let Ok(mut busy) = self.busy.lock() {
+            busy.remove(&self.id);
+        }
+    }
+}
+
+/// Loads a session with `received` taken from the partial file itself, which
+/// is what survives an interrupted write.
+async fn load_upload(state: &AppState, root_canon: &Path, id: &str) -> ApiResult<db::UploadSession> {
+    let not_found = || ApiError::new(StatusCode::NOT_FOUND, "Upload not found");
+    if !uploads::is_valid_id(id) {
+        return Err(not_found());
+    }
+    let upload = db::get_upload(&state.pool, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(not_found)?;
+    if upload.path.is_some() {
+        return Ok(upload);
+    }
+    let staged = uploads::staging_path(root_canon, id);
+    let on_disk = tokio::fs::metadata(&staged)
+        .await
+        .map(|meta| meta.len() as i64)
+        .unwrap_or(0);
+    if on_disk == upload.received {
+        return Ok(upload);
+    }
+    if on_disk > upload.size {
+        discard_upload(state, root_canon, id).await?;
+        return Err(not_found());
+    }
+    db::set_upload_received(&state.pool, id, on_disk)
+        .await
+        .map_err(internal_error)?;
+    Ok(db::UploadSession {
+        received: on_disk,
+        ..upload
+    })
+}
 
-        let target_full = dest_canon.join(safe_name);
-        if tokio::fs::metadata(&target_full).await.is_ok() {
+/// Checks a complete upload and moves it to its folder. A file that fails
+/// the checks is discarded with its session; the client starts over.
+async fn finish_upload(state: &AppState, root_canon: &Path, upload: &db::UploadSession) -> ApiResult<String> {
+    let staged = uploads::staging_path(root_canon, &upload.id);
+    let format = state
+        .formats
+        .get(Path::new(&upload.name))
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Unsupported file type"))?;
+    if let Some(err) = uploads::wrong_content(format, &staged, &upload.name, upload.size as u64).await? {
+        discard_upload(state, root_canon, &upload.id).await?;
+        return Err(err);
+    }
+    if let Some(expected) = &upload.sha256 {
+        let staged_clone = staged.clone();
+        let (_, digest) = tokio::task::spawn_blocking(move || transfer::file_digest(&staged_clone))
+            .await
+            .map_err(internal_error)?
+            .map_err(|err| map_fs_error(err, "Unable to read upload file"))?;
+        if hex::encode(digest) != *expected {
+            discard_upload(state, root_canon, &upload.id).await?;
             return Err(ApiError::new(
                 StatusCode::BAD_REQUEST,
-                "File already exists",
+                format!("{} does not match its SHA-256 checksum", upload.name),
             ));
         }
+    }
 
-        let mut file = tokio::fs::File::create(&target_full)
-            .await
-            .map_err(|err| map_fs_error(err, "Unable to create file"))?;
-        let mut field = field;
-        while let Some(chunk) = field.chunk().await.map_err(internal_error)? {
-            file.write_all(&chunk)
-                .await
-                .map_err(|err| map_fs_error(err, "Unable to write file"))?;
-        }
+    let (dest_rel, dest_canon) = resolve_destination(root_canon, &upload.directory).await?;
+    let rel = uploads::store(state, &staged, &dest_rel, &dest_canon, &upload.name, uploads::Taken::Fail).await?;
+    db::finish_upload(&state.pool, &upload.id, &rel)
+        .await
+        .map_err(internal_error)?;
+    Ok(rel)
+}
+
+async fn discard_upload(state: &AppState, root_canon: &Path, id: &str) -> ApiResult<()> {
+    match tokio::fs::remove_file(uploads::staging_path(root_canon, id)).await {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(map_fs_error(err, "Unable to remove upload file")),
     }
+    db::delete_upload(&state.pool, id)
+        .await
+        .map_err(internal_error)
+}
 
-    Ok(Json(FsResponse { success: true }))
+/// Drops upload sessions that have not received data for a week.
+fn spawn_upload_expiry(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            let Ok(root_canon) = get_root_canon(&state).await else {
+                continue;
+            };
+            let cutoff = to_unix_seconds(Some(SystemTime::now())) - uploads::EXPIRE_AFTER_SECS;
+            let expired = match db::list_uploads(&state.pool).await {
+                Ok(uploads) => uploads
+                    .into_iter()
+                    .filter(|upload| upload.updated_at < cutoff)
+                    .collect::<Vec<_>>(),
+                Err(err) => {
+                    error!("Failed to list uploads: {err}");
+                    continue;
+                }
+            };
+            for upload in expired {
+                match discard_upload(&state, &root_canon, &upload.id).await {
+                    Ok(()) => info!("Dropped expired upload of {}", upload.name),
+                    Err(err) => error!("Failed to drop upload {}: {}", upload.id, err.message),
+                }
+            }
+        }
+    });
 }
 
 fn is_cross_device_link(err: &io::Error) -> bool {
     #[cfg(target_family = "unix")]
     {
-        return err.raw_os_error() == Some(libc::EXDEV);
+        err.raw_os_error() == Some(libc::EXDEV)
     }
 
     #[cfg(not(target_family = "unix"))]
     {
         let _ = err;
-        return false;
+        false
     }
 }
 
@@ -1009,6 +5384,12 @@ fn sanitize_relative(path: &str) -> ApiResult<PathBuf> {
         }
     }
 
+    // The trash is only reachable through the trash endpoints, and unfinished
+    // uploads through the upload endpoints.
+    if trash::is_trash_path(&rel) || uploads::is_staging_path(&rel) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "Invalid path"));
+    }
+
     Ok(rel)
 }
 
@@ -1024,26 +5405,34 @@ fn join_rel(base: &Path, name: &OsStr) -> PathBuf {
     }
 }
 
-fn is_supported_raw(path: &Path) -> bool {
-    let ext = path
-        .extension()
-        .and_then(OsStr::to_str)
-        .map(|s| s.to_lowercase())
-        .unwrap_or_default();
+fn required_tag(raw: &str) -> ApiResult<String> {
+    tags::normalize_tag(raw).ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Tag is required"))
+}
 
-    matches!(
-        ext.as_str(),
-        "arw"
-            | "dng"
-            | "cr2"
-            | "cr3"
-            | "nef"
-            | "raf"
-            | "orf"
-            | "rw2"
-            | "srw"
-            | "pef"
-    )
+fn entry_from_meta(meta: FileMeta) -> BrowseEntry {
+    let name = Path::new(&meta.path)
+        .file_name()
+        .unwrap_or_else(|| OsStr::new(""))
+        .to_string_lossy()
+        .to_string();
+    BrowseEntry {
+        name,
+        kind: "file".to_string(),
+        size: Some(meta.file_size),
+        modified: Some(meta.last_modified),
+        camera_rating: meta.camera_rating,
+        user_rating: meta.user_rating,
+        tags: meta.tags,
+        gps_lat: meta.gps_lat,
+        gps_lon: meta.gps_lon,
+        taken_at: meta.taken_at,
+        needs_scan: meta.orientation.is_none(),
+        orientation: meta.orientation,
+        color_label: meta.color_label,
+        flag: meta.flag,
+        path: meta.path,
+        companions: Vec::new(),
+    }
 }
 
 async fn get_root_canon(state: &AppState) -> ApiResult<PathBuf> {
@@ -1075,6 +5464,111 @@ fn map_fs_error(err: io::Error, message: &str) -> ApiError {
     ApiError::new(StatusCode::BAD_REQUEST, format!("{message}: {err}"))
 }
 
+fn read_flag_env(name: &str) -> bool {
+    env::var(name)
+        .map(|raw| matches!(raw.trim().to_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+        .unwrap_or(false)
+}
+
+/// A comma-separated list such as `kdc, mef`. Items are trimmed but may
+/// contain spaces, like the `/Volumes/NO NAME` a camera card mounts as.
+fn read_list_env(name: &str) -> Vec<String> {
+    env::var(name)
+        .map(|raw| {
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| item.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A size in megabytes with a default; `0` turns the limit off.
+fn read_megabytes_env(name: &str, default_mb: u64) -> Option<u64> {
+    let megabytes = env::var(name)
+        .ok()
+        .and_then(|raw| raw.trim().parse::<u64>().ok())
+        .unwrap_or(default_mb);
+    (megabytes > 0).then(|| megabytes.saturating_mul(1024 * 1024))
+}
+
+/// The embedded FTP server's settings, or `None` when
+/// `RAW_MANAGER_FTP_ADDR` is unset and the server stays off.
+fn read_ftp_config_env() -> anyhow::Result<Option<ftp::FtpConfig>> {
+    let Some(addr) = env::var("RAW_MANAGER_FTP_ADDR")
+        .ok()
+        .map(|addr| addr.trim().to_string())
+        .filter(|addr| !addr.is_empty())
+    else {
+        return Ok(None);
+    };
+    let users = ftp::parse_users(&read_list_env("RAW_MANAGER_FTP_USERS"));
+    if users.is_empty() {
+        anyhow::bail!("RAW_MANAGER_FTP_ADDR is set but RAW_MANAGER_FTP_USERS has no name:password entries");
+    }
+    let folder = env::var("RAW_MANAGER_FTP_FOLDER").unwrap_or_default();
+    let folder = sanitize_relative(&folder)
+        .map_err(|err| anyhow::anyhow!("Invalid RAW_MANAGER_FTP_FOLDER: {}", err.message))?;
+    let passive_ports = match env::var("RAW_MANAGER_FTP_PASSIVE_PORTS") {
+        Ok(raw) => Some(
+            ftp::parse_port_range(&raw)
+                .ok_or_else(|| anyhow::anyhow!("Invalid RAW_MANAGER_FTP_PASSIVE_PORTS: {raw}"))?,
+        ),
+        Err(_) => None,
+    };
+    let passive_ip = match env::var("RAW_MANAGER_FTP_PASSIVE_IP") {
+        Ok(raw) => Some(
+            raw.trim()
+                .parse()
+                .with_context(|| format!("Invalid RAW_MANAGER_FTP_PASSIVE_IP: {raw}"))?,
+        ),
+        Err(_) => None,
+    };
+    let tls = match (
+        env::var_os("RAW_MANAGER_FTP_TLS_CERT").filter(|cert| !cert.is_empty()),
+        env::var_os("RAW_MANAGER_FTP_TLS_KEY").filter(|key| !key.is_empty()),
+    ) {
+        (Some(cert), Some(key)) => Some(ftp::load_tls(Path::new(&cert), Path::new(&key))?),
+        (None, None) => None,
+        _ => anyhow::bail!("RAW_MANAGER_FTP_TLS_CERT and RAW_MANAGER_FTP_TLS_KEY must be set together"),
+    };
+    Ok(Some(ftp::FtpConfig {
+        addr,
+        folder,
+        users,
+        passive_ports,
+        passive_ip,
+        tls,
+    }))
+}
+
+fn read_hot_folder_interval_env() -> u64 {
+    env::var("RAW_MANAGER_HOT_FOLDER_INTERVAL_SECS")
+        .ok()
+        .and_then(|raw| raw.trim().parse::<u64>().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(ingest::DEFAULT_HOT_FOLDER_INTERVAL_SECS)
+}
+
+fn read_hot_folder_settle_env() -> i64 {
+    env::var("RAW_MANAGER_HOT_FOLDER_SETTLE_SECS")
+        .ok()
+        .and_then(|raw| raw.trim().parse::<i64>().ok())
+        .filter(|secs| *secs >= 0)
+        .unwrap_or(ingest::DEFAULT_HOT_FOLDER_SETTLE_SECS)
+}
+
+/// Days to keep trashed items, `RAW_MANAGER_TRASH_RETENTION_DAYS`; `0`
+/// keeps them until the trash is emptied by hand.
+fn read_trash_retention_env() -> Option<i64> {
+    let days = env::var("RAW_MANAGER_TRASH_RETENTION_DAYS")
+        .ok()
+        .and_then(|raw| raw.trim().parse::<i64>().ok())
+        .unwrap_or(trash::DEFAULT_RETENTION_DAYS);
+    (days > 0).then_some(days)
+}
+
 fn read_library_root_env() -> Option<String> {
     let value = env::var("RAW_MANAGER_LIBRARY_ROOT")
         .ok()
@@ -5,9 +5,11 @@ use image::imageops::FilterType;
 use image::{GenericImageView, ImageFormat};
 use sha2::{Digest, Sha256};
 use std::fs;
-use std::io::{BufReader, Cursor};
+use std::io::{BufReader, Cursor, Read};
 use std::path::{Path, PathBuf};
 
+use crate::tags;
+
 #[derive(Debug, Clone)]
 pub struct ExtractedMeta {
     pub camera_rating: Option<i32>,
@@ -15,25 +17,53 @@ pub struct ExtractedMeta {
     pub gps_lon: Option<f64>,
     pub taken_at: Option<String>,
     pub orientation: Option<i32>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub keywords: Vec<String>,
+    pub title: Option<String>,
+    pub caption: Option<String>,
 }
 
+const XMP_TEMPLATE: &str = "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+  <rdf:Description rdf:about=\"\">
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>
+";
+
+/// How much of a file `read_metadata` searches for embedded XMP before it
+/// reads the rest.
+const XMP_SCAN_LEN: u64 = 2 * 1024 * 1024;
+
 #[derive(Debug, Clone, Copy)]
 pub enum PreviewKind {
     Full,
     Thumb,
 }
 
-pub fn read_metadata(path: &Path) -> Result<ExtractedMeta> {
+/// Reads what the catalog keeps about a file. EXIF is only parsed when
+/// `read_exif` is set (the format's `metadata` capability); XMP, embedded or
+/// in a sidecar, is read for every file.
+pub fn read_metadata(path: &Path, read_exif: bool) -> Result<ExtractedMeta> {
     let file = fs::File::open(path).with_context(|| format!("open {:?}", path))?;
     let mut bufreader = BufReader::new(&file);
 
-    let exif = Reader::new().read_from_container(&mut bufreader).ok();
+    let exif = if read_exif {
+        Reader::new().read_from_container(&mut bufreader).ok()
+    } else {
+        None
+    };
 
     let mut camera_rating = None;
     let mut gps_lat = None;
     let mut gps_lon = None;
     let mut taken_at = None;
     let mut orientation = None;
+    let mut camera_make = None;
+    let mut camera_model = None;
 
     if let Some(exif) = exif {
         camera_rating = extract_rating(&exif);
@@ -45,22 +75,75 @@ pub fn read_metadata(path: &Path) -> Result<ExtractedMeta> {
             gps_lon = Some(lon);
         }
         orientation = extract_orientation(&exif);
+        camera_make = extract_ascii(&exif, Tag::Make);
+        camera_model = extract_ascii(&exif, Tag::Model);
     }
 
+    let embedded_xmp = read_embedded_xmp(path).ok().flatten();
+    let sidecar_xmp = read_sidecar_xmp(path).ok().flatten();
+
     if camera_rating.is_none() {
-        if let Some(xmp_rating) = extract_xmp_rating_from_path(path).ok().flatten() {
-            camera_rating = Some(xmp_rating);
-        } else if let Some(sidecar_rating) = extract_sidecar_rating(path).ok().flatten() {
-            camera_rating = Some(sidecar_rating);
-        }
+        camera_rating = embedded_xmp
+            .as_deref()
+            .and_then(parse_xmp_rating)
+            .or_else(|| sidecar_xmp.as_deref().and_then(parse_xmp_rating));
     }
 
+    let mut keywords = sidecar_xmp.as_deref().map(parse_xmp_keywords).unwrap_or_default();
+    if keywords.is_empty() {
+        keywords = embedded_xmp.as_deref().map(parse_xmp_keywords).unwrap_or_default();
+    }
+
+    let xmp_text = |qname: &str| {
+        sidecar_xmp
+            .as_deref()
+            .and_then(|xmp| xmp_alt_text(xmp, qname))
+            .or_else(|| embedded_xmp.as_deref().and_then(|xmp| xmp_alt_text(xmp, qname)))
+    };
+    let title = xmp_text("dc:title");
+    let caption = xmp_text("dc:description");
+
     Ok(ExtractedMeta {
         camera_rating,
         gps_lat,
         gps_lon,
         taken_at,
         orientation,
+        camera_make,
+        camera_model,
+        keywords,
+        title,
+        caption,
+    })
+}
+
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    path.with_extension("xmp")
+}
+
+/// Writes the tags into the file's XMP sidecar as `dc:subject` (every keyword
+/// level, flattened) and `lr:hierarchicalSubject` (full `A|B|C` paths),
+/// keeping any other properties already present in the sidecar.
+pub fn write_sidecar_keywords(path: &Path, tags: &[String]) -> Result<()> {
+    let mut subjects = Vec::new();
+    for tag in tags {
+        for segment in tags::segments(tag) {
+            if !subjects
+                .iter()
+                .any(|existing: &String| existing.eq_ignore_ascii_case(segment))
+            {
+                subjects.push(segment.to_string());
+            }
+        }
+    }
+
+    update_sidecar(path, |xmp| {
+        let xmp = set_xmp_property(xmp, "dc:subject", xmp_bag("dc:subject", &subjects));
+        set_xmp_property(
+            &xmp,
+            "lr:hierarchicalSubject",
+            xmp_bag("lr:hierarchicalSubject", tags),
+        )
     })
 }
 
@@ -77,17 +160,8 @@ pub fn preview_cache_path(preview_dir: &Path, rel_path: &str, kind: PreviewKind)
 }
 
 pub fn ensure_preview(path: &Path, preview_path: &Path, kind: PreviewKind) -> Result<bool> {
-    let source_meta = fs::metadata(path)?;
-    let source_modified = source_meta.modified().ok();
-
-    if preview_path.exists() {
-        if let (Some(src), Ok(prev_meta)) = (source_modified, fs::metadata(preview_path)) {
-            if let Ok(prev_modified) = prev_meta.modified() {
-                if prev_modified >= src {
-                    return Ok(true);
-                }
-            }
-        }
+    if preview_is_fresh(path, preview_path)? {
+        return Ok(true);
     }
 
     let data = fs::read(path).with_context(|| format!("read {:?}", path))?;
@@ -118,6 +192,38 @@ pub fn ensure_preview(path: &Path, preview_path: &Path, kind: PreviewKind) -> Re
     Ok(true)
 }
 
+/// Like `ensure_preview`, for a camera JPEG rather than a RAW: the full
+/// preview is the whole image and the thumbnail is scaled down from it.
+pub fn ensure_jpeg_preview(path: &Path, preview_path: &Path, kind: PreviewKind) -> Result<bool> {
+    if preview_is_fresh(path, preview_path)? {
+        return Ok(true);
+    }
+
+    let data = fs::read(path).with_context(|| format!("read {:?}", path))?;
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Ok(false);
+    }
+    if matches!(kind, PreviewKind::Thumb) {
+        if let Ok(resized) = downscale_jpeg(&data, 640, 70) {
+            fs::write(preview_path, resized)?;
+            return Ok(true);
+        }
+    }
+
+    fs::write(preview_path, &data)?;
+    Ok(true)
+}
+
+fn preview_is_fresh(path: &Path, preview_path: &Path) -> Result<bool> {
+    let source_modified = fs::metadata(path)?.modified().ok();
+    if let (Some(src), Ok(prev_meta)) = (source_modified, fs::metadata(preview_path)) {
+        if let Ok(prev_modified) = prev_meta.modified() {
+            return Ok(prev_modified >= src);
+        }
+    }
+    Ok(false)
+}
+
 fn extract_rating(exif: &exif::Exif) -> Option<i32> {
     const TAG_RATING: u16 = 0x4746; // Rating
     const TAG_RATING_PERCENT: u16 = 0x4749; // RatingPercent
@@ -151,11 +257,11 @@ fn extract_rating(exif: &exif::Exif) -> Option<i32> {
 
 fn parse_numeric(value: &Value) -> Option<i32> {
     match value {
-        Value::Byte(v) => v.get(0).map(|n| *n as i32),
-        Value::Short(v) => v.get(0).map(|n| *n as i32),
-        Value::Long(v) => v.get(0).map(|n| *n as i32),
-        Value::SShort(v) => v.get(0).map(|n| *n as i32),
-        Value::SLong(v) => v.get(0).map(|n| *n as i32),
+        Value::Byte(v) => v.first().map(|n| *n as i32),
+        Value::Short(v) => v.first().map(|n| *n as i32),
+        Value::Long(v) => v.first().map(|n| *n as i32),
+        Value::SShort(v) => v.first().map(|n| *n as i32),
+        Value::SLong(v) => v.first().copied(),
         _ => None,
     }
 }
@@ -168,6 +274,22 @@ fn extract_datetime(exif: &exif::Exif) -> Option<String> {
     field.map(|f| f.display_value().with_unit(exif).to_string())
 }
 
+fn extract_ascii(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    let Value::Ascii(values) = &field.value else {
+        return None;
+    };
+    let raw = values.first()?;
+    let text = String::from_utf8_lossy(raw)
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
 fn extract_orientation(exif: &exif::Exif) -> Option<i32> {
     exif
         .get_field(Tag::Orientation, In::PRIMARY)
@@ -223,32 +345,66 @@ fn rational_to_f64(value: &exif::Rational) -> f64 {
     value.num as f64 / value.denom as f64
 }
 
-fn extract_xmp_rating_from_path(path: &Path) -> Result<Option<i32>> {
-    let data = fs::read(path)?;
-    extract_xmp_rating_from_bytes(&data)
+/// Writes the title and caption into the file's XMP sidecar as the default
+/// language entries of `dc:title` and `dc:description`. A field given as
+/// `None` is left as it is; `Some(None)` removes it.
+pub fn write_sidecar_text(path: &Path, title: Option<Option<&str>>, caption: Option<Option<&str>>) -> Result<()> {
+    update_sidecar(path, |xmp| {
+        let mut xmp = xmp.to_string();
+        if let Some(title) = title {
+            xmp = set_xmp_property(&xmp, "dc:title", title.map(|title| xmp_alt("dc:title", title)));
+        }
+        if let Some(caption) = caption {
+            xmp = set_xmp_property(
+                &xmp,
+                "dc:description",
+                caption.map(|caption| xmp_alt("dc:description", caption)),
+            );
+        }
+        xmp
+    })
+}
+
+/// Writes a copyright notice into the file's XMP sidecar as `dc:rights`.
+pub fn write_sidecar_rights(path: &Path, rights: &str) -> Result<()> {
+    update_sidecar(path, |xmp| {
+        set_xmp_property(xmp, "dc:rights", Some(xmp_alt("dc:rights", rights)))
+    })
+}
+
+/// Looks for an XMP packet in the file. Most cameras put it in the header or
+/// the first IFDs, so the first `XMP_SCAN_LEN` bytes are searched on their
+/// own; some RAWs carry it after the embedded previews, so without a packet
+/// there the rest of the file is read as well.
+fn read_embedded_xmp(path: &Path) -> Result<Option<String>> {
+    let mut file = fs::File::open(path)?;
+    let mut data = Vec::new();
+    (&mut file).take(XMP_SCAN_LEN).read_to_end(&mut data)?;
+    if let Some(packet) = find_xmp_packet(&data) {
+        return Ok(Some(packet));
+    }
+    file.read_to_end(&mut data)?;
+    Ok(find_xmp_packet(&data))
 }
 
-fn extract_sidecar_rating(path: &Path) -> Result<Option<i32>> {
-    let sidecar = path.with_extension("xmp");
+fn read_sidecar_xmp(path: &Path) -> Result<Option<String>> {
+    let sidecar = sidecar_path(path);
     if !sidecar.exists() {
         return Ok(None);
     }
     let data = fs::read(sidecar)?;
-    extract_xmp_rating_from_bytes(&data)
+    Ok(find_xmp_packet(&data))
 }
 
-fn extract_xmp_rating_from_bytes(data: &[u8]) -> Result<Option<i32>> {
+fn find_xmp_packet(data: &[u8]) -> Option<String> {
     let data_str = String::from_utf8_lossy(data);
     let start = data_str.find("<x:xmpmeta");
     let end = data_str.find("</x:xmpmeta>");
 
-    let (start, end) = match (start, end) {
-        (Some(s), Some(e)) if e > s => (s, e + "</x:xmpmeta>".len()),
-        _ => return Ok(None),
-    };
-
-    let xmp = &data_str[start..end];
-    Ok(parse_xmp_rating(xmp))
+    match (start, end) {
+        (Some(s), Some(e)) if e > s => Some(data_str[s..e + "</x:xmpmeta>".len()].to_string()),
+        _ => None,
+    }
 }
 
 fn parse_xmp_rating(xmp: &str) -> Option<i32> {
@@ -282,6 +438,230 @@ fn parse_xmp_rating(xmp: &str) -> Option<i32> {
     None
 }
 
+fn parse_xmp_keywords(xmp: &str) -> Vec<String> {
+    let mut keywords = tags::normalize_tags(xmp_list_items(xmp, "lr:hierarchicalSubject"));
+    for subject in xmp_list_items(xmp, "dc:subject") {
+        let lowered = subject.trim().to_lowercase();
+        let covered = keywords.iter().any(|keyword| {
+            tags::segments(keyword).any(|segment| segment.to_lowercase() == lowered)
+        });
+        if !covered {
+            keywords.push(subject);
+        }
+    }
+    tags::normalize_tags(keywords)
+}
+
+fn xmp_list_items(xmp: &str, qname: &str) -> Vec<String> {
+    let Some((start, end)) = find_xmp_element(xmp, qname) else {
+        return Vec::new();
+    };
+
+    let mut items = Vec::new();
+    let mut rest = &xmp[start..end];
+    while let Some(open) = rest.find("<rdf:li") {
+        let tail = &rest[open + "<rdf:li".len()..];
+        let Some(gt) = tail.find('>') else {
+            break;
+        };
+        if tail[..gt].ends_with('/') {
+            rest = &tail[gt + 1..];
+            continue;
+        }
+        let content = &tail[gt + 1..];
+        let Some(close) = content.find("</rdf:li>") else {
+            break;
+        };
+        items.push(xml_unescape(content[..close].trim()));
+        rest = &content[close + "</rdf:li>".len()..];
+    }
+    items
+}
+
+fn xmp_alt_text(xmp: &str, qname: &str) -> Option<String> {
+    xmp_list_items(xmp, qname)
+        .into_iter()
+        .find(|item| !item.is_empty())
+}
+
+/// Locates `<qname ...>...</qname>` (or a self-closing `<qname/>`) and returns
+/// its byte range, including the tags themselves.
+fn find_xmp_element(xmp: &str, qname: &str) -> Option<(usize, usize)> {
+    let open = format!("<{qname}");
+    let mut offset = 0;
+    while let Some(pos) = xmp[offset..].find(&open) {
+        let start = offset + pos;
+        let after = start + open.len();
+        match xmp[after..].chars().next() {
+            Some(c) if c == '>' || c == '/' || c.is_whitespace() => {
+                let gt = after + xmp[after..].find('>')?;
+                if xmp[..gt].ends_with('/') {
+                    return Some((start, gt + 1));
+                }
+                let close = format!("</{qname}>");
+                let end = gt + xmp[gt..].find(&close)? + close.len();
+                return Some((start, end));
+            }
+            _ => offset = after,
+        }
+    }
+    None
+}
+
+fn xmp_bag(qname: &str, items: &[String]) -> Option<String> {
+    if items.is_empty() {
+        return None;
+    }
+    let mut element = format!("   <{qname}>\n    <rdf:Bag>\n");
+    for item in items {
+        element.push_str("     <rdf:li>");
+        element.push_str(&xml_escape(item));
+        element.push_str("</rdf:li>\n");
+    }
+    element.push_str(&format!("    </rdf:Bag>\n   </{qname}>\n"));
+    Some(element)
+}
+
+fn xmp_alt(qname: &str, value: &str) -> String {
+    format!(
+        "   <{qname}>\n    <rdf:Alt>\n     <rdf:li xml:lang=\"x-default\">{}</rdf:li>\n    </rdf:Alt>\n   </{qname}>\n",
+        xml_escape(value)
+    )
+}
+
+/// Replaces every occurrence of the `qname` property with `element`, or just
+/// removes it when `element` is `None`.
+fn set_xmp_property(xmp: &str, qname: &str, element: Option<String>) -> String {
+    let mut xmp = xmp.to_string();
+    while let Some((start, mut end)) = find_xmp_element(&xmp, qname) {
+        let line_start = xmp[..start].trim_end_matches([' ', '\t']).len();
+        if xmp[..line_start].ends_with('\n') && xmp[end..].starts_with('\n') {
+            end += 1;
+        }
+        xmp.replace_range(line_start..end, "");
+    }
+
+    let Some(element) = element else {
+        return xmp;
+    };
+
+    if let Some((prefix, _)) = qname.split_once(':') {
+        xmp = ensure_xmp_namespace(&xmp, prefix);
+    }
+
+    let close = match xmp.find("</rdf:Description>") {
+        Some(close) => close,
+        None => {
+            let Some(open) = xmp.find("<rdf:Description") else {
+                return xmp;
+            };
+            let Some(gt) = xmp[open..].find("/>").map(|gt| open + gt) else {
+                return xmp;
+            };
+            xmp.replace_range(gt..gt + 2, ">\n  </rdf:Description>");
+            gt + 2
+        }
+    };
+
+    let line_start = xmp[..close].rfind('\n').map(|n| n + 1).unwrap_or(0);
+    if xmp[line_start..close].trim().is_empty() {
+        xmp.insert_str(line_start, &element);
+    } else {
+        xmp.insert_str(close, &format!("\n{element}"));
+    }
+    xmp
+}
+
+fn ensure_xmp_namespace(xmp: &str, prefix: &str) -> String {
+    let uri = match prefix {
+        "dc" => "http://purl.org/dc/elements/1.1/",
+        "lr" => "http://ns.adobe.com/lightroom/1.0/",
+        "xmp" => "http://ns.adobe.com/xap/1.0/",
+        _ => return xmp.to_string(),
+    };
+    if xmp.contains(&format!("xmlns:{prefix}=")) {
+        return xmp.to_string();
+    }
+    let mut xmp = xmp.to_string();
+    if let Some(open) = xmp.find("<rdf:Description") {
+        let insert_at = open + "<rdf:Description".len();
+        xmp.insert_str(insert_at, &format!("\n    xmlns:{prefix}=\"{uri}\""));
+    }
+    xmp
+}
+
+fn update_sidecar(path: &Path, update: impl FnOnce(&str) -> String) -> Result<()> {
+    let sidecar = sidecar_path(path);
+    let existing = if sidecar.exists() {
+        Some(fs::read_to_string(&sidecar).with_context(|| format!("read {:?}", sidecar))?)
+    } else {
+        None
+    };
+    let base = existing
+        .as_deref()
+        .filter(|xmp| xmp.contains("<rdf:Description"))
+        .unwrap_or(XMP_TEMPLATE);
+    let updated = update(base);
+
+    let tmp = sidecar.with_extension("xmp.tmp");
+    fs::write(&tmp, updated).with_context(|| format!("write {:?}", tmp))?;
+    fs::rename(&tmp, &sidecar).with_context(|| format!("rename {:?}", tmp))?;
+    Ok(())
+}
+
+fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn xml_unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(amp) = rest.find('&') {
+        unescaped.push_str(&rest[..amp]);
+        let tail = &rest[amp..];
+        let Some(semi) = tail.find(';') else {
+            unescaped.push_str(tail);
+            return unescaped;
+        };
+        let entity = &tail[1..semi];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                unescaped.push(c);
+                rest = &tail[semi + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &tail[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
 fn jpeg_orientation(data: &[u8]) -> Option<i32> {
     let mut cursor = Cursor::new(data);
     let mut reader = BufReader::new(&mut cursor);
@@ -0,0 +1,546 @@
+use anyhow::{bail, Context, Result};
+use sqlx::{Row, SqliteConnection, SqlitePool};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Ordered schema steps. Each one runs in its own transaction and is recorded
+/// in `schema_version`. Released steps must never change; append new ones.
+const MIGRATIONS: &[(i64, &str)] = &[
+    (1, "files table"),
+    (2, "file metadata columns"),
+    (3, "albums"),
+    (4, "saved searches"),
+    (5, "edit history"),
+    (6, "filesystem journal"),
+    (7, "trash"),
+    (8, "transfer jobs"),
+    (9, "upload sessions"),
+    (10, "ingest"),
+    (11, "hot folders"),
+    (12, "camera backfill"),
+    (13, "finished uploads"),
+];
+
+const BASE_FILE_COLUMNS: &[(&str, &str)] = &[
+    ("camera_rating", "INTEGER"),
+    ("user_rating", "INTEGER"),
+    ("tags", "TEXT"),
+    ("gps_lat", "REAL"),
+    ("gps_lon", "REAL"),
+    ("taken_at", "TEXT"),
+    ("orientation", "INTEGER"),
+    ("file_size", "INTEGER NOT NULL DEFAULT 0"),
+    ("last_modified", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+pub async fn run(pool: &SqlitePool, db_path: &Path) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_version (\
+            version INTEGER PRIMARY KEY,\
+            name TEXT NOT NULL,\
+            applied_at INTEGER NOT NULL\
+        );",
+    )
+    .execute(pool)
+    .await?;
+
+    let current: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM schema_version")
+        .fetch_one(pool)
+        .await?;
+    let pending: Vec<&(i64, &str)> = MIGRATIONS
+        .iter()
+        .filter(|(version, _)| *version > current)
+        .collect();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    if has_data_tables(pool).await? {
+        let backup = backup_database(pool, db_path, current).await?;
+        info!("Backed up database to {} before schema upgrade", backup.display());
+    }
+
+    for (version, name) in pending {
+        let mut tx = pool.begin().await?;
+        apply(&mut tx, *version)
+            .await
+            .with_context(|| format!("schema migration {version} ({name})"))?;
+        sqlx::query("INSERT INTO schema_version (version, name, applied_at) VALUES (?, ?, ?)")
+            .bind(version)
+            .bind(name)
+            .bind(unix_now())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        info!("Applied schema migration {version}: {name}");
+    }
+
+    Ok(())
+}
+
+async fn apply(conn: &mut SqliteConnection, version: i64) -> Result<()> {
+    match version {
+        1 => migrate_files_table(conn).await,
+        2 => {
+            add_missing_columns(
+                conn,
+                "files",
+                &[
+                    ("tags_updated_at", "INTEGER"),
+                    ("camera_make", "TEXT"),
+                    ("camera_model", "TEXT"),
+                    ("color_label", "TEXT"),
+                    ("flag", "TEXT"),
+                    ("title", "TEXT"),
+                    ("caption", "TEXT"),
+                    ("notes", "TEXT"),
+                ],
+            )
+            .await
+        }
+        3 => {
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS albums (\
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,\
+                    name TEXT NOT NULL,\
+                    description TEXT,\
+                    created_at INTEGER NOT NULL,\
+                    updated_at INTEGER NOT NULL\
+                );",
+            )
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS album_items (\
+                    album_id INTEGER NOT NULL REFERENCES albums(id) ON DELETE CASCADE,\
+                    path TEXT NOT NULL,\
+                    position INTEGER NOT NULL,\
+                    added_at INTEGER NOT NULL,\
+                    PRIMARY KEY (album_id, path)\
+                );",
+            )
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query("CREATE INDEX IF NOT EXISTS album_items_path ON album_items(path);")
+                .execute(&mut *conn)
+                .await?;
+            Ok(())
+        }
+        4 => {
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS saved_searches (\
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,\
+                    name TEXT NOT NULL,\
+                    filter TEXT NOT NULL,\
+                    created_at INTEGER NOT NULL,\
+                    updated_at INTEGER NOT NULL\
+                );",
+            )
+            .execute(&mut *conn)
+            .await?;
+            Ok(())
+        }
+        5 => {
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS history_operations (\
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,\
+                    kind TEXT NOT NULL,\
+                    actor TEXT NOT NULL,\
+                    created_at INTEGER NOT NULL,\
+                    undone_at INTEGER\
+                );",
+            )
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS history_entries (\
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,\
+                    operation_id INTEGER NOT NULL REFERENCES history_operations(id) ON DELETE CASCADE,\
+                    path TEXT NOT NULL,\
+                    field TEXT NOT NULL,\
+                    old_value TEXT NOT NULL,\
+                    new_value TEXT NOT NULL\
+                );",
+            )
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query("CREATE INDEX IF NOT EXISTS history_entries_path ON history_entries(path);")
+                .execute(&mut *conn)
+                .await?;
+            sqlx::query("CREATE INDEX IF NOT EXISTS history_entries_operation ON history_entries(operation_id);")
+                .execute(&mut *conn)
+                .await?;
+            Ok(())
+        }
+        6 => {
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS fs_journal (\
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,\
+                    batch INTEGER NOT NULL,\
+                    op TEXT NOT NULL,\
+                    source TEXT NOT NULL,\
+                    target TEXT NOT NULL,\
+                    is_dir INTEGER NOT NULL,\
+                    state TEXT NOT NULL,\
+                    created_at INTEGER NOT NULL\
+                );",
+            )
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query("CREATE INDEX IF NOT EXISTS fs_journal_batch ON fs_journal(batch);")
+                .execute(&mut *conn)
+                .await?;
+            Ok(())
+        }
+        7 => {
+            add_missing_columns(conn, "files", &[("deleted_at", "INTEGER")]).await?;
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS trash (\
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,\
+                    original_path TEXT NOT NULL,\
+                    trash_path TEXT NOT NULL UNIQUE,\
+                    is_dir INTEGER NOT NULL,\
+                    size INTEGER NOT NULL,\
+                    deleted_at INTEGER NOT NULL\
+                );",
+            )
+            .execute(&mut *conn)
+            .await?;
+            Ok(())
+        }
+        8 => {
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS jobs (\
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,\
+                    kind TEXT NOT NULL,\
+                    state TEXT NOT NULL,\
+                    error TEXT,\
+                    total_bytes INTEGER NOT NULL DEFAULT 0,\
+                    done_bytes INTEGER NOT NULL DEFAULT 0,\
+                    created_at INTEGER NOT NULL,\
+                    updated_at INTEGER NOT NULL\
+                );",
+            )
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS job_items (\
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,\
+                    job_id INTEGER NOT NULL REFERENCES jobs(id) ON DELETE CASCADE,\
+                    source TEXT NOT NULL,\
+                    target TEXT NOT NULL,\
+                    is_dir INTEGER NOT NULL,\
+                    size INTEGER NOT NULL,\
+                    state TEXT NOT NULL\
+                );",
+            )
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query("CREATE INDEX IF NOT EXISTS job_items_job ON job_items(job_id);")
+                .execute(&mut *conn)
+                .await?;
+            Ok(())
+        }
+        9 => {
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS upload_sessions (\
+                    id TEXT PRIMARY KEY,\
+                    directory TEXT NOT NULL,\
+                    name TEXT NOT NULL,\
+                    size INTEGER NOT NULL,\
+                    received INTEGER NOT NULL DEFAULT 0,\
+                    sha256 TEXT,\
+                    created_at INTEGER NOT NULL,\
+                    updated_at INTEGER NOT NULL\
+                );",
+            )
+            .execute(&mut *conn)
+            .await?;
+            Ok(())
+        }
+        10 => {
+            add_missing_columns(conn, "jobs", &[("options", "TEXT")]).await?;
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS ingested_files (\
+                    hash TEXT PRIMARY KEY,\
+                    path TEXT NOT NULL,\
+                    ingested_at INTEGER NOT NULL\
+                );",
+            )
+            .execute(&mut *conn)
+            .await?;
+            Ok(())
+        }
+        11 => {
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS hot_folders (\
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,\
+                    path TEXT NOT NULL UNIQUE,\
+                    destination TEXT NOT NULL,\
+                    settings TEXT NOT NULL DEFAULT '{}',\
+                    enabled INTEGER NOT NULL DEFAULT 1,\
+                    created_at INTEGER NOT NULL,\
+                    updated_at INTEGER NOT NULL\
+                );",
+            )
+            .execute(&mut *conn)
+            .await?;
+            Ok(())
+        }
+        12 => {
+            // Rows scanned before camera make and model were extracted count
+            // as fresh forever. Clearing their orientation marks them as not
+            // scanned, so the next browse or scan reads the file again; the
+            // size and mtime stay true and edits are kept.
+            sqlx::query(
+                "UPDATE files SET orientation = NULL \
+                 WHERE orientation IS NOT NULL AND camera_make IS NULL AND camera_model IS NULL",
+            )
+            .execute(&mut *conn)
+            .await?;
+            Ok(())
+        }
+        13 => add_missing_columns(conn, "upload_sessions", &[("path", "TEXT")]).await,
+        _ => bail!("unknown schema version {version}"),
+    }
+}
+
+/// Brings `files` to the original layout and copies in any `files_legacy_*`
+/// table older releases set aside. Rows are matched to library files by
+/// their `path` column; a table without one fails the migration rather than
+/// being dropped or left behind, so no ratings or tags go missing unnoticed.
+async fn migrate_files_table(conn: &mut SqliteConnection) -> Result<()> {
+    let columns = list_columns(conn, "files").await?;
+    if !columns.is_empty() && !columns.contains("path") {
+        bail!(no_path_column("files", &columns));
+    }
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS files (\
+            path TEXT PRIMARY KEY,\
+            camera_rating INTEGER,\
+            user_rating INTEGER,\
+            tags TEXT,\
+            gps_lat REAL,\
+            gps_lon REAL,\
+            taken_at TEXT,\
+            orientation INTEGER,\
+            file_size INTEGER NOT NULL,\
+            last_modified INTEGER NOT NULL\
+        );",
+    )
+    .execute(&mut *conn)
+    .await?;
+    add_missing_columns(conn, "files", BASE_FILE_COLUMNS).await?;
+
+    let legacy_tables: Vec<String> = sqlx::query_scalar(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE 'files\\_legacy\\_%' ESCAPE '\\' ORDER BY name DESC",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    // Newest first, so its rows win when several tables hold the same path.
+    for table in legacy_tables {
+        import_legacy_table(conn, &table).await?;
+    }
+
+    Ok(())
+}
+
+async fn import_legacy_table(conn: &mut SqliteConnection, table: &str) -> Result<()> {
+    let columns = list_columns(conn, table).await?;
+    if !columns.contains("path") {
+        bail!(no_path_column(table, &columns));
+    }
+
+    let path_expr = "trim(path, '/')";
+    let mut targets = vec!["path".to_string()];
+    let mut sources = vec![path_expr.to_string()];
+    for (name, ty) in BASE_FILE_COLUMNS {
+        let not_null = ty.contains("NOT NULL");
+        if columns.contains(*name) {
+            targets.push(name.to_string());
+            if not_null {
+                sources.push(format!("COALESCE({}, 0)", quote_ident(name)));
+            } else {
+                sources.push(quote_ident(name));
+            }
+        } else if not_null {
+            targets.push(name.to_string());
+            sources.push("0".to_string());
+        }
+    }
+
+    let sql = format!(
+        "INSERT OR IGNORE INTO files ({}) SELECT {} FROM {} WHERE {path_expr} IS NOT NULL AND {path_expr} != ''",
+        targets.join(", "),
+        sources.join(", "),
+        quote_ident(table),
+    );
+    let migrated = sqlx::query(&sql).execute(&mut *conn).await?.rows_affected();
+
+    let drop_sql = format!("DROP TABLE {}", quote_ident(table));
+    sqlx::query(&drop_sql).execute(&mut *conn).await?;
+    info!("Migrated {migrated} rows from legacy table {table}");
+    Ok(())
+}
+
+fn no_path_column(table: &str, columns: &HashSet<String>) -> String {
+    let mut columns: Vec<&str> = columns.iter().map(String::as_str).collect();
+    columns.sort();
+    format!(
+        "table {table} has no path column (it has {}), so its rows cannot be matched to library files. \
+         Rename the column holding the library-relative paths to path, or drop the table if its rows \
+         are not needed, and start again; nothing was changed",
+        columns.join(", ")
+    )
+}
+
+async fn add_missing_columns(
+    conn: &mut SqliteConnection,
+    table: &str,
+    required: &[(&str, &str)],
+) -> Result<()> {
+    let columns = list_columns(conn, table).await?;
+    for (name, ty) in required {
+        if !columns.contains(*name) {
+            let sql = format!("ALTER TABLE {} ADD COLUMN {} {}", quote_ident(table), name, ty);
+            sqlx::query(&sql).execute(&mut *conn).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn list_columns(conn: &mut SqliteConnection, table: &str) -> Result<HashSet<String>> {
+    let query = format!("PRAGMA table_info({});", quote_ident(table));
+    let rows = sqlx::query(&query).fetch_all(&mut *conn).await?;
+    let mut columns = HashSet::new();
+    for row in rows {
+        let name: String = row.get("name");
+        columns.insert(name);
+    }
+    Ok(columns)
+}
+
+async fn has_data_tables(pool: &SqlitePool) -> Result<bool> {
+    let count: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' AND name != 'schema_version'",
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(count > 0)
+}
+
+/// Writes a consistent copy of the database next to it, e.g.
+/// `raw-manager.db.v0-1700000000.bak`.
+async fn backup_database(pool: &SqlitePool, db_path: &Path, version: i64) -> Result<PathBuf> {
+    let file_name = db_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "raw-manager.db".to_string());
+    let backup = db_path.with_file_name(format!("{file_name}.v{version}-{}.bak", unix_now()));
+    sqlx::query("VACUUM INTO ?")
+        .bind(backup.to_string_lossy().to_string())
+        .execute(pool)
+        .await
+        .with_context(|| format!("backup database to {:?}", backup))?;
+    Ok(backup)
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    /// An in-memory database holding `setup`, and a folder for the backup
+    /// taken before migrating it.
+    async fn legacy_database(setup: &[&str], name: &str) -> (SqlitePool, PathBuf) {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        for sql in setup {
+            sqlx::query(sql).execute(&pool).await.unwrap();
+        }
+        let dir = std::env::temp_dir().join(format!("raw-manager-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        (pool, dir)
+    }
+
+    #[tokio::test]
+    async fn files_table_without_path_fails_instead_of_being_set_aside() {
+        let (pool, dir) = legacy_database(
+            &[
+                "CREATE TABLE files (name TEXT, rating INTEGER)",
+                "INSERT INTO files VALUES ('a.ARW', 5)",
+            ],
+            "no-path",
+        )
+        .await;
+        let err = run(&pool, &dir.join("test.db")).await.unwrap_err();
+        assert!(format!("{err:#}").contains("table files has no path column (it has name, rating)"));
+        let rating: i64 = sqlx::query_scalar("SELECT rating FROM files").fetch_one(&pool).await.unwrap();
+        assert_eq!(rating, 5);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn legacy_table_with_path_is_migrated() {
+        let (pool, dir) = legacy_database(
+            &[
+                "CREATE TABLE files_legacy_1 (path TEXT, user_rating INTEGER, tags TEXT)",
+                "INSERT INTO files_legacy_1 VALUES ('/day1/a.ARW', 4, '[\"Paris\"]')",
+            ],
+            "legacy",
+        )
+        .await;
+        run(&pool, &dir.join("test.db")).await.unwrap();
+        let (path, rating): (String, i64) = sqlx::query_as("SELECT path, user_rating FROM files")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!((path.as_str(), rating), ("day1/a.ARW", 4));
+        let left: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE name = 'files_legacy_1'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(left, 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn camera_backfill_marks_rows_unscanned_and_keeps_mtime() {
+        let (pool, dir) = legacy_database(
+            &[
+                "CREATE TABLE files (path TEXT PRIMARY KEY, camera_rating INTEGER, user_rating INTEGER, \
+                 tags TEXT, gps_lat REAL, gps_lon REAL, taken_at TEXT, orientation INTEGER, \
+                 file_size INTEGER NOT NULL, last_modified INTEGER NOT NULL)",
+                "INSERT INTO files (path, user_rating, orientation, file_size, last_modified) \
+                 VALUES ('day1/a.ARW', 3, 1, 10, 1234)",
+            ],
+            "backfill",
+        )
+        .await;
+        run(&pool, &dir.join("test.db")).await.unwrap();
+        let (orientation, modified, rating): (Option<i64>, i64, i64) =
+            sqlx::query_as("SELECT orientation, last_modified, user_rating FROM files")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!((orientation, modified, rating), (None, 1234, 3));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
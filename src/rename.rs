@@ -0,0 +1,272 @@
+/// A parsed batch-rename template such as
+/// `{taken:%Y%m%d}_{camera}_{seq:04}{ext}`.
+///
+/// Placeholders: `{name}` (the current name without extension), `{ext}`
+/// (the current extension including the dot), `{seq}` / `{seq:04}` (position
+/// in the batch, optionally zero-padded), `{taken}` / `{taken:<format>}`
+/// (capture time, `%Y %y %m %d %H %M %S %%`), `{camera}` (model, or make when
+/// the model is unknown), `{make}`, `{model}` and, for ingest folders,
+/// `{shoot}`.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Name,
+    Ext,
+    Seq { width: usize },
+    Taken { format: String },
+    Camera,
+    Make,
+    Model,
+    Shoot,
+}
+
+/// What a template can refer to for one file.
+#[derive(Debug, Clone, Default)]
+pub struct RenameFields<'a> {
+    pub stem: &'a str,
+    pub ext: &'a str,
+    pub seq: u64,
+    pub taken_at: Option<&'a str>,
+    pub make: Option<&'a str>,
+    pub model: Option<&'a str>,
+    pub shoot: Option<&'a str>,
+}
+
+impl Template {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = raw.chars();
+        while let Some(c) = chars.next() {
+            if c != '{' {
+                if c == '}' {
+                    return Err("Unmatched } in template".to_string());
+                }
+                text.push(c);
+                continue;
+            }
+            let mut placeholder = String::new();
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(c) => placeholder.push(c),
+                    None => return Err("Unclosed { in template".to_string()),
+                }
+            }
+            if !text.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut text)));
+            }
+            parts.push(parse_placeholder(&placeholder)?);
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        if parts.is_empty() {
+            return Err("Template is empty".to_string());
+        }
+        Ok(Self { parts })
+    }
+
+    /// Whether rendering needs camera data (capture time, make or model).
+    pub fn needs_metadata(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Taken { .. } | Part::Camera | Part::Make | Part::Model))
+    }
+
+    pub fn render(&self, fields: &RenameFields) -> Result<String, String> {
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => name.push_str(text),
+                Part::Name => name.push_str(fields.stem),
+                Part::Ext => name.push_str(fields.ext),
+                Part::Seq { width } => name.push_str(&format!("{:0width$}", fields.seq, width = *width)),
+                Part::Taken { format } => {
+                    let taken_at = fields
+                        .taken_at
+                        .ok_or_else(|| "No capture date".to_string())?;
+                    name.push_str(&format_taken(taken_at, format)?);
+                }
+                Part::Camera => {
+                    let camera = fields.model.or(fields.make).unwrap_or("unknown");
+                    name.push_str(&name_safe(camera));
+                }
+                Part::Make => name.push_str(&name_safe(fields.make.unwrap_or("unknown"))),
+                Part::Model => name.push_str(&name_safe(fields.model.unwrap_or("unknown"))),
+                Part::Shoot => name.push_str(&name_safe(fields.shoot.unwrap_or_default())),
+            }
+        }
+        Ok(name)
+    }
+}
+
+fn parse_placeholder(raw: &str) -> Result<Part, String> {
+    let (key, arg) = match raw.split_once(':') {
+        Some((key, arg)) => (key.trim(), Some(arg)),
+        None => (raw.trim(), None),
+    };
+    let part = match (key, arg) {
+        ("name", None) => Part::Name,
+        ("ext", None) => Part::Ext,
+        ("seq", None) => Part::Seq { width: 1 },
+        ("seq", Some(width)) => {
+            let width = width
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|width| (1..=12).contains(width))
+                .ok_or_else(|| format!("Invalid sequence width: {width}"))?;
+            Part::Seq { width }
+        }
+        ("taken", format) => {
+            let format = format.unwrap_or("%Y-%m-%d").to_string();
+            // Checked up front so a bad format fails the template, not each file.
+            format_taken("2000-01-01 00:00:00", &format)?;
+            Part::Taken { format }
+        }
+        ("camera", None) => Part::Camera,
+        ("make", None) => Part::Make,
+        ("model", None) => Part::Model,
+        ("shoot", None) => Part::Shoot,
+        _ => return Err(format!("Unknown placeholder {{{raw}}}")),
+    };
+    Ok(part)
+}
+
+/// Formats an EXIF capture time (`2024-05-01 14:03:22`) with a small
+/// strftime subset.
+fn format_taken(taken_at: &str, format: &str) -> Result<String, String> {
+    let numbers: Vec<&str> = taken_at
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .collect();
+    if numbers.len() < 3 || numbers[0].len() != 4 {
+        return Err(format!("Unreadable capture date: {taken_at}"));
+    }
+    let field = |index: usize| {
+        numbers
+            .get(index)
+            .map(|value| format!("{:0>2}", value))
+            .unwrap_or_else(|| "00".to_string())
+    };
+
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(numbers[0]),
+            Some('y') => out.push_str(&numbers[0][2..]),
+            Some('m') => out.push_str(&field(1)),
+            Some('d') => out.push_str(&field(2)),
+            Some('H') => out.push_str(&field(3)),
+            Some('M') => out.push_str(&field(4)),
+            Some('S') => out.push_str(&field(5)),
+            Some('%') => out.push('%'),
+            Some(other) => return Err(format!("Unsupported date format %{other}")),
+            None => return Err("Date format ends with %".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+/// Camera names go into file names, so spaces become dashes and anything
+/// that is not a plain name character is dropped.
+fn name_safe(value: &str) -> String {
+    value
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> RenameFields<'static> {
+        RenameFields {
+            stem: "DSC0001",
+            ext: ".ARW",
+            seq: 7,
+            taken_at: Some("2024:05:01 14:03:22"),
+            make: Some("SONY"),
+            model: Some("ILCE-7M4"),
+            shoot: None,
+        }
+    }
+
+    fn render(template: &str, fields: &RenameFields) -> Result<String, String> {
+        Template::parse(template)?.render(fields)
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        assert_eq!(
+            render("{taken:%Y%m%d}_{camera}_{seq:04}{ext}", &fields()).unwrap(),
+            "20240501_ILCE-7M4_0007.ARW"
+        );
+        assert_eq!(render("{name}-{seq}{ext}", &fields()).unwrap(), "DSC0001-7.ARW");
+        assert_eq!(render("{make} {model}", &fields()).unwrap(), "SONY ILCE-7M4");
+        assert_eq!(render("{taken}", &fields()).unwrap(), "2024-05-01");
+        assert_eq!(render("{taken:%y%%%H%M%S}", &fields()).unwrap(), "24%140322");
+    }
+
+    #[test]
+    fn camera_falls_back_to_make_then_unknown() {
+        let mut fields = fields();
+        fields.model = None;
+        assert_eq!(render("{camera}", &fields).unwrap(), "SONY");
+        fields.make = None;
+        assert_eq!(render("{camera}|{make}", &fields).unwrap(), "unknown|unknown");
+    }
+
+    #[test]
+    fn camera_names_are_made_file_safe() {
+        let mut fields = fields();
+        fields.model = Some("  Canon EOS R5 / C ");
+        assert_eq!(render("{model}", &fields).unwrap(), "Canon-EOS-R5--C");
+        fields.shoot = Some("Smith wedding");
+        assert_eq!(render("{shoot}", &fields).unwrap(), "Smith-wedding");
+    }
+
+    #[test]
+    fn missing_or_unreadable_date_fails_render() {
+        let mut fields = fields();
+        fields.taken_at = None;
+        assert!(render("{taken}", &fields).is_err());
+        fields.taken_at = Some("unknown");
+        assert!(render("{taken}", &fields).is_err());
+        assert!(render("{name}", &fields).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        let templates = [
+            "", "{name", "name}", "{nope}", "{seq:0}", "{seq:13}", "{seq:x}", "{taken:%Q}", "{taken:%}", "{ext:x}",
+        ];
+        for template in templates {
+            assert!(Template::parse(template).is_err(), "{template} should not parse");
+        }
+    }
+
+    #[test]
+    fn needs_metadata_only_for_camera_fields() {
+        assert!(!Template::parse("{name}_{seq:03}{ext}").unwrap().needs_metadata());
+        assert!(!Template::parse("{shoot}").unwrap().needs_metadata());
+        for template in ["{taken}", "{camera}", "{make}", "{model}"] {
+            assert!(Template::parse(template).unwrap().needs_metadata());
+        }
+    }
+}
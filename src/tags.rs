@@ -0,0 +1,378 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+pub const HIERARCHY_SEPARATOR: char = '|';
+
+const RECENCY_HALF_LIFE_DAYS: f64 = 14.0;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagNode {
+    pub name: String,
+    pub path: String,
+    /// Files tagged with exactly this keyword.
+    pub count: i64,
+    /// Files tagged with this keyword or any keyword below it, each counted
+    /// once.
+    pub total: i64,
+    pub children: Vec<TagNode>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagUsage {
+    pub tag: String,
+    pub count: i64,
+    pub last_used: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagSuggestion {
+    pub tag: String,
+    pub count: i64,
+    pub last_used: i64,
+    pub related: bool,
+}
+
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let segments: Vec<&str> = tag
+        .split(HIERARCHY_SEPARATOR)
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    if segments.is_empty() {
+        return None;
+    }
+    Some(segments.join(&HIERARCHY_SEPARATOR.to_string()))
+}
+
+pub fn normalize_tags<I, S>(tags: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut seen = HashSet::new();
+    let mut normalized = Vec::new();
+    for tag in tags {
+        let Some(tag) = normalize_tag(tag.as_ref()) else {
+            continue;
+        };
+        if seen.insert(tag.to_lowercase()) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+pub fn segments(tag: &str) -> impl Iterator<Item = &str> {
+    tag.split(HIERARCHY_SEPARATOR)
+}
+
+/// Returns true when `tag` equals `ancestor` or sits anywhere below it in the
+/// keyword tree. Comparison is case-insensitive, matching how tags are deduped.
+pub fn matches_tag(tag: &str, ancestor: &str) -> bool {
+    let tag = tag.to_lowercase();
+    let ancestor = ancestor.to_lowercase();
+    if tag == ancestor {
+        return true;
+    }
+    tag.len() > ancestor.len()
+        && tag.starts_with(&ancestor)
+        && tag[ancestor.len()..].starts_with(HIERARCHY_SEPARATOR)
+}
+
+/// Returns true when `tag` sits below `ancestor` in the keyword tree, not at
+/// it. Renaming or merging `ancestor` into such a tag would nest the branch
+/// inside itself: `A` renamed to `A|B` turns `A|C` into `A|B|C` and `A|B`
+/// into `A|B|B`.
+pub fn is_below(tag: &str, ancestor: &str) -> bool {
+    matches_tag(tag, ancestor) && tag.len() > ancestor.len()
+}
+
+/// Moves `tag` (or the matching branch it belongs to) from under `from` to
+/// under `to`, e.g. renaming `Clients|Acme` turns `Clients|Acme|Spring26`
+/// into `<to>|Spring26`. Returns `None` when the tag is outside `from`.
+pub fn replace_prefix(tag: &str, from: &str, to: &str) -> Option<String> {
+    if !matches_tag(tag, from) {
+        return None;
+    }
+    let mut replaced = to.to_string();
+    for segment in segments(tag).skip(segments(from).count()) {
+        replaced.push(HIERARCHY_SEPARATOR);
+        replaced.push_str(segment);
+    }
+    Some(replaced)
+}
+
+/// Replaces every tag that falls under one of `sources` with the same branch
+/// under `target`, or drops it when `target` is `None`. Tag lists without a
+/// matching tag are returned untouched.
+pub fn retag(current: &[String], sources: &[String], target: Option<&str>) -> Vec<String> {
+    let is_affected = |tag: &String| sources.iter().any(|source| matches_tag(tag, source));
+    if !current.iter().any(is_affected) {
+        return current.to_vec();
+    }
+
+    let mut updated = Vec::new();
+    for tag in current {
+        match sources.iter().find(|source| matches_tag(tag, source)) {
+            Some(source) => {
+                if let Some(target) = target {
+                    updated.extend(replace_prefix(tag, source, target));
+                }
+            }
+            None => updated.push(tag.clone()),
+        }
+    }
+    normalize_tags(updated)
+}
+
+/// Aggregates per-file tag lists (with the time they were last edited) into
+/// one usage entry per tag, sorted alphabetically.
+pub fn usage<'a, I>(files: I) -> Vec<TagUsage>
+where
+    I: IntoIterator<Item = (&'a [String], i64)>,
+{
+    let mut by_key: HashMap<String, TagUsage> = HashMap::new();
+    for (tags, updated_at) in files {
+        for tag in normalize_tags(tags) {
+            let entry = by_key.entry(tag.to_lowercase()).or_insert_with(|| TagUsage {
+                tag,
+                count: 0,
+                last_used: 0,
+            });
+            entry.count += 1;
+            entry.last_used = entry.last_used.max(updated_at);
+        }
+    }
+
+    let mut usage: Vec<TagUsage> = by_key.into_values().collect();
+    usage.sort_by_key(|entry| entry.tag.to_lowercase());
+    usage
+}
+
+/// Folds usage entries whose tags only differ in case or spacing into one,
+/// sorted alphabetically like [`usage`].
+pub fn merge_usage<I>(entries: I) -> Vec<TagUsage>
+where
+    I: IntoIterator<Item = TagUsage>,
+{
+    let mut by_key: HashMap<String, TagUsage> = HashMap::new();
+    for entry in entries {
+        let Some(tag) = normalize_tag(&entry.tag) else {
+            continue;
+        };
+        let merged = by_key.entry(tag.to_lowercase()).or_insert_with(|| TagUsage {
+            tag,
+            count: 0,
+            last_used: 0,
+        });
+        merged.count += entry.count;
+        merged.last_used = merged.last_used.max(entry.last_used);
+    }
+
+    let mut usage: Vec<TagUsage> = by_key.into_values().collect();
+    usage.sort_by_key(|entry| entry.tag.to_lowercase());
+    usage
+}
+
+/// Counts how often each tag appears on the same file as one of `anchors`.
+/// Keys are lowercased; the anchors themselves are not counted.
+pub fn cooccurrence<'a, I>(files: I, anchors: &[String]) -> HashMap<String, i64>
+where
+    I: IntoIterator<Item = &'a [String]>,
+{
+    let anchors: HashSet<String> = anchors.iter().map(|tag| tag.to_lowercase()).collect();
+    let mut counts = HashMap::new();
+    if anchors.is_empty() {
+        return counts;
+    }
+
+    for tags in files {
+        let keys: Vec<String> = tags.iter().map(|tag| tag.to_lowercase()).collect();
+        if !keys.iter().any(|key| anchors.contains(key)) {
+            continue;
+        }
+        for key in keys {
+            if !anchors.contains(&key) {
+                *counts.entry(key).or_insert(0) += 1;
+            }
+        }
+    }
+    counts
+}
+
+/// Ranks tags for autocomplete. Tags whose full path starts with `prefix`
+/// come first, then tags with a matching keyword level, then substring
+/// matches; within each group the most used, most recently used and most
+/// related tags win. Tags in `exclude` (lowercased) are skipped.
+pub fn suggest(
+    usage: &[TagUsage],
+    prefix: &str,
+    related: &HashMap<String, i64>,
+    exclude: &HashSet<String>,
+    now: i64,
+    limit: usize,
+) -> Vec<TagSuggestion> {
+    let needle = prefix.trim().to_lowercase();
+    let mut ranked = Vec::new();
+
+    for entry in usage {
+        let key = entry.tag.to_lowercase();
+        if exclude.contains(&key) {
+            continue;
+        }
+        let Some(class) = match_class(&key, &needle) else {
+            continue;
+        };
+        let related_count = related.get(&key).copied().unwrap_or(0);
+        let age_days = (now - entry.last_used).max(0) as f64 / 86_400.0;
+        let score = (entry.count as f64 + 1.0).ln()
+            + 2.0 * 0.5f64.powf(age_days / RECENCY_HALF_LIFE_DAYS)
+            + 2.0 * (related_count as f64 + 1.0).ln();
+        ranked.push((class, score, entry, related_count > 0));
+    }
+
+    ranked.sort_by(|a, b| {
+        a.0.cmp(&b.0)
+            .then(b.1.total_cmp(&a.1))
+            .then_with(|| a.2.tag.to_lowercase().cmp(&b.2.tag.to_lowercase()))
+    });
+
+    ranked
+        .into_iter()
+        .take(limit)
+        .map(|(_, _, entry, related)| TagSuggestion {
+            tag: entry.tag.clone(),
+            count: entry.count,
+            last_used: entry.last_used,
+            related,
+        })
+        .collect()
+}
+
+fn match_class(key: &str, needle: &str) -> Option<u8> {
+    if key.starts_with(needle) {
+        Some(0)
+    } else if segments(key).any(|segment| segment.starts_with(needle)) {
+        Some(1)
+    } else if key.contains(needle) {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// Builds the keyword tree from per-file tag lists.
+pub fn build_tree<'a, I>(files: I) -> Vec<TagNode>
+where
+    I: IntoIterator<Item = &'a [String]>,
+{
+    #[derive(Default)]
+    struct Builder {
+        name: String,
+        path: String,
+        count: i64,
+        total: i64,
+        children: BTreeMap<String, Builder>,
+    }
+
+    fn finish(builder: Builder) -> TagNode {
+        TagNode {
+            name: builder.name,
+            path: builder.path,
+            count: builder.count,
+            total: builder.total,
+            children: builder.children.into_values().map(finish).collect(),
+        }
+    }
+
+    let mut root = Builder::default();
+    for tags in files {
+        // A file tagged `A|B` and `A|C` counts once towards `A`.
+        let mut counted = HashSet::new();
+        for tag in normalize_tags(tags) {
+            let mut node = &mut root;
+            let mut path = String::new();
+            for segment in segments(&tag) {
+                if !path.is_empty() {
+                    path.push(HIERARCHY_SEPARATOR);
+                }
+                path.push_str(segment);
+                node = node
+                    .children
+                    .entry(segment.to_lowercase())
+                    .or_insert_with(|| Builder {
+                        name: segment.to_string(),
+                        path: path.clone(),
+                        ..Default::default()
+                    });
+                if counted.insert(path.to_lowercase()) {
+                    node.total += 1;
+                }
+            }
+            node.count += 1;
+        }
+    }
+
+    finish(root).children
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_below_only_counts_descendants() {
+        assert!(is_below("A|B", "A"));
+        assert!(is_below("a|b|c", "A|B"));
+        assert!(!is_below("A", "A"));
+        assert!(!is_below("a", "A"));
+        assert!(!is_below("AB", "A"));
+        assert!(!is_below("A", "A|B"));
+    }
+
+    #[test]
+    fn tree_totals_count_each_file_once_per_branch() {
+        let files = [
+            vec!["Places|Europe|Paris".to_string(), "Places|Europe|Rome".to_string()],
+            vec!["Places".to_string(), "places|europe".to_string()],
+            vec!["People".to_string()],
+        ];
+        let tree = build_tree(files.iter().map(Vec::as_slice));
+        assert_eq!(tree.len(), 2);
+        let places = &tree[1];
+        assert_eq!((places.path.as_str(), places.count, places.total), ("Places", 1, 2));
+        let europe = &places.children[0];
+        assert_eq!((europe.count, europe.total), (1, 2));
+        let counts: Vec<_> = europe
+            .children
+            .iter()
+            .map(|node| (node.name.as_str(), node.count, node.total))
+            .collect();
+        assert_eq!(counts, vec![("Paris", 1, 1), ("Rome", 1, 1)]);
+    }
+
+    #[test]
+    fn merge_usage_folds_case_and_spacing() {
+        let entry = |tag: &str, count, last_used| TagUsage {
+            tag: tag.to_string(),
+            count,
+            last_used,
+        };
+        let merged = merge_usage([entry("b", 1, 5), entry("A | x", 2, 1), entry("a|X", 1, 9)]);
+        let merged: Vec<_> = merged
+            .iter()
+            .map(|entry| (entry.tag.as_str(), entry.count, entry.last_used))
+            .collect();
+        assert_eq!(merged, vec![("A|x", 3, 9), ("b", 1, 5)]);
+    }
+
+    #[test]
+    fn retag_moves_a_branch() {
+        let current = vec!["Clients|Acme|Spring26".to_string(), "Other".to_string()];
+        assert_eq!(
+            retag(&current, &["Clients|Acme".to_string()], Some("Archive|Acme")),
+            vec!["Archive|Acme|Spring26", "Other"]
+        );
+        assert_eq!(retag(&current, &["Clients".to_string()], None), vec!["Other"]);
+    }
+}
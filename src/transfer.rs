@@ -0,0 +1,176 @@
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+const PARTIAL_MARKER: &str = ".raw-manager-partial";
+
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// What a folder being copied contains, relative to the folder itself.
+#[derive(Debug, Clone)]
+pub enum TreeEntry {
+    Dir(PathBuf),
+    File(PathBuf),
+    Symlink(PathBuf),
+}
+
+/// Where a copy is written until it has been verified: a hidden name next to
+/// the destination, so the final rename stays on one filesystem.
+pub fn partial_path(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{name}{PARTIAL_MARKER}"))
+}
+
+/// Like [`partial_path`], with `token` added to the name so uploads of the same
+/// name that run at the same time each write their own file.
+pub fn unique_partial_path(target: &Path, token: &str) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{name}.{token}{PARTIAL_MARKER}"))
+}
+
+pub fn is_partial(name: &str) -> bool {
+    name.starts_with('.') && name.ends_with(PARTIAL_MARKER)
+}
+
+/// Whether `path` and `folder` live on the same filesystem, i.e. whether a
+/// rename between them can work.
+pub fn same_device(path: &Path, folder: &Path) -> io::Result<bool> {
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(fs::symlink_metadata(path)?.dev() == fs::metadata(folder)?.dev())
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    {
+        let _ = (path, folder);
+        Ok(true)
+    }
+}
+
+/// Size and SHA-256 of a file, read in one pass.
+pub fn file_digest(path: &Path) -> io::Result<(u64, [u8; 32])> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok((size, hasher.finalize().into()))
+}
+
+/// Copies one file and checks the copy before it appears under its final
+/// name: the data is streamed to a partial file while hashing, flushed, read
+/// back and compared by size and SHA-256, then given the source's mtime and
+/// renamed into place. A destination that already holds identical content
+/// (a copy finished before an interruption) counts as done. Returns the size.
+pub fn copy_verified(source: &Path, target: &Path) -> io::Result<u64> {
+    if fs::symlink_metadata(target).is_ok() {
+        let (size, digest) = file_digest(source)?;
+        if file_digest(target)? == (size, digest) {
+            return Ok(size);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", target.display()),
+        ));
+    }
+
+    let partial = partial_path(target);
+    let result = copy_to_partial(source, &partial).and_then(|size| {
+        fs::rename(&partial, target)?;
+        Ok(size)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+fn copy_to_partial(source: &Path, partial: &Path) -> io::Result<u64> {
+    let mut input = File::open(source)?;
+    let modified = input.metadata()?.modified()?;
+    let mut output = File::create(partial)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut size = 0;
+    loop {
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        output.write_all(&buffer[..read])?;
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    output.sync_all()?;
+    let digest: [u8; 32] = hasher.finalize().into();
+
+    if file_digest(partial)? != (size, digest) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Copy of {} does not match the original", source.display()),
+        ));
+    }
+    output.set_modified(modified)?;
+    Ok(size)
+}
+
+/// Lists a folder tree, parents before their contents.
+pub fn list_tree(root: &Path) -> io::Result<Vec<TreeEntry>> {
+    let mut entries = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(root.join(&dir))? {
+            let entry = entry?;
+            let rel = dir.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                entries.push(TreeEntry::Dir(rel.clone()));
+                pending.push(rel);
+            } else if file_type.is_symlink() {
+                entries.push(TreeEntry::Symlink(rel));
+            } else {
+                entries.push(TreeEntry::File(rel));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Recreates a symlink at `target`, pointing where `source` points.
+pub fn copy_symlink(source: &Path, target: &Path) -> io::Result<()> {
+    let link = fs::read_link(source)?;
+    if let Ok(existing) = fs::read_link(target) {
+        if existing == link {
+            return Ok(());
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    {
+        std::os::unix::fs::symlink(link, target)
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    {
+        let _ = link;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Cannot copy symlink {}", target.display()),
+        ))
+    }
+}
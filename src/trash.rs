@@ -0,0 +1,67 @@
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Folder that holds deleted items, at the library root and at the top of
+/// every other filesystem mounted inside the library, so trashing is always
+/// a rename on the same filesystem.
+pub const TRASH_DIR: &str = ".raw-manager-trash";
+
+pub const DEFAULT_RETENTION_DAYS: i64 = 30;
+
+/// Library-relative location for one trashed item, in the trash of the
+/// volume at `volume` (library-relative, empty for the library root). Every
+/// item gets its own folder so items with the same name never collide.
+pub fn item_path(volume: &Path, token: &str, index: usize, name: &OsStr) -> PathBuf {
+    volume
+        .join(TRASH_DIR)
+        .join(format!("{token}-{index}"))
+        .join(name)
+}
+
+pub fn is_trash_path(rel: &Path) -> bool {
+    rel.components()
+        .any(|component| matches!(component, Component::Normal(part) if part == TRASH_DIR))
+}
+
+/// The topmost folder between `root` and `path` that is on the same
+/// filesystem as `path`: the library root, or the mount point of a volume
+/// mounted inside the library. An item that is itself a mount point gets
+/// the root.
+pub fn volume_root(root: &Path, path: &Path) -> io::Result<PathBuf> {
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let device = std::fs::symlink_metadata(path)?.dev();
+        let mut volume = root.to_path_buf();
+        for folder in path.ancestors().skip(1) {
+            if !folder.starts_with(root) {
+                break;
+            }
+            if std::fs::metadata(folder)?.dev() != device {
+                break;
+            }
+            volume = folder.to_path_buf();
+        }
+        Ok(volume)
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    {
+        let _ = path;
+        Ok(root.to_path_buf())
+    }
+}
+
+/// Total size of a file or folder tree in bytes.
+pub fn disk_usage(path: &Path) -> io::Result<u64> {
+    let meta = std::fs::symlink_metadata(path)?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let mut total = 0;
+    for entry in std::fs::read_dir(path)? {
+        total += disk_usage(&entry?.path())?;
+    }
+    Ok(total)
+}
@@ -0,0 +1,161 @@
+use crate::formats::{self, Format};
+use crate::{internal_error, map_fs_error, rel_to_string, spawn_file_scan, transfer, ApiError, ApiResult, AppState};
+use axum::http::StatusCode;
+use std::collections::HashSet;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// Folder at the library root that holds unfinished uploads. Being inside
+/// the library makes the final move a rename on the same filesystem.
+pub const STAGING_DIR: &str = ".raw-manager-uploads";
+
+pub const DEFAULT_MAX_FILE_MB: u64 = 4 * 1024;
+pub const DEFAULT_MAX_REQUEST_MB: u64 = 32 * 1024;
+
+/// Upload size limits; `None` means unlimited.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadLimits {
+    pub max_file_bytes: Option<u64>,
+    pub max_request_bytes: Option<u64>,
+}
+
+/// Sessions with no data for this long are dropped with their partial file.
+pub const EXPIRE_AFTER_SECS: i64 = 7 * 86_400;
+
+pub fn staging_path(root: &Path, id: &str) -> PathBuf {
+    root.join(STAGING_DIR).join(id)
+}
+
+pub fn is_staging_path(rel: &Path) -> bool {
+    matches!(rel.components().next(), Some(Component::Normal(first)) if first == STAGING_DIR)
+}
+
+/// A new session id: 128 random bits, hex encoded. Whoever knows an id can
+/// add to or cancel the upload, so it must not be guessable.
+pub fn new_id() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(hex::encode(bytes))
+}
+
+/// Creates the partial file one upload of `target` is received into, under a
+/// name no other upload uses. `create_new` makes sure it never truncates the
+/// data of another transfer.
+pub async fn create_partial(target: &Path) -> io::Result<(PathBuf, tokio::fs::File)> {
+    let partial = transfer::unique_partial_path(target, &new_id()?);
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&partial)
+        .await?;
+    Ok((partial, file))
+}
+
+/// Session ids end up in file names, so only plain tokens are accepted.
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Moves a finished upload to `target`. A rename when the destination is on
+/// the library's filesystem, a verified copy when it is another disk.
+pub fn place(staged: &Path, target: &Path) -> io::Result<()> {
+    if std::fs::symlink_metadata(target).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", target.display()),
+        ));
+    }
+    let parent = target.parent().unwrap_or(target);
+    if transfer::same_device(staged, parent)? {
+        return std::fs::rename(staged, target);
+    }
+    transfer::copy_verified(staged, target)?;
+    std::fs::remove_file(staged)
+}
+
+/// What to do when the name a finished upload should get is taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Taken {
+    /// Refuse with a conflict; the client picked the name.
+    Fail,
+    /// Use the next free `name-N.ext`; cameras restart their numbering.
+    NextFree,
+}
+
+/// The error to turn a received file away with when its content does not
+/// fit the format its name claims; `None` when it fits.
+pub async fn wrong_content(format: &Format, received: &Path, name: &str, size: u64) -> ApiResult<Option<ApiError>> {
+    let path = received.to_path_buf();
+    let detected = tokio::task::spawn_blocking(move || formats::sniff_file(&path))
+        .await
+        .map_err(internal_error)?
+        .map_err(|err| map_fs_error(err, "Unable to read file"))?;
+    Ok((!format.accepts(detected, size == 0)).then(|| content_error(name, format, detected, size == 0)))
+}
+
+pub fn content_error(name: &str, format: &Format, detected: Option<formats::Container>, empty: bool) -> ApiError {
+    let message = match detected {
+        _ if empty => format!("{name} is empty"),
+        Some(container) => format!(
+            "{name} is not a {} file (content looks like {})",
+            format.name,
+            container.label()
+        ),
+        None => format!("{name} is not a {} file", format.name),
+    };
+    ApiError::new(StatusCode::BAD_REQUEST, message)
+}
+
+/// The first `stem-N.ext` that is neither in `dir` on disk nor already
+/// claimed by the current request.
+pub async fn free_name(dir: &Path, name: &str, claimed: &HashSet<PathBuf>) -> ApiResult<String> {
+    let path = Path::new(name);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    for n in 1..10_000 {
+        let candidate = format!("{stem}-{n}{ext}");
+        let full = dir.join(&candidate);
+        if !claimed.contains(&full) && tokio::fs::symlink_metadata(&full).await.is_err() {
+            return Ok(candidate);
+        }
+    }
+    Err(ApiError::new(
+        StatusCode::CONFLICT,
+        format!("No free name for {name}"),
+    ))
+}
+
+/// Moves a received and checked file from `received` into the folder
+/// `dest_rel` (canonical path `dest_canon`) as `name`, and scans it into the
+/// catalog in the background. Returns its library path.
+pub async fn store(
+    state: &AppState,
+    received: &Path,
+    dest_rel: &Path,
+    dest_canon: &Path,
+    name: &str,
+    taken: Taken,
+) -> ApiResult<String> {
+    let mut target_name = name.to_string();
+    if taken == Taken::NextFree && tokio::fs::symlink_metadata(dest_canon.join(name)).await.is_ok() {
+        target_name = free_name(dest_canon, name, &HashSet::new()).await?;
+    }
+    let target = dest_canon.join(&target_name);
+    let (from, to) = (received.to_path_buf(), target.clone());
+    tokio::task::spawn_blocking(move || place(&from, &to))
+        .await
+        .map_err(internal_error)?
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => ApiError::new(StatusCode::CONFLICT, "File already exists"),
+            _ => map_fs_error(err, "Unable to move upload into place"),
+        })?;
+    let rel = rel_to_string(&dest_rel.join(&target_name));
+    spawn_file_scan(state.clone(), rel.clone(), target);
+    Ok(rel)
+}